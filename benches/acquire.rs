@@ -0,0 +1,179 @@
+//! How long one sample takes, and how much it allocates
+//!
+//! Runs against a fake sysfs tree in a temporary directory, so the
+//! numbers don't depend on the drivers of the host. Plain std timing,
+//! run with `cargo bench`, a filter argument selects the variants
+//! with it in their name.
+//!
+//! On a single core Xeon VM (release build) it looked like this:
+//!
+//! ```text
+//! sysfs, reopening files       21.6µs/iter   25.0 allocs/iter
+//! sysfs, cached files           5.9µs/iter    0.0 allocs/iter
+//! sysfs, cached, timed         12.9µs/iter    0.1 allocs/iter
+//! acquire, cached files        44.2µs/iter   21.0 allocs/iter
+//! ```
+//!
+//! With all ten counter files kept open a read takes a quarter of the
+//! time, and allocates nothing. The read timeout (off for the acquire
+//! variant) costs about as much again, for the thread handover. What
+//! acquire allocates is the output, and the extended error counters,
+//! read by path.
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use munin_if1sec::{
+    source::{StatSource, Sysfs, Timed},
+    InterfacePlugin,
+};
+use munin_plugin::{Config, MuninPlugin};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    io::{sink, BufWriter},
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Counts the allocations, otherwise the system allocator
+struct Counting;
+
+/// Allocations so far
+static ALLOCS: AtomicU64 = AtomicU64::new(0);
+
+// SAFETY: hands everything to System, only counting
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Iterations per variant
+const ITERATIONS: u32 = 100_000;
+
+/// Create interface `interface` below `root`, with all the files
+/// sysfs would have for a plain ethernet card, and then some, so
+/// every graph of IF1SEC_FIELDS=all has something to read
+fn fake_interface(root: &Path, interface: &str) {
+    let dir = root.join(interface);
+    let stats = dir.join("statistics");
+    std::fs::create_dir_all(&stats).unwrap();
+    for counter in [
+        "rx_bytes",
+        "tx_bytes",
+        "rx_packets",
+        "tx_packets",
+        "rx_errors",
+        "tx_errors",
+        "rx_dropped",
+        "tx_dropped",
+        "multicast",
+        "collisions",
+        "rx_over_errors",
+        "rx_missed_errors",
+        "rx_frame_errors",
+        "rx_crc_errors",
+        "tx_carrier_errors",
+        "rx_nohandler",
+        "rx_compressed",
+        "tx_compressed",
+    ] {
+        std::fs::write(stats.join(counter), "1234567890\n").unwrap();
+    }
+    for (name, content) in [
+        ("operstate", "up\n"),
+        ("speed", "1000\n"),
+        ("mtu", "1500\n"),
+        ("carrier_changes", "2\n"),
+        ("tx_queue_len", "1000\n"),
+        ("ifindex", "2\n"),
+    ] {
+        std::fs::write(dir.join(name), content).unwrap();
+    }
+}
+
+/// Run `sample` ITERATIONS times, and tell how it went as `name`
+fn bench(filter: Option<&str>, name: &str, mut sample: impl FnMut(u64)) {
+    if filter.is_some_and(|filter| !name.contains(filter)) {
+        return;
+    }
+    // Warm up, the first samples open files and fill buffers
+    for i in 0..100 {
+        sample(i);
+    }
+    let allocs = ALLOCS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for i in 0..ITERATIONS {
+        sample(100 + u64::from(i));
+    }
+    let elapsed = start.elapsed();
+    let allocs = ALLOCS.load(Ordering::Relaxed) - allocs;
+    println!(
+        "{:<26} {:>6.1}µs/iter {:>6.1} allocs/iter",
+        name,
+        (elapsed / ITERATIONS).as_secs_f64() * 1e6,
+        allocs as f64 / f64::from(ITERATIONS)
+    );
+}
+
+fn main() {
+    // cargo bench hands us --bench, anything else filters
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    let filter = filter.as_deref();
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fake_interface(root, "fake0");
+    // Only what we select here, and no state file writes
+    for var in [
+        "IF1SEC_SOURCE",
+        "IF1SEC_STATE_FILE",
+        "MUNIN_PLUGSTATE",
+        "IF1SEC_SHOW_TOTAL",
+        "IF1SEC_UPDATE_RATE",
+    ] {
+        std::env::remove_var(var);
+    }
+    std::env::set_var("IF1SEC_READ_TIMEOUT_MS", "0");
+    // Every graph, as that is the most work
+    std::env::set_var("IF1SEC_FIELDS", "all");
+
+    bench(filter, "sysfs, reopening files", |_| {
+        let mut source = Sysfs::new(root, "fake0").unwrap();
+        black_box(source.read_counters().unwrap());
+    });
+
+    let mut source = Sysfs::new(root, "fake0").unwrap();
+    bench(filter, "sysfs, cached files", |_| {
+        black_box(source.read_counters().unwrap());
+    });
+
+    let mut timed = Timed::new(
+        Box::new(Sysfs::new(root, "fake0").unwrap()),
+        Duration::from_millis(500),
+    );
+    // As in the acquire daemon, reading in a thread of its own
+    timed.daemon_running();
+    bench(filter, "sysfs, cached, timed", |_| {
+        black_box(timed.read_counters().unwrap());
+    });
+
+    let mut plugin = InterfacePlugin::new("fake0", root).unwrap();
+    let config = Config::new(String::from("if1sec_fake0"));
+    let mut handle = BufWriter::new(sink());
+    bench(filter, "acquire, cached files", |epoch| {
+        plugin.acquire(&mut handle, &config, epoch).unwrap();
+    });
+}
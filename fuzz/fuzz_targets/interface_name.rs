@@ -0,0 +1,25 @@
+//! Throw arbitrary names at the parsing of our own name
+//!
+//! Our name comes from the symlink munin calls us by, anyone able to
+//! create one picks it. Whatever it is, parsing must not panic, and
+//! what it accepts as an interface must be usable as one. Run with
+//! `cargo fuzz run interface_name`.
+// SPDX-License-Identifier:  GPL-3.0-only
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use munin_if1sec::InterfacePlugin;
+use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(interface) = InterfacePlugin::interface_from_name(OsStr::from_bytes(data)) {
+        assert!(!interface.is_empty() && interface.len() < 16, "{:?}", interface);
+        assert!(interface != "." && interface != "..", "{:?}", interface);
+        assert!(
+            !interface.contains(['/', ':', '\0']) && !interface.chars().any(char::is_whitespace),
+            "{:?}",
+            interface
+        );
+    }
+});
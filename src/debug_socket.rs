@@ -0,0 +1,198 @@
+//! A Unix socket telling what the daemon sees right now
+//!
+//! For debugging on the box, without munin and without raising the
+//! log level. With IF1SEC_DEBUG_SOCKET set, the acquire daemon
+//! listens there, and dumps the state of every interface to anyone
+//! connecting, one JSON object per line: the raw counters, the rates
+//! since the sample before, the skipped samples and the last error.
+//! It never reads from the client, and hangs up after the dump.
+//!
+//! The listener has to run in the daemon, threads don't survive the
+//! fork into the background, so it gets started from acquire().
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    net::Shutdown,
+    os::unix::{fs::FileTypeExt, net::UnixListener},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::source::Counters;
+
+/// What we know about one interface
+#[derive(Debug, Default, Clone, Serialize)]
+struct Snapshot {
+    /// The interface
+    interface: String,
+    /// When we last read the counters
+    epoch: u64,
+    /// The counters as read, before unwrapping
+    counters: Option<Counters>,
+    /// Received bytes per second since the sample before
+    rx_bytes_per_sec: Option<f64>,
+    /// Transmitted bytes per second since the sample before
+    tx_bytes_per_sec: Option<f64>,
+    /// Samples skipped so far
+    skipped: u64,
+    /// The last error reading the counters
+    last_error: Option<String>,
+    /// Epoch and unwrapped rx/tx bytes of the last sample, for the
+    /// rates
+    #[serde(skip)]
+    last: Option<(u64, u64, u64)>,
+}
+
+/// The state of all our interfaces, shared with the listener
+#[derive(Debug, Clone, Default)]
+pub struct DebugStats(Arc<Mutex<BTreeMap<String, Snapshot>>>);
+
+impl DebugStats {
+    /// Change the snapshot of `interface` with `change`
+    fn update(&self, interface: &str, change: impl FnOnce(&mut Snapshot)) {
+        // A panic elsewhere doesn't make our data any worse
+        let mut all = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let snapshot = all
+            .entry(interface.to_string())
+            .or_insert_with(|| Snapshot {
+                interface: interface.to_string(),
+                ..Default::default()
+            });
+        change(snapshot);
+    }
+
+    /// Note a sample of `interface` at `epoch`, as read (`raw`) and
+    /// unwrapped (`value`)
+    pub fn sample(&self, interface: &str, epoch: u64, raw: &Counters, value: &Counters) {
+        self.update(interface, |snapshot| {
+            if let Some((last, rx, tx)) = snapshot.last {
+                if epoch > last {
+                    let secs = (epoch - last) as f64;
+                    snapshot.rx_bytes_per_sec =
+                        Some(value.rx_bytes.saturating_sub(rx) as f64 / secs);
+                    snapshot.tx_bytes_per_sec =
+                        Some(value.tx_bytes.saturating_sub(tx) as f64 / secs);
+                }
+            }
+            snapshot.last = Some((epoch, value.rx_bytes, value.tx_bytes));
+            snapshot.epoch = epoch;
+            snapshot.counters = Some(*raw);
+        });
+    }
+
+    /// Note the skipped samples of `interface`, and the last error
+    /// reading it, if that is why
+    pub fn skipped(&self, interface: &str, skipped: u64, error: Option<&str>) {
+        self.update(interface, |snapshot| {
+            snapshot.skipped = skipped;
+            if let Some(error) = error {
+                snapshot.last_error = Some(error.to_string());
+            }
+        });
+    }
+
+    /// Write every snapshot to `out`, one JSON object per line
+    fn dump(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let all = self.0.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        for snapshot in all.values() {
+            serde_json::to_writer(&mut *out, snapshot)?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// Start listening on `path`, handing out the dump of `stats` to
+/// everyone connecting. A stale socket from an earlier run gets
+/// replaced, anything else there is a failure.
+fn listen(path: &Path, stats: DebugStats) -> std::io::Result<()> {
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(mut stream) => {
+                    if let Err(e) = stats.dump(&mut stream) {
+                        debug!("Debug socket client went away: {}", e);
+                    }
+                    let _ = stream.shutdown(Shutdown::Both);
+                }
+                Err(e) => debug!("Debug socket accept failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Start the debug socket at IF1SEC_DEBUG_SOCKET, if set, returns
+/// the stats to keep updated.
+pub fn start_from_env() -> Option<DebugStats> {
+    let path = std::env::var_os("IF1SEC_DEBUG_SOCKET")?;
+    let path = Path::new(&path);
+    let stats = DebugStats::default();
+    match listen(path, stats.clone()) {
+        Ok(()) => {
+            info!("Debug socket listening on {}", path.display());
+            Some(stats)
+        }
+        Err(e) => {
+            warn!("Can not listen on debug socket {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Read, os::unix::net::UnixStream};
+
+    #[test]
+    fn test_debug_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("debug");
+        let stats = DebugStats::default();
+        let counters = |rx_bytes, tx_bytes| Counters {
+            rx_bytes,
+            tx_bytes,
+            ..Default::default()
+        };
+        stats.sample("eth0", 12, &counters(3000, 600), &counters(3000, 600));
+        stats.sample("eth0", 14, &counters(5000, 600), &counters(5000, 600));
+        stats.skipped("eth0", 1, Some("Reading counters took longer than 500ms"));
+        stats.skipped("eth1", 3, None);
+        listen(&path, DebugStats::default()).unwrap();
+        // Again, replacing the socket of the earlier run
+        listen(&path, stats).unwrap();
+
+        let mut dump = String::new();
+        UnixStream::connect(&path)
+            .unwrap()
+            .read_to_string(&mut dump)
+            .unwrap();
+        let lines: Vec<serde_json::Value> = dump
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["interface"], "eth0");
+        assert_eq!(lines[0]["epoch"], 14);
+        assert_eq!(lines[0]["counters"]["rx_bytes"], 5000);
+        assert_eq!(lines[0]["rx_bytes_per_sec"], 1000.0);
+        assert_eq!(lines[0]["tx_bytes_per_sec"], 0.0);
+        assert_eq!(lines[0]["skipped"], 1);
+        assert_eq!(
+            lines[0]["last_error"],
+            "Reading counters took longer than 500ms"
+        );
+        assert_eq!(lines[1]["interface"], "eth1");
+        assert_eq!(lines[1]["counters"], serde_json::Value::Null);
+        assert_eq!(lines[1]["skipped"], 3);
+    }
+}
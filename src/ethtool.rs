@@ -0,0 +1,438 @@
+//! Ask the driver directly, using the ethtool ioctl
+//!
+//! Not everything a driver knows ends up in sysfs (or ends up there
+//! usable), the SIOCETHTOOL ioctl gets us to it. Every query here
+//! returns None if the ioctl isn't permitted or the driver does not
+//! support it, callers are expected to have a fallback.
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use log::{debug, warn};
+use std::{
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+use crate::options;
+
+/// The ioctl request for ethtool
+const SIOCETHTOOL: libc::c_ulong = 0x8946;
+
+/// Get settings, including the link speed
+const ETHTOOL_GSET: u32 = 0x00000001;
+
+/// The link speed if the driver doesn't know it
+const SPEED_UNKNOWN: u32 = 0xffffffff;
+
+/// Get the ring buffer sizes
+const ETHTOOL_GRINGPARAM: u32 = 0x00000010;
+
+/// Get the names of a string set, like the driver statistics
+const ETHTOOL_GSTRINGS: u32 = 0x0000001b;
+
+/// Get the driver statistics
+const ETHTOOL_GSTATS: u32 = 0x0000001d;
+
+/// Get the sizes of string sets
+const ETHTOOL_GSSET_INFO: u32 = 0x00000037;
+
+/// The string set of the driver statistics
+const ETH_SS_STATS: u32 = 1;
+
+/// Length of one name in a string set, NUL padded
+const ETH_GSTRING_LEN: usize = 32;
+
+/// struct ethtool_cmd from linux/ethtool.h
+#[repr(C)]
+#[derive(Debug, Default)]
+struct EthtoolCmd {
+    cmd: u32,
+    supported: u32,
+    advertising: u32,
+    speed: u16,
+    duplex: u8,
+    port: u8,
+    phy_address: u8,
+    transceiver: u8,
+    autoneg: u8,
+    mdio_support: u8,
+    maxtxpkt: u32,
+    maxrxpkt: u32,
+    speed_hi: u16,
+    eth_tp_mdix: u8,
+    eth_tp_mdix_ctrl: u8,
+    lp_advertising: u32,
+    reserved: [u32; 2],
+}
+
+/// struct ethtool_ringparam from linux/ethtool.h
+#[repr(C)]
+#[derive(Debug, Default)]
+struct EthtoolRingparam {
+    cmd: u32,
+    rx_max_pending: u32,
+    rx_mini_max_pending: u32,
+    rx_jumbo_max_pending: u32,
+    tx_max_pending: u32,
+    rx_pending: u32,
+    rx_mini_pending: u32,
+    rx_jumbo_pending: u32,
+    tx_pending: u32,
+}
+
+/// struct ifreq from linux/if.h, with only the ifr_data member of
+/// the union, padded to the full size.
+#[repr(C)]
+struct IfReq {
+    ifr_name: [libc::c_char; libc::IFNAMSIZ],
+    ifr_data: *mut libc::c_void,
+    _pad: [u8; 16],
+}
+
+/// Open a socket to run interface ioctls on
+pub fn ioctl_socket() -> io::Result<OwnedFd> {
+    // SAFETY: plain socket call, the fd gets owned (and closed) below
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: fd is a freshly opened socket nobody else owns
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Run the ethtool command in `data` against `iface`. `data` has to
+/// be one of the ethtool structs, with its cmd field set.
+fn ethtool_ioctl<T: ?Sized>(iface: &str, data: &mut T) -> io::Result<()> {
+    ethtool_ioctl_on(&ioctl_socket()?, iface, data)
+}
+
+/// [ethtool_ioctl] on the ioctl socket `sock`, for those asking
+/// again and again
+fn ethtool_ioctl_on<T: ?Sized>(sock: &OwnedFd, iface: &str, data: &mut T) -> io::Result<()> {
+    if iface.len() >= libc::IFNAMSIZ {
+        return Err(io::Error::from(io::ErrorKind::InvalidInput));
+    }
+    let mut ifr = IfReq {
+        ifr_name: [0; libc::IFNAMSIZ],
+        ifr_data: data as *mut T as *mut libc::c_void,
+        _pad: [0; 16],
+    };
+    for (dst, src) in ifr.ifr_name.iter_mut().zip(iface.bytes()) {
+        *dst = src as libc::c_char;
+    }
+
+    // SAFETY: ifr is a properly sized and NUL terminated ifreq, and
+    // ifr_data points to the ethtool struct the kernel expects for
+    // the command in it, both outliving the call.
+    let ret = unsafe { libc::ioctl(sock.as_raw_fd(), SIOCETHTOOL as _, &mut ifr) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Query the link speed of `iface` in Mbps from the driver. None if
+/// the driver can't tell (or we aren't allowed to ask).
+pub fn detect_speed(iface: &str) -> Option<u32> {
+    let mut cmd = EthtoolCmd {
+        cmd: ETHTOOL_GSET,
+        ..Default::default()
+    };
+    if let Err(e) = ethtool_ioctl(iface, &mut cmd) {
+        debug!("ETHTOOL_GSET failed for {}: {}", iface, e);
+        return None;
+    }
+    let speed = (u32::from(cmd.speed_hi) << 16) | u32::from(cmd.speed);
+    debug!("ETHTOOL_GSET speed for {}: {}", iface, speed);
+    match speed {
+        0 | 0xffff | SPEED_UNKNOWN => None,
+        speed => Some(speed),
+    }
+}
+
+/// The ring buffer sizes of an interface, in descriptors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rings {
+    /// Receive ring, as configured
+    pub rx: u32,
+    /// The largest receive ring the hardware can do
+    pub rx_max: u32,
+    /// Transmit ring, as configured
+    pub tx: u32,
+    /// The largest transmit ring the hardware can do
+    pub tx_max: u32,
+}
+
+/// Query the ring buffer sizes of `iface`, what `ethtool -g` shows.
+/// None if the driver doesn't have (or tell) them.
+pub fn ring_sizes(iface: &str) -> Option<Rings> {
+    let mut ring = EthtoolRingparam {
+        cmd: ETHTOOL_GRINGPARAM,
+        ..Default::default()
+    };
+    if let Err(e) = ethtool_ioctl(iface, &mut ring) {
+        debug!("ETHTOOL_GRINGPARAM failed for {}: {}", iface, e);
+        return None;
+    }
+    debug!("ETHTOOL_GRINGPARAM for {}: {:?}", iface, ring);
+    if ring.rx_pending == 0 && ring.tx_pending == 0 {
+        return None;
+    }
+    Some(Rings {
+        rx: ring.rx_pending,
+        rx_max: ring.rx_max_pending,
+        tx: ring.tx_pending,
+        tx_max: ring.tx_max_pending,
+    })
+}
+
+/// The driver statistics counting packets dropped as the receive
+/// ring was full, as far as we know them: igb and e1000e, ixgbe, mlx5
+const RING_FULL_STATS: [&str; 3] = [
+    "rx_no_buffer_count",
+    "rx_no_dma_resources",
+    "rx_out_of_buffer",
+];
+
+/// The driver statistic counting drops for a full receive ring, among
+/// the `names` the driver has, with its index in them
+pub fn ring_full_stat(names: &[String]) -> Option<(&'static str, usize)> {
+    RING_FULL_STATS
+        .into_iter()
+        .find_map(|name| Some((name, names.iter().position(|has| has == name)?)))
+}
+
+/// The number of driver statistics of `iface`, what `ethtool -S`
+/// shows
+fn stats_count(sock: &OwnedFd, iface: &str) -> io::Result<usize> {
+    // struct ethtool_sset_info: cmd, reserved, sset_mask, then one
+    // u32 per set in the mask
+    let mut info = [0u8; 20];
+    info[..4].copy_from_slice(&ETHTOOL_GSSET_INFO.to_ne_bytes());
+    info[8..16].copy_from_slice(&(1u64 << ETH_SS_STATS).to_ne_bytes());
+    ethtool_ioctl_on(sock, iface, &mut info[..])?;
+    let mask = u64::from_ne_bytes(info[8..16].try_into().expect("8 bytes"));
+    if mask & (1 << ETH_SS_STATS) == 0 {
+        return Err(io::Error::from(io::ErrorKind::Unsupported));
+    }
+    Ok(u32::from_ne_bytes(info[16..20].try_into().expect("4 bytes")) as usize)
+}
+
+/// The names of the driver statistics of `iface`, in the order
+/// [Stats::read] has them. None if the driver has none.
+fn stat_names(sock: &OwnedFd, iface: &str) -> Option<Vec<String>> {
+    let count = match stats_count(sock, iface) {
+        Ok(count) if count > 0 => count,
+        Ok(_) => return None,
+        Err(e) => {
+            debug!("No driver statistics for {}: {}", iface, e);
+            return None;
+        }
+    };
+    // struct ethtool_gstrings: cmd, string_set, len, the names
+    let mut strings = vec![0u8; 12 + count * ETH_GSTRING_LEN];
+    strings[..4].copy_from_slice(&ETHTOOL_GSTRINGS.to_ne_bytes());
+    strings[4..8].copy_from_slice(&ETH_SS_STATS.to_ne_bytes());
+    strings[8..12].copy_from_slice(&(count as u32).to_ne_bytes());
+    if let Err(e) = ethtool_ioctl_on(sock, iface, &mut strings[..]) {
+        debug!("ETHTOOL_GSTRINGS failed for {}: {}", iface, e);
+        return None;
+    }
+    Some(
+        strings[12..]
+            .chunks(ETH_GSTRING_LEN)
+            .map(|name| {
+                let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                String::from_utf8_lossy(&name[..end]).to_string()
+            })
+            .collect(),
+    )
+}
+
+/// Reads the driver statistics of one interface, sample after
+/// sample. Their names are a whole string table (tens of KB for some
+/// drivers), those come once from [Stats::open]. Every read is then
+/// only the count check and ETHTOOL_GSTATS, on a socket kept open.
+#[derive(Debug)]
+pub struct Stats {
+    /// The interface
+    iface: String,
+    /// The ioctl socket
+    sock: OwnedFd,
+    /// How many statistics the driver has, as named
+    count: usize,
+    /// struct ethtool_stats for ETHTOOL_GSTATS: cmd, n_stats, the
+    /// values
+    request: Vec<u8>,
+    /// The values of the last read
+    values: Vec<u64>,
+}
+
+impl Stats {
+    /// The names of the driver statistics of `iface`, what `ethtool
+    /// -S` shows, and what reads their values. None if the driver has
+    /// none, or doesn't let us see them.
+    pub fn open(iface: &str) -> Option<(Stats, Vec<String>)> {
+        let sock = match ioctl_socket() {
+            Ok(sock) => sock,
+            Err(e) => {
+                debug!("No ioctl socket for {}: {}", iface, e);
+                return None;
+            }
+        };
+        let names = stat_names(&sock, iface)?;
+        let count = names.len();
+        let stats = Stats {
+            iface: iface.to_string(),
+            sock,
+            count,
+            request: vec![0; 8 + count * 8],
+            values: Vec::with_capacity(count),
+        };
+        Some((stats, names))
+    }
+
+    /// The values of the driver statistics, in the order of the names
+    /// from [Stats::open]. None if the driver doesn't tell, or has a
+    /// different number of them by now.
+    pub fn read(&mut self) -> Option<&[u64]> {
+        // The kernel writes as many as the driver has, no matter how
+        // much room we leave, so make sure that is what we expect
+        match stats_count(&self.sock, &self.iface) {
+            Ok(now) if now == self.count => {}
+            Ok(now) => {
+                debug!(
+                    "Driver statistics of {} changed from {} to {}",
+                    self.iface, self.count, now
+                );
+                return None;
+            }
+            Err(e) => {
+                debug!("No driver statistics for {}: {}", self.iface, e);
+                return None;
+            }
+        }
+        self.request[..4].copy_from_slice(&ETHTOOL_GSTATS.to_ne_bytes());
+        self.request[4..8].copy_from_slice(&(self.count as u32).to_ne_bytes());
+        if let Err(e) = ethtool_ioctl_on(&self.sock, &self.iface, &mut self.request[..]) {
+            debug!("ETHTOOL_GSTATS failed for {}: {}", self.iface, e);
+            return None;
+        }
+        self.values.clear();
+        self.values.extend(
+            self.request[8..]
+                .chunks(8)
+                .map(|value| u64::from_ne_bytes(value.try_into().expect("8 bytes"))),
+        );
+        Some(&self.values)
+    }
+}
+
+/// One of the driver statistics we graph
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DriverStat {
+    /// Its name, as `ethtool -S` shows it
+    pub name: String,
+    /// The munin field for it, the name with everything munin doesn't
+    /// allow in a field name replaced by _
+    pub field: String,
+    /// A gauge (a current value, like a queue length), not a counter
+    pub gauge: bool,
+    /// Where it is in the values of [Stats::read]
+    pub index: usize,
+}
+
+/// Find the `wanted` statistics, a comma separated list as in
+/// IF1SEC_ETHTOOL_STATS, among the `names` the driver has. A name with a
+/// `:gauge` suffix is graphed as is, not as a rate. Those the driver
+/// doesn't have are left out, with a warning if asked for explicitly
+/// (`wanted` not None), the default set (the pause frames) may well
+/// not be there.
+pub fn pick(wanted: Option<&str>, names: &[String]) -> Vec<DriverStat> {
+    let mut picked: Vec<DriverStat> = Vec::new();
+    let default = options::default("IF1SEC_ETHTOOL_STATS");
+    for entry in wanted.unwrap_or(default).split(',') {
+        let (name, gauge) = match entry.trim().rsplit_once(':') {
+            Some((name, "gauge")) => (name, true),
+            Some((name, "derive")) => (name, false),
+            Some((_, kind)) => {
+                warn!("Unknown type {} for driver statistic {}", kind, entry);
+                continue;
+            }
+            None => (entry.trim(), false),
+        };
+        if name.is_empty() || picked.iter().any(|stat| stat.name == name) {
+            continue;
+        }
+        let Some(index) = names.iter().position(|has| has == name) else {
+            if wanted.is_some() {
+                warn!("Driver has no statistic {}, see ethtool -S", name);
+            } else {
+                debug!("Driver has no statistic {}", name);
+            }
+            continue;
+        };
+        picked.push(DriverStat {
+            name: name.to_string(),
+            field: name
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect(),
+            gauge,
+            index,
+        });
+    }
+    picked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick() {
+        let has: Vec<String> = ["rx_packets", "tx_pause", "rx_pause", "rx-0.drops"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let stat = |name: &str, field: &str, gauge, index| DriverStat {
+            name: String::from(name),
+            field: String::from(field),
+            gauge,
+            index,
+        };
+        assert_eq!(
+            pick(None, &has),
+            vec![
+                stat("rx_pause", "rx_pause", false, 2),
+                stat("tx_pause", "tx_pause", false, 1)
+            ]
+        );
+        assert_eq!(
+            pick(
+                Some(
+                    "tx_pause:derive, rx_nothing,rx-0.drops,rx_packets:gauge,tx_pause,rx_pause:max"
+                ),
+                &has
+            ),
+            vec![
+                stat("tx_pause", "tx_pause", false, 1),
+                stat("rx-0.drops", "rx_0_drops", false, 3),
+                stat("rx_packets", "rx_packets", true, 0),
+            ]
+        );
+        assert!(pick(Some(""), &has).is_empty());
+        assert!(pick(None, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_ring_full_stat() {
+        let mut has: Vec<String> = ["rx_packets", "rx_out_of_buffer"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(ring_full_stat(&has), Some(("rx_out_of_buffer", 1)));
+        has.push(String::from("rx_no_buffer_count"));
+        assert_eq!(ring_full_stat(&has), Some(("rx_no_buffer_count", 2)));
+        assert_eq!(ring_full_stat(&[]), None);
+    }
+}
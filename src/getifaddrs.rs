@@ -0,0 +1,314 @@
+//! Read the counters with getifaddrs(3)
+//!
+//! The portable way to the interface counters, and the only one on
+//! FreeBSD, which has neither sysfs nor /proc/net/dev: there the
+//! AF_LINK entry of an interface carries its struct if_data. On Linux
+//! the AF_PACKET entry carries a struct rtnl_link_stats, with 32bit
+//! counters only, sysfs or netlink are the better choice there.
+//!
+//! The counters we get:
+//!
+//! - Linux: bytes, packets, errors, drops, multicast and collisions,
+//!   all 32bit (wraps get handled, see [crate::wrap])
+//! - FreeBSD: bytes, packets, errors, drops, multicast (received) and
+//!   collisions, 64bit
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use anyhow::{anyhow, Result};
+use log::debug;
+use std::{
+    ffi::CStr,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ptr,
+};
+
+use crate::source::{Counters, StatSource};
+
+/// The address family of the entry with the link counters
+#[cfg(target_os = "linux")]
+const LINK_FAMILY: libc::c_int = libc::AF_PACKET;
+#[cfg(target_os = "freebsd")]
+const LINK_FAMILY: libc::c_int = libc::AF_LINK;
+
+/// The start of struct rtnl_link_stats from linux/if_link.h, as much
+/// as we use of it
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Debug, Default)]
+struct LinkStats {
+    rx_packets: u32,
+    tx_packets: u32,
+    rx_bytes: u32,
+    tx_bytes: u32,
+    rx_errors: u32,
+    tx_errors: u32,
+    rx_dropped: u32,
+    tx_dropped: u32,
+    multicast: u32,
+    collisions: u32,
+}
+
+/// The counters in the ifa_data of a link entry
+///
+/// # Safety
+///
+/// `data` has to be the ifa_data of an entry of [LINK_FAMILY]
+#[cfg(target_os = "linux")]
+unsafe fn link_counters(data: *const libc::c_void) -> Counters {
+    let stats = &*(data as *const LinkStats);
+    Counters {
+        rx_bytes: stats.rx_bytes.into(),
+        tx_bytes: stats.tx_bytes.into(),
+        rx_packets: stats.rx_packets.into(),
+        tx_packets: stats.tx_packets.into(),
+        rx_errors: Some(stats.rx_errors.into()),
+        tx_errors: Some(stats.tx_errors.into()),
+        rx_dropped: Some(stats.rx_dropped.into()),
+        tx_dropped: Some(stats.tx_dropped.into()),
+        multicast: Some(stats.multicast.into()),
+        collisions: Some(stats.collisions.into()),
+    }
+}
+
+/// The counters in the ifa_data of a link entry
+///
+/// # Safety
+///
+/// `data` has to be the ifa_data of an entry of [LINK_FAMILY]
+#[cfg(target_os = "freebsd")]
+unsafe fn link_counters(data: *const libc::c_void) -> Counters {
+    let data = &*(data as *const libc::if_data);
+    Counters {
+        rx_bytes: data.ifi_ibytes as u64,
+        tx_bytes: data.ifi_obytes as u64,
+        rx_packets: data.ifi_ipackets as u64,
+        tx_packets: data.ifi_opackets as u64,
+        rx_errors: Some(data.ifi_ierrors as u64),
+        tx_errors: Some(data.ifi_oerrors as u64),
+        rx_dropped: Some(data.ifi_iqdrops as u64),
+        tx_dropped: Some(data.ifi_oqdrops as u64),
+        multicast: Some(data.ifi_imcasts as u64),
+        collisions: Some(data.ifi_collisions as u64),
+    }
+}
+
+/// Find the counters of `interface` in the list of entries starting
+/// at `ifa`
+///
+/// # Safety
+///
+/// `ifa` has to be a list as getifaddrs returns it, or null
+unsafe fn find(mut ifa: *const libc::ifaddrs, interface: &str) -> Option<Counters> {
+    while let Some(entry) = ifa.as_ref() {
+        ifa = entry.ifa_next;
+        if entry.ifa_name.is_null() || entry.ifa_data.is_null() {
+            continue;
+        }
+        match entry.ifa_addr.as_ref() {
+            Some(addr) if libc::c_int::from(addr.sa_family) == LINK_FAMILY => {}
+            _ => continue,
+        }
+        if CStr::from_ptr(entry.ifa_name).to_bytes() == interface.as_bytes() {
+            return Some(link_counters(entry.ifa_data));
+        }
+    }
+    None
+}
+
+/// The IPv4 and IPv6 addresses of `interface` in the list of entries
+/// starting at `ifa`, in the order getifaddrs has them
+///
+/// # Safety
+///
+/// `ifa` has to be a list as getifaddrs returns it, or null
+unsafe fn find_addresses(mut ifa: *const libc::ifaddrs, interface: &str) -> Vec<IpAddr> {
+    let mut addresses = Vec::new();
+    while let Some(entry) = ifa.as_ref() {
+        ifa = entry.ifa_next;
+        if entry.ifa_name.is_null()
+            || CStr::from_ptr(entry.ifa_name).to_bytes() != interface.as_bytes()
+        {
+            continue;
+        }
+        let Some(addr) = entry.ifa_addr.as_ref() else {
+            continue;
+        };
+        match libc::c_int::from(addr.sa_family) {
+            libc::AF_INET => {
+                let addr = &*(entry.ifa_addr as *const libc::sockaddr_in);
+                addresses.push(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                    addr.sin_addr.s_addr,
+                ))));
+            }
+            libc::AF_INET6 => {
+                let addr = &*(entry.ifa_addr as *const libc::sockaddr_in6);
+                addresses.push(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)));
+            }
+            _ => {}
+        }
+    }
+    addresses
+}
+
+/// The IPv4 and IPv6 addresses `interface` has right now, none if
+/// getifaddrs fails
+pub fn addresses(interface: &str) -> Vec<IpAddr> {
+    let mut addrs = ptr::null_mut();
+    // SAFETY: on success addrs is ours, until freeifaddrs
+    if unsafe { libc::getifaddrs(&mut addrs) } < 0 {
+        debug!("getifaddrs failed: {}", io::Error::last_os_error());
+        return Vec::new();
+    }
+    // SAFETY: as getifaddrs returned it, and freed only after
+    let addresses = unsafe { find_addresses(addrs, interface) };
+    // SAFETY: from getifaddrs, nothing points into it any more
+    unsafe { libc::freeifaddrs(addrs) };
+    addresses
+}
+
+/// Read the counters of an interface with getifaddrs
+#[derive(Debug)]
+pub struct GetIfAddrs {
+    /// Which interface
+    interface: String,
+}
+
+impl GetIfAddrs {
+    /// Setup reading the counters of `interface`, which getifaddrs
+    /// has to know.
+    pub fn new(interface: &str) -> Result<Self> {
+        let mut source = Self {
+            interface: interface.to_string(),
+        };
+        source.read_counters()?;
+        Ok(source)
+    }
+}
+
+impl StatSource for GetIfAddrs {
+    fn read_counters(&mut self) -> Result<Counters> {
+        let mut addrs = ptr::null_mut();
+        // SAFETY: on success addrs is ours, until freeifaddrs
+        if unsafe { libc::getifaddrs(&mut addrs) } < 0 {
+            return Err(anyhow!("getifaddrs failed: {}", io::Error::last_os_error()));
+        }
+        // SAFETY: as getifaddrs returned it, and freed only after
+        let counters = unsafe { find(addrs, &self.interface) };
+        // SAFETY: from getifaddrs, nothing points into it any more
+        unsafe { libc::freeifaddrs(addrs) };
+        counters.ok_or_else(|| anyhow!("Can not find interface {} with getifaddrs", self.interface))
+    }
+
+    /// Nothing to open, works for everyone
+    fn keeps_open(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::{ffi::CString, mem};
+
+    #[test]
+    fn test_find() {
+        let name = CString::new("eth0").unwrap();
+        let other = CString::new("eth1").unwrap();
+        let stats = LinkStats {
+            rx_packets: 1,
+            tx_packets: 2,
+            rx_bytes: 3,
+            tx_bytes: 4,
+            rx_errors: 5,
+            tx_errors: 6,
+            rx_dropped: 7,
+            tx_dropped: 8,
+            multicast: 9,
+            collisions: 10,
+        };
+        // SAFETY: plain C structs, all zero is valid for them
+        let (mut packet, mut inet): (libc::sockaddr, libc::sockaddr) =
+            unsafe { (mem::zeroed(), mem::zeroed()) };
+        packet.sa_family = libc::AF_PACKET as libc::sa_family_t;
+        inet.sa_family = libc::AF_INET as libc::sa_family_t;
+        let inet_data = LinkStats::default();
+        let entry = |name: &CString, addr: &mut libc::sockaddr, data: &LinkStats, next| {
+            // SAFETY: as above
+            let mut entry: libc::ifaddrs = unsafe { mem::zeroed() };
+            entry.ifa_name = name.as_ptr() as *mut _;
+            entry.ifa_addr = addr;
+            entry.ifa_data = data as *const LinkStats as *mut _;
+            entry.ifa_next = next;
+            entry
+        };
+        // eth1 link, eth0 inet (with other data), eth0 link
+        let mut last = entry(&name, &mut packet, &stats, ptr::null_mut());
+        let mut middle = entry(&name, &mut inet, &inet_data, &mut last);
+        let first = entry(&other, &mut packet, &inet_data, &mut middle);
+
+        // SAFETY: a proper list, its parts live to the end
+        let found = unsafe { find(&first, "eth0") }.unwrap();
+        assert_eq!(
+            found,
+            Counters {
+                rx_bytes: 3,
+                tx_bytes: 4,
+                rx_packets: 1,
+                tx_packets: 2,
+                rx_errors: Some(5),
+                tx_errors: Some(6),
+                rx_dropped: Some(7),
+                tx_dropped: Some(8),
+                multicast: Some(9),
+                collisions: Some(10),
+            }
+        );
+        // SAFETY: as above
+        assert_eq!(unsafe { find(&first, "eth2") }, None);
+        assert!(GetIfAddrs::new("if1sec-none").is_err());
+    }
+
+    #[test]
+    fn test_find_addresses() {
+        let name = CString::new("eth0").unwrap();
+        let other = CString::new("eth1").unwrap();
+        // SAFETY: plain C structs, all zero is valid for them
+        let (mut inet, mut inet6, mut packet): (
+            libc::sockaddr_in,
+            libc::sockaddr_in6,
+            libc::sockaddr,
+        ) = unsafe { (mem::zeroed(), mem::zeroed(), mem::zeroed()) };
+        inet.sin_family = libc::AF_INET as libc::sa_family_t;
+        inet.sin_addr.s_addr = u32::from(Ipv4Addr::new(192, 0, 2, 7)).to_be();
+        inet6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+        inet6.sin6_addr.s6_addr = "2001:db8::7".parse::<Ipv6Addr>().unwrap().octets();
+        packet.sa_family = libc::AF_PACKET as libc::sa_family_t;
+        let entry = |name: &CString, addr: *mut libc::sockaddr, next| {
+            // SAFETY: as above
+            let mut entry: libc::ifaddrs = unsafe { mem::zeroed() };
+            entry.ifa_name = name.as_ptr() as *mut _;
+            entry.ifa_addr = addr;
+            entry.ifa_next = next;
+            entry
+        };
+        // eth0 link, inet6 and inet, eth1 inet, eth0 without address
+        let mut fifth = entry(&name, ptr::null_mut(), ptr::null_mut());
+        let mut fourth = entry(&other, &mut inet as *mut _ as *mut _, &mut fifth);
+        let mut third = entry(&name, &mut inet as *mut _ as *mut _, &mut fourth);
+        let mut second = entry(&name, &mut inet6 as *mut _ as *mut _, &mut third);
+        let first = entry(&name, &mut packet, &mut second);
+
+        // SAFETY: a proper list, its parts live to the end
+        assert_eq!(
+            unsafe { find_addresses(&first, "eth0") },
+            vec![
+                "2001:db8::7".parse::<IpAddr>().unwrap(),
+                "192.0.2.7".parse::<IpAddr>().unwrap()
+            ]
+        );
+        // SAFETY: as above
+        assert!(unsafe { find_addresses(&first, "eth2") }.is_empty());
+        assert!(addresses("if1sec-none").is_empty());
+    }
+}
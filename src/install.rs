@@ -0,0 +1,136 @@
+//! Create and remove our symlinks in the munin plugins directory
+//!
+//! Instead of hand crafting one if1sec_<iface> symlink per interface,
+//! `install` creates them for every interface `suggest` lists, and
+//! `uninstall` removes all of them again. Both tell what they did,
+//! and carry on past interfaces they can't handle, failing at the end.
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use anyhow::{anyhow, Result};
+use std::{
+    io::Write,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use crate::options;
+
+/// Where munin looks for the plugins to run, from IF1SEC_PLUGINS_DIR,
+/// /etc/munin/plugins by default.
+pub fn plugins_dir() -> PathBuf {
+    std::env::var_os("IF1SEC_PLUGINS_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(options::default("IF1SEC_PLUGINS_DIR")))
+}
+
+/// Link if1sec_<interface> in `dir` to `plugin`, for every one of
+/// `interfaces`. Links already there are fine, anything else in the
+/// way is left alone. Tells `out` what happened.
+pub fn install(
+    out: &mut impl Write,
+    dir: &Path,
+    plugin: &Path,
+    interfaces: &[String],
+) -> Result<()> {
+    let mut failed = 0;
+    for interface in interfaces {
+        let name = format!("if1sec_{}", interface);
+        let link = dir.join(&name);
+        if std::fs::read_link(&link).is_ok_and(|target| target == plugin) {
+            writeln!(out, "{} is already there", name)?;
+        } else if link.symlink_metadata().is_ok() {
+            writeln!(out, "Skipping {}, something else is in the way", name)?;
+            failed += 1;
+        } else {
+            match symlink(plugin, &link) {
+                Ok(()) => writeln!(out, "Created {}", name)?,
+                Err(e) => {
+                    writeln!(out, "Can not create {}: {}", link.display(), e)?;
+                    failed += 1;
+                }
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} of {} links not created",
+            failed,
+            interfaces.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Remove every if1sec_* symlink to `plugin` from `dir`, whatever
+/// interface it is for. Tells `out` what happened.
+pub fn uninstall(out: &mut impl Write, dir: &Path, plugin: &Path) -> Result<()> {
+    let mut failed = 0;
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| anyhow!("Can not read {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("if1sec_"))
+        .map(|entry| entry.path())
+        .collect();
+    entries.sort();
+    for link in entries {
+        if std::fs::read_link(&link).ok().as_deref() != Some(plugin) {
+            continue;
+        }
+        let name = link.file_name().unwrap_or_default().to_string_lossy();
+        match std::fs::remove_file(&link) {
+            Ok(()) => writeln!(out, "Removed {}", name)?,
+            Err(e) => {
+                writeln!(out, "Can not remove {}: {}", link.display(), e)?;
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow!("{} links not removed", failed));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_install_uninstall() {
+        let dir = tempfile::tempdir().unwrap();
+        let plugins = dir.path().join("plugins");
+        std::fs::create_dir(&plugins).unwrap();
+        let plugin = dir.path().join("if1sec_");
+        std::fs::write(&plugin, "").unwrap();
+        // Someone elses
+        std::fs::write(plugins.join("if1sec_eth1"), "").unwrap();
+        symlink("/bin/true", plugins.join("if1sec_other")).unwrap();
+
+        let interfaces: Vec<String> = ["eth0", "eth1"].map(String::from).to_vec();
+        let mut out = Vec::new();
+        assert!(install(&mut out, &plugins, &plugin, &interfaces).is_err());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Created if1sec_eth0\nSkipping if1sec_eth1, something else is in the way\n"
+        );
+        assert_eq!(
+            std::fs::read_link(plugins.join("if1sec_eth0")).unwrap(),
+            plugin
+        );
+
+        // Again, nothing new
+        let mut out = Vec::new();
+        install(&mut out, &plugins, &plugin, &interfaces[..1]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "if1sec_eth0 is already there\n"
+        );
+
+        let mut out = Vec::new();
+        uninstall(&mut out, &plugins, &plugin).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Removed if1sec_eth0\n");
+        // Only ours went away
+        assert!(plugins.join("if1sec_eth1").exists());
+        assert!(plugins.join("if1sec_other").symlink_metadata().is_ok());
+    }
+}
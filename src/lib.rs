@@ -0,0 +1,4619 @@
+//! munin-if1sec - Collect network interface data for munin every second
+//!
+//! The plugin itself, [InterfacePlugin] for one interface,
+//! [multi::MultiPlugin] for many, the binary is a small wrapper
+//! around it.
+//!
+//! Use as munin plugin, it expects to be symlinked per interface. So
+//! a symlink if1sec_eth0 to this plugin will collect data for the eth0
+//! interface.
+//!
+//! Symlinked as if1sec_all, or with IF1SEC_INTERFACES=eth0,eth1 set,
+//! one process collects data for several interfaces. The list may
+//! contain shell style globs, like IF1SEC_INTERFACES=en*,wg*, which
+//! get evaluated again on SIGHUP.
+//!
+//! Made for Linux. On FreeBSD the counters come from getifaddrs (see
+//! the getifaddrs module for which ones), everything needing sysfs,
+//! like suggest and the link, MTU or queue graphs, is Linux only.
+// SPDX-License-Identifier:  GPL-3.0-only
+
+#![warn(missing_docs)]
+
+use anyhow::{anyhow, Result};
+use log::{debug, error, info, log, warn, Level};
+use munin_plugin::{Config, MuninPlugin};
+use serde::Serialize;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use std::{
+    collections::BTreeSet,
+    ffi::OsStr,
+    fs::File,
+    io::{BufWriter, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+mod debug_socket;
+mod ethtool;
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+mod getifaddrs;
+pub mod install;
+pub mod logging;
+pub mod multi;
+#[cfg(target_os = "linux")]
+mod netlink;
+pub mod netns;
+#[cfg(target_os = "linux")]
+mod nftables;
+pub mod notify;
+pub mod options;
+pub mod privs;
+pub mod source;
+mod state;
+mod timing;
+#[cfg(feature = "wireless")]
+mod wireless;
+pub mod wrap;
+use debug_socket::DebugStats;
+use ethtool::{DriverStat, Rings};
+use notify::Notifier;
+use source::{BadSample, Counters, ReadTimeout, StatSource};
+use state::State;
+use timing::{SampleClock, Tick};
+use wrap::{is_glitch, CounterWidth, Unwrapper};
+
+#[derive(Debug)]
+/// The struct for our plugin, so we can easily store some values over
+/// the lifetime of our plugin.
+pub struct InterfacePlugin {
+    /// For which interface we should gather data
+    interface: String,
+
+    /// What the interface is called now, the same as interface,
+    /// unless we followed a rename
+    device: String,
+
+    /// The ifindex of the interface, to recognize it after a rename
+    ifindex: Option<u64>,
+
+    /// Follow renames of the interface, set IF1SEC_FOLLOW_RENAME to 1
+    follow_rename: bool,
+
+    /// Where to find the interface in sysfs, usually /sys/class/net
+    sysfs_root: PathBuf,
+
+    /// Where we read the counters from, None while we wait for the
+    /// interface to appear
+    source: Option<Box<dyn StatSource>>,
+
+    /// The counters as first read, telling which of the optional
+    /// ones the interface provides
+    available: Counters,
+
+    /// How wide the counters of the interface are
+    counter_width: CounterWidth,
+
+    /// Keeps wrapping counters going upwards
+    unwrapper: Unwrapper,
+
+    /// The raw counters of the last sample we used, to notice a
+    /// driver going backwards
+    last_counters: Option<Counters>,
+
+    /// Whether we skipped the last sample for going backwards
+    glitched: bool,
+
+    /// Where to keep the byte counters over a restart, if anywhere
+    state_file: Option<PathBuf>,
+
+    /// When we sampled last
+    clock: SampleClock,
+
+    /// Link speed in Mbps
+    speed: u64,
+
+    /// Whether we know the speed, or just assume 1000
+    speed_known: bool,
+
+    /// Keep the MAC and IP addresses out of the graph info, set
+    /// IF1SEC_HIDE_ADDR to 1 for it
+    hide_addr: bool,
+
+    /// Warn above this utilization of the link, in percent, from
+    /// IF1SEC_WARN_PCT
+    warn_pct: Option<u64>,
+
+    /// Critical above this utilization of the link, in percent, from
+    /// IF1SEC_CRIT_PCT
+    crit_pct: Option<u64>,
+
+    /// Sample every this many seconds
+    update_rate: u64,
+
+    /// The graph_data_size instead of ours, from IF1SEC_DATA_SIZE
+    data_size: Option<String>,
+
+    /// Add a line with the total of rx and tx to the traffic graph
+    show_total: bool,
+
+    /// Show the traffic in bits or bytes
+    units: Units,
+
+    /// Whether to hand munin the counters or the rate of the
+    /// traffic, from IF1SEC_TYPE
+    value_type: ValueType,
+
+    /// Epoch and received bytes of the last sample, for the rate
+    last_rx: Option<(u64, u64)>,
+
+    /// Epoch and transmitted bytes of the last sample, for the rate
+    last_tx: Option<(u64, u64)>,
+
+    /// The highest receive rate so far, since the start or the last
+    /// reload, in the units of the traffic graph
+    rx_peak: f64,
+
+    /// The highest transmit rate so far
+    tx_peak: f64,
+
+    /// The graph base for the traffic graph, 1000 or 1024
+    base: u64,
+
+    /// What to show in the traffic graph instead of our defaults
+    overrides: GraphOverrides,
+
+    /// Which graphs to output, from IF1SEC_FIELDS
+    fields: Fields,
+
+    /// Where to get the link state from, operstate or, lacking
+    /// that, carrier
+    if_link: Option<PathBuf>,
+
+    /// The link state we read last
+    link_state: String,
+
+    /// Where to get the MTU from
+    if_mtu: Option<PathBuf>,
+
+    /// Where to get the number of carrier changes from, not there
+    /// on older kernels
+    if_carrier_changes: Option<PathBuf>,
+
+    /// Where to get the receive overruns (rx_over_errors) from
+    if_rx_over_errors: Option<PathBuf>,
+
+    /// Where to get the packets the NIC missed (rx_missed_errors),
+    /// having no room for them, from
+    if_rx_missed_errors: Option<PathBuf>,
+
+    /// Where to get the frame errors (rx_frame_errors) from, a
+    /// physical layer problem
+    if_rx_frame_errors: Option<PathBuf>,
+
+    /// Where to get the CRC errors (rx_crc_errors) from, a physical
+    /// layer problem
+    if_rx_crc_errors: Option<PathBuf>,
+
+    /// Where to get the transmit carrier errors (tx_carrier_errors)
+    /// from
+    if_tx_carrier_errors: Option<PathBuf>,
+
+    /// Where to get the compressed packets received (rx_compressed)
+    /// from, only for interfaces doing compression, like PPP
+    if_rx_compressed: Option<PathBuf>,
+
+    /// Where to get the compressed packets sent (tx_compressed) from
+    if_tx_compressed: Option<PathBuf>,
+
+    /// Where to get the packets dropped for lack of a protocol
+    /// handler (rx_nohandler) from, not there on kernels before 4.6
+    if_rx_nohandler: Option<PathBuf>,
+
+    /// The bonding directory, if this is a bond
+    if_bonding: Option<PathBuf>,
+
+    /// Is this a Wi-Fi interface, with signal and bitrate?
+    #[cfg(feature = "wireless")]
+    wireless: bool,
+
+    /// Asks for the stations and their traffic, if this is a Wi-Fi
+    /// access point
+    #[cfg(feature = "wireless")]
+    stations: Option<wireless::Stations>,
+
+    /// Reads the nftables counters of the interface, split by IP
+    /// version, if asked for them
+    #[cfg(target_os = "linux")]
+    nftables: Option<nftables::Nftables>,
+
+    /// Where to get the length of the transmit queue from
+    if_txqueuelen: Option<PathBuf>,
+
+    /// The transmit queues, their number and where to get their
+    /// backlog from. Found on startup (and reload), as the number of
+    /// queues differs between interfaces.
+    tx_queues: Vec<(u64, PathBuf)>,
+
+    /// Where to count the receive and transmit queues, the queues
+    /// directory, if the interface has one
+    if_queues: Option<PathBuf>,
+
+    /// Where to read the backlog drops of all CPUs from, if there.
+    /// Not of this interface, but the whole host.
+    softnet: Option<PathBuf>,
+
+    /// The driver statistics (ethtool -S) we graph, the pause frames
+    /// unless IF1SEC_ETHTOOL_STATS says otherwise, as far as the
+    /// driver has them
+    driver_stats: Vec<DriverStat>,
+
+    /// The ring buffer sizes, if the driver tells
+    rings: Option<Rings>,
+
+    /// The driver statistic counting drops for a full receive ring,
+    /// if the driver has rings and one we know, with its index
+    ring_full: Option<(&'static str, usize)>,
+
+    /// Reads the driver statistics, if we graph any
+    ethtool: Option<ethtool::Stats>,
+
+    /// Set when we got asked to stop (SIGTERM/SIGINT)
+    stop: Arc<AtomicBool>,
+
+    /// Set when we got asked to reload (SIGHUP)
+    reload: Arc<AtomicBool>,
+
+    /// Could we read the counters last time?
+    interface_present: bool,
+
+    /// While the interface is missing: the epoch of the next try to
+    /// open it
+    retry_at: u64,
+
+    /// While the interface is missing: seconds to wait after the
+    /// next failed try, doubling every time
+    retry_delay: u64,
+
+    /// Samples we could not take, unreadable, late or while the
+    /// interface was gone
+    skipped: u64,
+
+    /// Tells systemd we are alive, if it runs us
+    notifier: Option<Notifier>,
+
+    /// What we tell the debug socket, if there is one
+    debug: Option<DebugStats>,
+
+    /// Whether acquire already set up what only lives in the daemon,
+    /// the debug socket and the systemd notifier
+    daemon_started: bool,
+}
+
+/// Read the environment variable `name`, if set. As the value ends up
+/// in our config output, it must not span multiple lines, which
+/// would allow to inject arbitrary munin directives.
+fn env_line(name: &str) -> Option<String> {
+    let value = std::env::var(name).ok()?;
+    if value.contains(['\n', '\r']) {
+        warn!("Ignoring {}, it must not contain newlines", name);
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// One sample of our counters, for the JSON output
+#[derive(Debug, Serialize)]
+struct JsonSample<'a> {
+    /// The interface
+    interface: &'a str,
+    /// When we read the counters, seconds since the epoch
+    epoch: u64,
+    /// Received bytes
+    rx_bytes: u64,
+    /// Transmitted bytes
+    tx_bytes: u64,
+    /// Received packets
+    rx_packets: u64,
+    /// Transmitted packets
+    tx_packets: u64,
+}
+
+/// `value` as a positive number, None for anything else
+fn positive(value: &str) -> Option<u64> {
+    value.trim().parse().ok().filter(|&number| number > 0)
+}
+
+/// Parse `value` of the environment variable `name` as a positive
+/// number, ignoring (and complaining about) anything else.
+fn parse_positive(name: &str, value: Option<&str>) -> Option<u64> {
+    let value = value?;
+    match positive(value) {
+        Some(number) => Some(number),
+        None => {
+            warn!("Ignoring invalid {} {:?}", name, value);
+            None
+        }
+    }
+}
+
+/// A utilization threshold in percent from the environment variable
+/// `name`, 1 to 100
+fn threshold_pct(name: &str) -> Option<u64> {
+    let value = std::env::var(name).ok();
+    match parse_positive(name, value.as_deref()) {
+        Some(pct) if pct > 100 => {
+            warn!("Ignoring {} {}, more than 100%", name, pct);
+            None
+        }
+        pct => pct,
+    }
+}
+
+/// How often to sample, in seconds, from IF1SEC_UPDATE_RATE, every
+/// second by default. munin can't go below a second.
+pub fn update_rate() -> u64 {
+    options::parse_or_default("IF1SEC_UPDATE_RATE", positive)
+}
+
+/// How much to buffer when handing out data, from IF1SEC_FETCHSIZE,
+/// 64k by default. Arbitary, but better than the default 8k of
+/// munin-plugin.
+pub fn fetch_size() -> usize {
+    options::parse_or_default("IF1SEC_FETCHSIZE", positive) as usize
+}
+
+/// Should we follow the interface to its new name, when it gets
+/// renamed? Set IF1SEC_FOLLOW_RENAME to 1 for it.
+fn follow_rename() -> bool {
+    std::env::var("IF1SEC_FOLLOW_RENAME").as_deref() == Ok("1")
+}
+
+/// Should the MAC and IP addresses stay out of the config? Set
+/// IF1SEC_HIDE_ADDR to 1 for it.
+fn hide_addr() -> bool {
+    std::env::var("IF1SEC_HIDE_ADDR").as_deref() == Ok("1")
+}
+
+/// Should the traffic graph have a total line? Set IF1SEC_SHOW_TOTAL
+/// to 1 for it.
+fn show_total() -> bool {
+    std::env::var("IF1SEC_SHOW_TOTAL").as_deref() == Ok("1")
+}
+
+/// Presentation settings of the traffic graph to use instead of our
+/// own, from IF1SEC_GRAPH_ARGS, IF1SEC_GRAPH_VLABEL and
+/// IF1SEC_GRAPH_ORDER. Each a single line, see [env_line].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct GraphOverrides {
+    /// graph_args, instead of just the base
+    args: Option<String>,
+    /// graph_vlabel
+    vlabel: Option<String>,
+    /// graph_order, we don't set one
+    order: Option<String>,
+}
+
+impl GraphOverrides {
+    /// What the environment wants changed
+    fn from_env() -> Self {
+        Self {
+            args: env_line("IF1SEC_GRAPH_ARGS"),
+            vlabel: env_line("IF1SEC_GRAPH_VLABEL"),
+            order: env_line("IF1SEC_GRAPH_ORDER"),
+        }
+    }
+}
+
+/// All the graphs we have, as IF1SEC_FIELDS names them. `bytes` is
+/// the traffic graph, the others go by their multigraph name. Each is
+/// only there if the interface has what it needs.
+const GRAPHS: [&str; 27] = [
+    "bytes",
+    "peak",
+    "packets",
+    "pktsize",
+    "errors",
+    "overruns",
+    "linkerrors",
+    "carrier",
+    "nohandler",
+    "compressed",
+    "drops",
+    "softnet",
+    "multicast",
+    "collisions",
+    "link",
+    "mtu",
+    "flaps",
+    "bond",
+    "wireless",
+    "stations",
+    "ipver",
+    "txqueuelen",
+    "txbacklog",
+    "queues",
+    "ethtool",
+    "rings",
+    "skipped",
+];
+
+/// The graphs of [GRAPHS] about the whole host, not the interface.
+/// Every if1sec_<interface> plugin would output the same, so `all`
+/// leaves them out, they have to be asked for by name.
+const HOST_GRAPHS: [&str; 1] = ["softnet"];
+
+/// The graphs to output, out of [GRAPHS]. Only the traffic graph by
+/// default, as it always was, IF1SEC_FIELDS picks others.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Fields(BTreeSet<&'static str>);
+
+impl Default for Fields {
+    fn default() -> Self {
+        Fields::parse(options::default("IF1SEC_FIELDS"))
+    }
+}
+
+impl Fields {
+    /// All of them, but the [HOST_GRAPHS]
+    fn all() -> Self {
+        Fields(
+            GRAPHS
+                .into_iter()
+                .filter(|graph| !HOST_GRAPHS.contains(graph))
+                .collect(),
+        )
+    }
+
+    /// Parse a comma separated list of graph names, `all` for all of
+    /// them (see [Fields::all]), ignoring (and complaining about)
+    /// unknown ones
+    fn parse(value: &str) -> Self {
+        let mut fields = BTreeSet::new();
+        for name in value
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            if name == "all" {
+                fields.extend(Fields::all().0);
+                continue;
+            }
+            match GRAPHS.into_iter().find(|graph| *graph == name) {
+                Some(graph) => {
+                    fields.insert(graph);
+                }
+                None => warn!(
+                    "Ignoring unknown field {} in IF1SEC_FIELDS, known are all, {}",
+                    name,
+                    GRAPHS.join(", ")
+                ),
+            }
+        }
+        Fields(fields)
+    }
+
+    /// The graphs IF1SEC_FIELDS asks for
+    fn from_env() -> Self {
+        std::env::var("IF1SEC_FIELDS")
+            .map_or_else(|_| Fields::default(), |value| Fields::parse(&value))
+    }
+
+    /// Is `graph` one to output?
+    fn has(&self, graph: &str) -> bool {
+        self.0.contains(graph)
+    }
+}
+
+/// What the traffic graph shows. The values are always bytes, munin
+/// multiplies them for bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Units {
+    /// Bits per second, the default
+    Bits,
+    /// Bytes per second
+    Bytes,
+}
+
+impl Units {
+    /// Read the units from IF1SEC_UNITS, `bits` (the default) or
+    /// `bytes`.
+    fn from_env() -> Self {
+        options::parse_or_default("IF1SEC_UNITS", |value| match value {
+            "bits" => Some(Units::Bits),
+            "bytes" => Some(Units::Bytes),
+            _ => None,
+        })
+    }
+
+    /// The cdef suffix to get from bytes to these units
+    fn cdef(self) -> &'static str {
+        match self {
+            Units::Bits => ",8,*",
+            Units::Bytes => "",
+        }
+    }
+
+    /// How many of these units make a byte
+    fn per_byte(self) -> u64 {
+        match self {
+            Units::Bits => 8,
+            Units::Bytes => 1,
+        }
+    }
+}
+
+/// How we hand the traffic to munin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValueType {
+    /// The byte counters, munin makes a rate of them, the default
+    Derive,
+    /// The rate, computed by us, in the units of the graph
+    Gauge,
+}
+
+impl ValueType {
+    /// Read the type from IF1SEC_TYPE, `derive` (the default) or
+    /// `gauge`.
+    fn from_env() -> Self {
+        options::parse_or_default("IF1SEC_TYPE", |value| match value {
+            "derive" => Some(ValueType::Derive),
+            "gauge" => Some(ValueType::Gauge),
+            _ => None,
+        })
+    }
+}
+
+/// The rate of a counter, now at `value` at `epoch`, since the
+/// sample `last` (epoch and value), per second and times `scale`.
+/// None without an earlier sample, or if the counter went backwards.
+fn rate(last: Option<(u64, u64)>, epoch: u64, value: u64, scale: u64) -> Option<f64> {
+    let (last_epoch, last_value) = last?;
+    if epoch <= last_epoch || value < last_value {
+        return None;
+    }
+    Some(((value - last_value) * scale) as f64 / (epoch - last_epoch) as f64)
+}
+
+/// The base for scaling the traffic graph, from IF1SEC_BASE. 1000 (the
+/// default) as usual for networks, or 1024 to match storage graphs.
+fn graph_base() -> u64 {
+    options::parse_or_default("IF1SEC_BASE", |value| match value {
+        "1000" => Some(1000),
+        "1024" => Some(1024),
+        _ => None,
+    })
+}
+
+/// The graph_data_size for samples every `rate` seconds. A day at
+/// full resolution, then coarser and longer, leaving out the steps
+/// that aren't a multiple of `rate`.
+fn data_size(rate: u64) -> String {
+    let mut size = String::from("custom 1d");
+    for (step, name, span) in [
+        (1, "1s", "1d"),
+        (5, "5s", "2d"),
+        (10, "10s", "7d"),
+        (60, "1m", "1t"),
+        (300, "5m", "1y"),
+    ] {
+        if step % rate == 0 {
+            size.push_str(&format!(", {} for {}", name, span));
+        }
+    }
+    size
+}
+
+/// Check a graph_data_size for munin: `normal`, `huge`, or `custom`
+/// with the spans. None (and a warning) for anything else.
+fn parse_data_size(value: &str) -> Option<String> {
+    let value = value.trim();
+    let custom = value
+        .strip_prefix("custom ")
+        .is_some_and(|spans| !spans.trim().is_empty());
+    if custom || value == "normal" || value == "huge" {
+        Some(value.to_string())
+    } else {
+        warn!(
+            "Ignoring IF1SEC_DATA_SIZE {:?}, not normal, huge or custom",
+            value
+        );
+        None
+    }
+}
+
+/// The graph_data_size from IF1SEC_DATA_SIZE, if set (and sensible)
+fn data_size_override() -> Option<String> {
+    parse_data_size(&env_line("IF1SEC_DATA_SIZE")?)
+}
+
+/// Longest wait between two tries to open a missing interface
+const MAX_RETRY_DELAY: u64 = 64;
+
+/// Wait before the first retry to open an interface at startup,
+/// doubling with every further one
+const OPEN_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// How often to retry opening an interface at startup, from
+/// IF1SEC_OPEN_RETRIES. 3 by default, together some 700ms, 0 to fail
+/// right away.
+fn open_retries() -> u32 {
+    options::parse_or_default("IF1SEC_OPEN_RETRIES", |value| value.trim().parse().ok())
+}
+
+/// The value of the link graph for states neither up nor down
+const LINK_OTHER: f64 = 0.5;
+
+/// What the content of operstate (or carrier) says about the link
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LinkState {
+    /// There is a link
+    Up,
+    /// There is none
+    Down,
+    /// Anything else: dormant, testing, unknown, ...
+    Other,
+}
+
+impl LinkState {
+    /// The link state for the content `state` of operstate or carrier
+    fn of(state: &str) -> Self {
+        match state {
+            "up" | "1" => LinkState::Up,
+            "down" | "lowerlayerdown" | "notpresent" | "0" => LinkState::Down,
+            _ => LinkState::Other,
+        }
+    }
+}
+
+/// Map the content of operstate (or carrier) to the value of the link
+/// graph: 1 up, 0 down, [LINK_OTHER] for anything else.
+fn link_value(state: &str) -> f64 {
+    match LinkState::of(state) {
+        LinkState::Up => 1.0,
+        LinkState::Down => 0.0,
+        LinkState::Other => LINK_OTHER,
+    }
+}
+
+/// What to log, and how loud, when the link state of `interface` goes
+/// from `old` to `new` at `epoch`. Going down is a warning, as is any
+/// odd state, coming up just information. The first state we see is
+/// no change, only worth a debug line.
+fn link_change(interface: &str, old: &str, new: &str, epoch: u64) -> (Level, String) {
+    if old.is_empty() {
+        return (Level::Debug, format!("Link of {} is {}", interface, new));
+    }
+    let (level, change) = match LinkState::of(new) {
+        LinkState::Up => (Level::Info, "went up"),
+        LinkState::Down => (Level::Warn, "went down"),
+        LinkState::Other => (Level::Warn, "changed"),
+    };
+    (
+        level,
+        format!(
+            "Link of {} {} at {} (from {} to {})",
+            interface, change, epoch, old, new
+        ),
+    )
+}
+
+impl InterfacePlugin {
+    /// Open the source for the counters of `interface` below `root`,
+    /// and read them once, to see what it provides.
+    fn open_source(root: &Path, interface: &str) -> Result<(Box<dyn StatSource>, Counters)> {
+        let mut source = source::from_env(root, interface)?;
+        let available = source.read_counters()?;
+        Ok((source, available))
+    }
+
+    /// [InterfacePlugin::open_source], trying again up to `retries`
+    /// times. Right after udev or a namespace created the interface
+    /// its files may not all be there yet.
+    fn open_source_retry(
+        root: &Path,
+        interface: &str,
+        retries: u32,
+    ) -> Result<(Box<dyn StatSource>, Counters)> {
+        let mut delay = OPEN_RETRY_DELAY;
+        let mut attempt = 0;
+        loop {
+            match InterfacePlugin::open_source(root, interface) {
+                Err(e) if attempt < retries => {
+                    attempt += 1;
+                    debug!(
+                        "{}, retry {} of {} in {}ms",
+                        e,
+                        attempt,
+                        retries,
+                        delay.as_millis()
+                    );
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Read the ifindex of `interface` below `root`
+    fn read_ifindex(root: &Path, interface: &str) -> Option<u64> {
+        let content = std::fs::read_to_string(root.join(interface).join("ifindex")).ok()?;
+        content.trim().parse().ok()
+    }
+
+    /// Find the interface with `ifindex` below `root`
+    fn find_ifindex(root: &Path, ifindex: u64) -> Option<String> {
+        std::fs::read_dir(root)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .find(|name| InterfacePlugin::read_ifindex(root, name) == Some(ifindex))
+    }
+
+    /// If our interface is gone, but its ifindex is still there
+    /// under another name, it got renamed. Follow it there, if asked
+    /// to, keeping the original name in our graphs.
+    fn check_rename(&mut self) {
+        if !self.follow_rename || self.sysfs_root.join(&self.device).exists() {
+            return;
+        }
+        let Some(ifindex) = self.ifindex else {
+            return;
+        };
+        if let Some(name) = InterfacePlugin::find_ifindex(&self.sysfs_root, ifindex) {
+            info!(
+                "Interface {} got renamed to {}, following it",
+                self.device, name
+            );
+            self.device = name;
+            self.find_device_files();
+        }
+    }
+
+    /// Count a sample we could not take, and tell the debug socket
+    fn skip(&mut self) {
+        self.skipped += 1;
+        if let Some(debug) = &self.debug {
+            debug.skipped(&self.interface, self.skipped, None);
+        }
+    }
+
+    /// Tell the debug socket why reading the counters failed
+    fn debug_error(&self, e: &anyhow::Error) {
+        if let Some(debug) = &self.debug {
+            debug.skipped(&self.interface, self.skipped, Some(&e.to_string()));
+        }
+    }
+
+    /// Read the counters, dealing with the interface vanishing and
+    /// coming back. None if there is nothing to report this time.
+    fn read_counters(&mut self, epoch: u64) -> Option<Counters> {
+        let result = match self.source.as_mut() {
+            Some(source) => source.read_counters(),
+            None => Err(anyhow!("Interface {} not found", self.interface)),
+        };
+        match result {
+            Ok(counters) => {
+                if !self.interface_present {
+                    info!("Interface {} is back, collecting again", self.interface);
+                    self.interface_present = true;
+                    self.retry_delay = 1;
+                }
+                Some(counters)
+            }
+            Err(e) if e.is::<BadSample>() => {
+                self.debug_error(&e);
+                // A glitch, skip this one, the next will be fine
+                warn!("Skipping bad sample for {}: {}", self.interface, e);
+                None
+            }
+            Err(e) if e.is::<ReadTimeout>() => {
+                self.debug_error(&e);
+                // The driver hangs, it is still there though
+                warn!("Skipping sample for {}: {}", self.interface, e);
+                None
+            }
+            Err(e) => {
+                self.debug_error(&e);
+                // Only tell once per outage, not every second
+                if self.interface_present {
+                    warn!(
+                        "Can not read counters for {}, interface gone? ({})",
+                        self.interface, e
+                    );
+                    self.interface_present = false;
+                    self.retry_at = epoch;
+                }
+                // The old source may be stale for good (sysfs files of
+                // a removed interface stay dead, even if it comes
+                // back), so try a fresh one now and then.
+                if epoch >= self.retry_at {
+                    self.check_rename();
+                    match InterfacePlugin::open_source(&self.sysfs_root, &self.device) {
+                        Ok((source, available)) => {
+                            info!("Interface {} appeared, collecting again", self.interface);
+                            self.ifindex =
+                                InterfacePlugin::read_ifindex(&self.sysfs_root, &self.device);
+                            self.source = Some(source);
+                            self.available = available;
+                            // Likely counting from scratch, a drop that
+                            // must not look like a wrap
+                            self.last_counters = None;
+                            self.unwrapper.restart(&available);
+                            self.interface_present = true;
+                            self.retry_delay = 1;
+                            return Some(available);
+                        }
+                        Err(e) => {
+                            debug!(
+                                "Interface {} still missing ({}), next try in {}s",
+                                self.interface, e, self.retry_delay
+                            );
+                            self.retry_at = epoch + self.retry_delay;
+                            self.retry_delay = (self.retry_delay * 2).min(MAX_RETRY_DELAY);
+                        }
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    /// Whether `counters` went backwards from the last sample, in a
+    /// way only a buggy driver does (see [is_glitch]). Such a sample
+    /// gets skipped, once: if the next is still lower, the counters
+    /// really went down, and we carry on from there.
+    fn check_glitch(&mut self, counters: &Counters) -> bool {
+        if let Some(last) = self.last_counters.filter(|_| !self.glitched) {
+            let pairs = [
+                ("rx_bytes", Some(last.rx_bytes), Some(counters.rx_bytes)),
+                ("tx_bytes", Some(last.tx_bytes), Some(counters.tx_bytes)),
+                (
+                    "rx_packets",
+                    Some(last.rx_packets),
+                    Some(counters.rx_packets),
+                ),
+                (
+                    "tx_packets",
+                    Some(last.tx_packets),
+                    Some(counters.tx_packets),
+                ),
+                ("rx_errors", last.rx_errors, counters.rx_errors),
+                ("tx_errors", last.tx_errors, counters.tx_errors),
+                ("rx_dropped", last.rx_dropped, counters.rx_dropped),
+                ("tx_dropped", last.tx_dropped, counters.tx_dropped),
+                ("multicast", last.multicast, counters.multicast),
+                ("collisions", last.collisions, counters.collisions),
+            ];
+            let mut glitch = false;
+            for (name, prev, curr) in pairs {
+                if let (Some(prev), Some(curr)) = (prev, curr) {
+                    if is_glitch(prev, curr, self.counter_width) {
+                        warn!(
+                            "Counter {} of {} went back from {} to {}, driver bug? Skipping the sample",
+                            name, self.interface, prev, curr
+                        );
+                        glitch = true;
+                    }
+                }
+            }
+            if glitch {
+                self.glitched = true;
+                return true;
+            }
+        }
+        self.last_counters = Some(*counters);
+        self.glitched = false;
+        false
+    }
+
+    /// Read the counters and write out the values for all our graphs
+    fn write_values<W: Write>(&mut self, handle: &mut BufWriter<W>, epoch: u64) -> Result<()> {
+        // Read in all the counters in one go, nothing to say while
+        // the interface is missing
+        let mut counters = match self.read_counters(epoch) {
+            Some(counters) => counters,
+            None => {
+                self.skip();
+                return Ok(());
+            }
+        };
+        if self.check_glitch(&counters) {
+            self.skip();
+            return Ok(());
+        }
+        let raw = counters;
+        self.unwrapper.apply(&mut counters, self.counter_width);
+        if let Some(debug) = &self.debug {
+            debug.sample(&self.interface, epoch, &raw, &counters);
+        }
+        if let Some(path) = &self.state_file {
+            let (rx_bytes, tx_bytes) = self.unwrapper.bytes();
+            let state = State {
+                epoch,
+                rx_bytes,
+                tx_bytes,
+            };
+            if let Err(e) = state.save(path) {
+                debug!("Can not save state to {}: {}", path.display(), e);
+            }
+        }
+
+        // And now write out values
+        if self.fields.has("bytes") {
+            match self.value_type {
+                ValueType::Derive => {
+                    writeln!(handle, "multigraph if1sec_{}", self.interface)?;
+                    writeln!(
+                        handle,
+                        "{0}_tx.value {1}:{2}",
+                        self.interface, epoch, counters.tx_bytes
+                    )?;
+                    writeln!(
+                        handle,
+                        "{0}_rx.value {1}:{2}",
+                        self.interface, epoch, counters.rx_bytes
+                    )?;
+                }
+                ValueType::Gauge => {
+                    let scale = self.units.per_byte();
+                    let tx = rate(self.last_tx, epoch, counters.tx_bytes, scale);
+                    let rx = rate(self.last_rx, epoch, counters.rx_bytes, scale);
+                    // Nothing yet for the first sample
+                    if tx.is_some() || rx.is_some() {
+                        writeln!(handle, "multigraph if1sec_{}", self.interface)?;
+                    }
+                    if let Some(tx) = tx {
+                        writeln!(handle, "{0}_tx.value {1}:{2}", self.interface, epoch, tx)?;
+                    }
+                    if let Some(rx) = rx {
+                        writeln!(handle, "{0}_rx.value {1}:{2}", self.interface, epoch, rx)?;
+                    }
+                }
+            }
+        }
+        let scale = self.units.per_byte();
+        if let Some(rx) = rate(self.last_rx, epoch, counters.rx_bytes, scale) {
+            self.rx_peak = self.rx_peak.max(rx);
+        }
+        if let Some(tx) = rate(self.last_tx, epoch, counters.tx_bytes, scale) {
+            self.tx_peak = self.tx_peak.max(tx);
+        }
+        self.last_rx = Some((epoch, counters.rx_bytes));
+        self.last_tx = Some((epoch, counters.tx_bytes));
+        if self.fields.has("peak") {
+            writeln!(handle, "multigraph if1sec_peak_{}", self.interface)?;
+            writeln!(
+                handle,
+                "{0}_tx_peak.value {1}:{2}",
+                self.interface, epoch, self.tx_peak
+            )?;
+            writeln!(
+                handle,
+                "{0}_rx_peak.value {1}:{2}",
+                self.interface, epoch, self.rx_peak
+            )?;
+        }
+        if self.fields.has("packets") {
+            self.write_rxtx(
+                handle,
+                "packets",
+                epoch,
+                Some(counters.rx_packets),
+                Some(counters.tx_packets),
+            )?;
+        }
+        if self.fields.has("pktsize") {
+            writeln!(handle, "multigraph if1sec_pktsize_{}", self.interface)?;
+            for (field, value) in [
+                ("txbytes", counters.tx_bytes),
+                ("txpackets", counters.tx_packets),
+                ("rxbytes", counters.rx_bytes),
+                ("rxpackets", counters.rx_packets),
+            ] {
+                writeln!(
+                    handle,
+                    "{0}_{1}.value {2}:{3}",
+                    self.interface, field, epoch, value
+                )?;
+            }
+        }
+        if self.fields.has("errors")
+            && (counters.rx_errors.is_some() || counters.tx_errors.is_some())
+        {
+            self.write_rxtx(
+                handle,
+                "errors",
+                epoch,
+                counters.rx_errors,
+                counters.tx_errors,
+            )?;
+        }
+        self.write_files(
+            handle,
+            "overruns",
+            epoch,
+            &[
+                ("over", &self.if_rx_over_errors),
+                ("missed", &self.if_rx_missed_errors),
+            ],
+        )?;
+        self.write_files(
+            handle,
+            "linkerrors",
+            epoch,
+            &[
+                ("frame", &self.if_rx_frame_errors),
+                ("crc", &self.if_rx_crc_errors),
+            ],
+        )?;
+        self.write_files(
+            handle,
+            "carrier",
+            epoch,
+            &[("carrier", &self.if_tx_carrier_errors)],
+        )?;
+        self.write_files(
+            handle,
+            "nohandler",
+            epoch,
+            &[("nohandler", &self.if_rx_nohandler)],
+        )?;
+        self.write_files(
+            handle,
+            "compressed",
+            epoch,
+            &[
+                ("tx", &self.if_tx_compressed),
+                ("rx", &self.if_rx_compressed),
+            ],
+        )?;
+        if self.fields.has("drops")
+            && (counters.rx_dropped.is_some() || counters.tx_dropped.is_some())
+        {
+            self.write_rxtx(
+                handle,
+                "drops",
+                epoch,
+                counters.rx_dropped,
+                counters.tx_dropped,
+            )?;
+        }
+        if let Some(path) = self.softnet.as_ref().filter(|_| self.fields.has("softnet")) {
+            match source::softnet_dropped(path) {
+                Ok(value) => {
+                    writeln!(handle, "multigraph if1sec_softnet")?;
+                    writeln!(handle, "softnet.value {}:{}", epoch, value)?;
+                }
+                Err(e) => debug!("Can not read the backlog drops: {}", e),
+            }
+        }
+        if let Some(value) = counters.multicast.filter(|_| self.fields.has("multicast")) {
+            self.write_counter(handle, "multicast", "multicast", epoch, value)?;
+        }
+        if let Some(value) = counters
+            .collisions
+            .filter(|_| self.fields.has("collisions"))
+        {
+            self.write_counter(handle, "collisions", "collisions", epoch, value)?;
+        }
+        // Also keeps track of the link state, so always read
+        if let Some(value) = self.read_link(epoch).filter(|_| self.fields.has("link")) {
+            self.write_counter(handle, "link", "link", epoch, value)?;
+        }
+        if let Some(path) = self.if_mtu.as_ref().filter(|_| self.fields.has("mtu")) {
+            match source::read_counter(path) {
+                Ok(value) => self.write_counter(handle, "mtu", "mtu", epoch, value)?,
+                Err(e) => debug!("Can not read MTU of {}: {}", self.interface, e),
+            }
+        }
+        if let Some(path) = self
+            .if_carrier_changes
+            .as_ref()
+            .filter(|_| self.fields.has("flaps"))
+        {
+            match source::read_counter(path) {
+                Ok(value) => self.write_counter(handle, "flaps", "flaps", epoch, value)?,
+                Err(e) => debug!("Can not read carrier_changes of {}: {}", self.interface, e),
+            }
+        }
+        if let Some(dir) = self.if_bonding.as_ref().filter(|_| self.fields.has("bond")) {
+            match InterfacePlugin::bond_active_slaves(&self.sysfs_root, dir) {
+                Ok(value) => self.write_counter(handle, "bond", "active", epoch, value)?,
+                Err(e) => debug!("Can not read slaves of {}: {}", self.interface, e),
+            }
+        }
+        #[cfg(feature = "wireless")]
+        if self.wireless && self.fields.has("wireless") {
+            if let Some(value) = wireless::signal(&self.device) {
+                self.write_counter(handle, "signal", "signal", epoch, value)?;
+            }
+            if let Some(value) = wireless::bitrate(&self.device) {
+                self.write_counter(handle, "bitrate", "bitrate", epoch, value)?;
+            }
+        }
+        #[cfg(feature = "wireless")]
+        if let Some(stations) = self
+            .stations
+            .as_ref()
+            .filter(|_| self.fields.has("stations"))
+        {
+            match stations.dump() {
+                Ok(stations) => {
+                    writeln!(handle, "multigraph if1sec_stations_{}", self.interface)?;
+                    for station in stations {
+                        let field = station.field();
+                        writeln!(
+                            handle,
+                            "{0}_{1}_tx.value {2}:{3}",
+                            self.interface, field, epoch, station.tx_bytes
+                        )?;
+                        writeln!(
+                            handle,
+                            "{0}_{1}_rx.value {2}:{3}",
+                            self.interface, field, epoch, station.rx_bytes
+                        )?;
+                    }
+                }
+                Err(e) => debug!("Can not get the stations of {}: {}", self.interface, e),
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(nftables) = self.nftables.as_ref().filter(|_| self.fields.has("ipver")) {
+            match nftables.traffic() {
+                Ok(traffic) if traffic.is_empty() => {}
+                Ok(traffic) => {
+                    writeln!(handle, "multigraph if1sec_ipver_{}", self.interface)?;
+                    for (version, _, rx, tx) in traffic.versions() {
+                        for (dir, value) in [("tx", tx), ("rx", rx)] {
+                            if let Some(value) = value {
+                                writeln!(
+                                    handle,
+                                    "{0}_{1}_{2}.value {3}:{4}",
+                                    self.interface, version, dir, epoch, value
+                                )?;
+                            }
+                        }
+                    }
+                }
+                Err(e) => debug!(
+                    "Can not read the nftables counters of {}: {}",
+                    self.interface, e
+                ),
+            }
+        }
+        if let Some(path) = self
+            .if_txqueuelen
+            .as_ref()
+            .filter(|_| self.fields.has("txqueuelen"))
+        {
+            match source::read_counter(path) {
+                Ok(value) => {
+                    self.write_counter(handle, "txqueuelen", "txqueuelen", epoch, value)?
+                }
+                Err(e) => debug!("Can not read tx_queue_len of {}: {}", self.interface, e),
+            }
+        }
+        if self.fields.has("txbacklog") && !self.tx_queues.is_empty() {
+            writeln!(handle, "multigraph if1sec_txbacklog_{}", self.interface)?;
+            for (queue, path) in &self.tx_queues {
+                match source::read_counter(path) {
+                    Ok(value) => writeln!(
+                        handle,
+                        "{0}_txq{1}.value {2}:{3}",
+                        self.interface, queue, epoch, value
+                    )?,
+                    Err(e) => debug!(
+                        "Can not read backlog of queue {} of {}: {}",
+                        queue, self.interface, e
+                    ),
+                }
+            }
+        }
+        if let Some(path) = self
+            .if_queues
+            .as_ref()
+            .filter(|_| self.fields.has("queues"))
+        {
+            match source::queue_counts(path) {
+                Ok((rx, tx)) => self.write_rxtx(handle, "queues", epoch, Some(rx), Some(tx))?,
+                Err(e) => debug!("Can not count queues of {}: {}", self.interface, e),
+            }
+        }
+        let driver_stats: &[DriverStat] = if self.fields.has("ethtool") {
+            &self.driver_stats
+        } else {
+            &[]
+        };
+        let ring_full = self.ring_full.filter(|_| self.fields.has("rings"));
+        let mut ring_full_value = None;
+        if !driver_stats.is_empty() || ring_full.is_some() {
+            if let Some(values) = self.ethtool.as_mut().and_then(|stats| stats.read()) {
+                if !driver_stats.is_empty() {
+                    writeln!(handle, "multigraph if1sec_ethtool_{}", self.interface)?;
+                }
+                for stat in driver_stats {
+                    writeln!(
+                        handle,
+                        "{0}_{1}.value {2}:{3}",
+                        self.interface, stat.field, epoch, values[stat.index]
+                    )?;
+                }
+                ring_full_value = ring_full.map(|(_, index)| values[index]);
+            }
+        }
+        if let Some(value) = ring_full_value {
+            self.write_counter(handle, "rings", "ringfull", epoch, value)?;
+        }
+        if self.fields.has("skipped") {
+            self.write_counter(handle, "skipped", "skipped", epoch, self.skipped)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the link state, None if we can't tell. Logs changes.
+    fn read_link(&mut self, epoch: u64) -> Option<f64> {
+        let path = self.if_link.as_ref()?;
+        // carrier can not be read while the interface is down, that
+        // is as good as no link at all
+        let state = match std::fs::read_to_string(path) {
+            Ok(state) => state.trim().to_string(),
+            Err(e) => {
+                debug!("Can not read link state of {}: {}", self.interface, e);
+                // Open counter files keep working after a rename, the
+                // link state is the first to notice
+                self.check_rename();
+                String::from("0")
+            }
+        };
+        let value = link_value(&state);
+        if state != self.link_state {
+            let (level, message) = link_change(&self.interface, &self.link_state, &state, epoch);
+            log!(level, "{}", message);
+            self.link_state = state;
+        }
+        Some(value)
+    }
+
+    /// Find where to read the link state of `interface` below `root`
+    /// from
+    fn link_file(root: &Path, interface: &str) -> Option<PathBuf> {
+        source::device_file(root, interface, "operstate")
+            .or_else(|| source::device_file(root, interface, "carrier"))
+    }
+
+    /// Find the optional sysfs files of our interface, those that
+    /// may come and go with a reconfiguration
+    fn find_device_files(&mut self) {
+        let root = &self.sysfs_root;
+        let interface = &self.device;
+        self.if_link = InterfacePlugin::link_file(root, interface);
+        self.if_mtu = source::device_file(root, interface, "mtu");
+        self.if_carrier_changes = source::device_file(root, interface, "carrier_changes");
+        self.if_rx_over_errors = source::device_file(root, interface, "statistics/rx_over_errors");
+        self.if_rx_missed_errors =
+            source::device_file(root, interface, "statistics/rx_missed_errors");
+        self.if_rx_frame_errors =
+            source::device_file(root, interface, "statistics/rx_frame_errors");
+        self.if_rx_crc_errors = source::device_file(root, interface, "statistics/rx_crc_errors");
+        self.if_tx_carrier_errors =
+            source::device_file(root, interface, "statistics/tx_carrier_errors");
+        self.if_rx_nohandler = source::device_file(root, interface, "statistics/rx_nohandler");
+        // Every interface has them, only those compressing count
+        let rx_compressed = source::device_file(root, interface, "statistics/rx_compressed");
+        let tx_compressed = source::device_file(root, interface, "statistics/tx_compressed");
+        let compressing = [&rx_compressed, &tx_compressed].iter().any(|path| {
+            path.as_ref()
+                .is_some_and(|path| source::read_counter(path).is_ok_and(|value| value > 0))
+        });
+        (self.if_rx_compressed, self.if_tx_compressed) = if compressing {
+            (rx_compressed, tx_compressed)
+        } else {
+            (None, None)
+        };
+        self.if_bonding = source::device_file(root, interface, "bonding");
+        #[cfg(feature = "wireless")]
+        {
+            self.wireless = wireless::is_wireless(root, interface);
+            // A netlink socket and two round trips, only when wanted
+            self.stations = if self.wireless && self.fields.has("stations") {
+                wireless::Stations::ap(interface)
+            } else {
+                None
+            };
+        }
+        // Needs CAP_NET_ADMIN, only try when wanted
+        #[cfg(target_os = "linux")]
+        {
+            self.nftables = if self.fields.has("ipver") {
+                nftables::Nftables::open(interface)
+            } else {
+                None
+            };
+        }
+        self.if_txqueuelen = source::device_file(root, interface, "tx_queue_len");
+        self.tx_queues = source::tx_queues(root, interface);
+        self.if_queues = source::device_file(root, interface, "queues");
+        self.softnet = Some(PathBuf::from(source::SOFTNET_STAT)).filter(|path| path.exists());
+        // Asking the driver is some ioctls, the names of its statistics a
+        // whole string table, only do that for graphs using them. The
+        // ring sizes also go into the info of the drops.
+        let (stats, names) = if self.fields.has("ethtool") || self.fields.has("rings") {
+            ethtool::Stats::open(interface).unzip()
+        } else {
+            (None, None)
+        };
+        let names = names.unwrap_or_default();
+        self.driver_stats = ethtool::pick(
+            std::env::var("IF1SEC_ETHTOOL_STATS").ok().as_deref(),
+            &names,
+        );
+        self.rings = if self.fields.has("rings") || self.fields.has("drops") {
+            ethtool::ring_sizes(interface)
+        } else {
+            None
+        };
+        self.ring_full = self.rings.and(ethtool::ring_full_stat(&names));
+        // Only kept open for something to read
+        self.ethtool = stats.filter(|_| !self.driver_stats.is_empty() || self.ring_full.is_some());
+    }
+
+    /// Read the counters once, ignoring all the tracking acquire()
+    /// does, for the one-shot outputs
+    fn read_once(&mut self) -> Result<Counters> {
+        match self.source.as_mut() {
+            Some(source) => source.read_counters(),
+            None => Err(anyhow!("Interface {} not found", self.interface)),
+        }
+    }
+
+    /// Write the current byte counters in the Prometheus text
+    /// exposition format
+    pub fn write_prometheus<W: Write>(&mut self, handle: &mut BufWriter<W>) -> Result<()> {
+        let counters = self.read_once()?;
+        let interface = self
+            .interface
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        for (name, help, value) in [
+            ("rx_bytes", "Received bytes", counters.rx_bytes),
+            ("tx_bytes", "Transmitted bytes", counters.tx_bytes),
+        ] {
+            writeln!(handle, "# HELP if1sec_{}_total {}.", name, help)?;
+            writeln!(handle, "# TYPE if1sec_{}_total counter", name)?;
+            writeln!(
+                handle,
+                "if1sec_{}_total{{interface=\"{}\"}} {}",
+                name, interface, value
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write the current counters as one JSON object
+    pub fn write_json<W: Write>(&mut self, handle: &mut BufWriter<W>, epoch: u64) -> Result<()> {
+        let counters = self.read_once()?;
+        let sample = JsonSample {
+            interface: &self.interface,
+            epoch,
+            rx_bytes: counters.rx_bytes,
+            tx_bytes: counters.tx_bytes,
+            rx_packets: counters.rx_packets,
+            tx_packets: counters.tx_packets,
+        };
+        serde_json::to_writer(&mut *handle, &sample)?;
+        writeln!(handle)?;
+        Ok(())
+    }
+
+    /// Check the name we are called with and return the interface
+    /// part of it. So if1sec_eth0 -> eth0. Unless IF1SEC_INTERFACE
+    /// names one, that wins.
+    pub fn get_interface() -> Result<String> {
+        if let Some(interface) = InterfacePlugin::interface_override(env_line("IF1SEC_INTERFACE")) {
+            return interface;
+        }
+        // args_os, a name that isn't UTF-8 is an error, not a panic
+        let name = std::env::args_os()
+            .next()
+            .ok_or_else(|| anyhow!("Called without a name, symlink me as if1sec_<iface>"))?;
+        InterfacePlugin::interface_from_name(&name)
+    }
+
+    /// Find out the link speed of `interface` below `root` in Mbps,
+    /// None if neither the admin, sysfs nor the driver know.
+    fn link_speed(root: &Path, interface: &str) -> Option<u64> {
+        let wanted = std::env::var("IF1SEC_MAX_SPEED_MBPS").ok();
+        InterfacePlugin::choose_speed(
+            InterfacePlugin::parse_speed_override(wanted.as_deref()),
+            || {
+                InterfacePlugin::bond_aggregate_speed(root, interface).or_else(|| {
+                    InterfacePlugin::bridge_speed(root, interface, InterfacePlugin::bridge_sum())
+                })
+            },
+            // Check network "speed" as shown by VM
+            || InterfacePlugin::sysfs_speed(root, interface),
+            || ethtool::detect_speed(interface).map(u64::from),
+        )
+    }
+
+    /// Pick the speed to use: set by the admin, from the interfaces
+    /// making up a bond or bridge, from sysfs or from the driver, in that
+    /// order. The later ones only get asked if the earlier don't know.
+    fn choose_speed(
+        wanted: Option<u64>,
+        aggregate: impl FnOnce() -> Option<u64>,
+        sysfs: impl FnOnce() -> Option<u64>,
+        driver: impl FnOnce() -> Option<u64>,
+    ) -> Option<u64> {
+        if let Some(speed) = wanted {
+            info!("Using speed {} Mbps from IF1SEC_MAX_SPEED_MBPS", speed);
+            return Some(speed);
+        }
+        // The speed sysfs has for a bond is the one of a single slave
+        // at best, unreadable at worst, for a bridge it is meaningless
+        if let Some(speed) = aggregate() {
+            info!("Using speed {} Mbps of the member interfaces", speed);
+            return Some(speed);
+        }
+        if let Some(speed) = sysfs() {
+            info!("Using speed {} Mbps from sysfs", speed);
+            return Some(speed);
+        }
+        // If sysfs doesn't know, the driver may still tell us
+        if let Some(speed) = driver() {
+            info!("Using speed {} Mbps from the driver", speed);
+            return Some(speed);
+        }
+        info!("Speed unknown, assuming 1000 Mbps");
+        None
+    }
+
+    /// How many slaves of the bond with the bonding directory `dir`
+    /// are active, their MII status up. The slaves are below `root`.
+    fn bond_active_slaves(root: &Path, dir: &Path) -> Result<u64> {
+        let slaves = std::fs::read_to_string(dir.join("slaves"))?;
+        let active = slaves
+            .split_whitespace()
+            .filter(|slave| {
+                std::fs::read_to_string(root.join(slave).join("bonding_slave/mii_status"))
+                    .is_ok_and(|status| status.trim() == "up")
+            })
+            .count();
+        Ok(active as u64)
+    }
+
+    /// What hardware backs `interface` below `root`, like "Driver
+    /// e1000e, PCI 0000:00:1f.6.", from its device/ directory. None
+    /// for virtual interfaces, they have none.
+    fn hardware_info(root: &Path, interface: &str) -> Option<String> {
+        let device = root.join(interface).join("device");
+        let mut info = Vec::new();
+        if let Ok(driver) = std::fs::read_link(device.join("driver")) {
+            if let Some(name) = driver.file_name() {
+                info.push(format!("Driver {}", name.to_string_lossy()));
+            }
+        }
+        if let Ok(uevent) = std::fs::read_to_string(device.join("uevent")) {
+            let value = |key: &str| {
+                uevent
+                    .lines()
+                    .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            };
+            if let Some(slot) = value("PCI_SLOT_NAME") {
+                info.push(format!("PCI {}", slot));
+            } else if value("DEVTYPE").is_some_and(|devtype| devtype.starts_with("usb")) {
+                // USB has no address in there, the device name is one
+                if let Some(name) = std::fs::canonicalize(&device)
+                    .ok()
+                    .and_then(|path| Some(path.file_name()?.to_string_lossy().to_string()))
+                {
+                    info.push(format!("USB {}", name));
+                }
+            }
+        }
+        (!info.is_empty()).then(|| format!("{}.", info.join(", ")))
+    }
+
+    /// The MAC and IP addresses of `interface` below `root`, like "MAC
+    /// 52:54:00:12:34:56, addresses 192.0.2.7, 2001:db8::7.". None if
+    /// it has none of them.
+    fn address_info(root: &Path, interface: &str) -> Option<String> {
+        let mac = std::fs::read_to_string(root.join(interface).join("address")).ok();
+        #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+        let addresses = getifaddrs::addresses(interface);
+        #[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+        let addresses = Vec::new();
+        InterfacePlugin::describe_addresses(mac.as_deref(), &addresses)
+    }
+
+    /// Put `mac` (as sysfs has it) and (the first few of) `addresses`
+    /// into words for the graph info
+    fn describe_addresses(mac: Option<&str>, addresses: &[IpAddr]) -> Option<String> {
+        /// More addresses make the info line unreadable
+        const MAX_ADDRESSES: usize = 4;
+
+        let mut info = Vec::new();
+        // Loopback and tunnels have none, or only zeros
+        if let Some(mac) = mac
+            .map(str::trim)
+            .filter(|mac| !mac.is_empty() && mac.chars().any(|c| c != '0' && c != ':'))
+        {
+            info.push(format!("MAC {}", mac));
+        }
+        if !addresses.is_empty() {
+            let mut shown: Vec<String> = addresses
+                .iter()
+                .take(MAX_ADDRESSES)
+                .map(IpAddr::to_string)
+                .collect();
+            if addresses.len() > MAX_ADDRESSES {
+                shown.push(format!("{} more", addresses.len() - MAX_ADDRESSES));
+            }
+            let what = if addresses.len() == 1 {
+                "address"
+            } else {
+                "addresses"
+            };
+            info.push(format!("{} {}", what, shown.join(", ")));
+        }
+        let info = info.join(", ");
+        let mut chars = info.chars();
+        let first = chars.next()?;
+        Some(format!("{}{}.", first.to_ascii_uppercase(), chars.as_str()))
+    }
+
+    /// The speed of `interface` below `root` as sysfs has it, if it
+    /// knows
+    fn sysfs_speed(root: &Path, interface: &str) -> Option<u64> {
+        let content = std::fs::read_to_string(root.join(interface).join("speed")).ok()?;
+        InterfacePlugin::parse_speed(&content)
+    }
+
+    /// The sum of the speeds of the slaves of the bond `interface`
+    /// below `root`. Slaves without a known speed (down ones say -1)
+    /// don't count. None if `interface` is no bond, or none of its
+    /// slaves know their speed.
+    fn bond_aggregate_speed(root: &Path, interface: &str) -> Option<u64> {
+        let slaves =
+            std::fs::read_to_string(root.join(interface).join("bonding").join("slaves")).ok()?;
+        let speed: u64 = slaves
+            .split_whitespace()
+            .filter_map(|slave| InterfacePlugin::sysfs_speed(root, slave))
+            .sum();
+        debug!("Bond {} ({}) speed: {}", interface, slaves.trim(), speed);
+        (speed > 0).then_some(speed)
+    }
+
+    /// The speed of the bridge `interface` below `root`, from its
+    /// ports: the fastest one, or with `sum` all of them added up.
+    /// Ports without a known speed don't count. None if `interface`
+    /// is no bridge, or none of its ports know their speed.
+    fn bridge_speed(root: &Path, interface: &str, sum: bool) -> Option<u64> {
+        let bridge = root.join(interface);
+        if !bridge.join("bridge").is_dir() {
+            return None;
+        }
+        let speeds = std::fs::read_dir(bridge.join("brif"))
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                InterfacePlugin::sysfs_speed(root, &entry.file_name().to_string_lossy())
+            });
+        let speed = if sum {
+            speeds.sum()
+        } else {
+            speeds.max().unwrap_or(0)
+        };
+        debug!("Bridge {} speed: {}", interface, speed);
+        (speed > 0).then_some(speed)
+    }
+
+    /// Should the speed of a bridge be the sum of its ports, instead
+    /// of the fastest one? Set in IF1SEC_BRIDGE_SPEED, `max` (the
+    /// default) or `sum`.
+    fn bridge_sum() -> bool {
+        options::parse_or_default("IF1SEC_BRIDGE_SPEED", |value| match value {
+            "max" => Some(false),
+            "sum" => Some(true),
+            _ => None,
+        })
+    }
+
+    /// Parse the speed override from IF1SEC_MAX_SPEED_MBPS, ignoring
+    /// (and complaining about) anything not a positive number.
+    fn parse_speed_override(value: Option<&str>) -> Option<u64> {
+        parse_positive("IF1SEC_MAX_SPEED_MBPS", value)
+    }
+
+    /// Re-read everything that may have changed since we started,
+    /// triggered by SIGHUP. The counter state is kept, so this does
+    /// not disturb DERIVE continuity. Munin asks for the config with
+    /// a fresh process every time, that already sees any change.
+    fn reload(&mut self) {
+        info!("Reloading settings for {}", self.interface);
+        let speed = InterfacePlugin::link_speed(&self.sysfs_root, &self.device);
+        self.speed = speed.unwrap_or(1000);
+        self.speed_known = speed.is_some();
+        self.hide_addr = hide_addr();
+        self.warn_pct = threshold_pct("IF1SEC_WARN_PCT");
+        self.crit_pct = threshold_pct("IF1SEC_CRIT_PCT");
+        self.counter_width = CounterWidth::from_env();
+        self.show_total = show_total();
+        self.units = Units::from_env();
+        self.value_type = ValueType::from_env();
+        self.base = graph_base();
+        self.overrides = GraphOverrides::from_env();
+        self.data_size = data_size_override();
+        self.fields = Fields::from_env();
+        self.follow_rename = follow_rename();
+        self.find_device_files();
+        match InterfacePlugin::open_source(&self.sysfs_root, &self.device) {
+            Ok((source, available)) => {
+                self.source = Some(source);
+                self.available = available;
+            }
+            Err(e) => warn!("Can not reopen counters for {}: {}", self.interface, e),
+        }
+        // Peaks from before the change would only hide those after
+        self.rx_peak = 0.0;
+        self.tx_peak = 0.0;
+    }
+
+    /// Parse the content of the sysfs speed file. The kernel writes
+    /// -1 there if it doesn't know the speed (say, no link), so
+    /// anything not positive means unknown. Some drivers leave it
+    /// empty, or worse, while negotiating, that's unknown as well.
+    fn parse_speed(content: &str) -> Option<u64> {
+        let content = content.trim();
+        match content.parse::<i64>() {
+            Ok(speed) if speed > 0 => Some(speed as u64),
+            Ok(speed) => {
+                debug!("Unknown speed {} in sysfs", speed);
+                None
+            }
+            Err(e) => {
+                debug!("Can not parse speed {:?} in sysfs: {}", content, e);
+                None
+            }
+        }
+    }
+
+    /// The maximum bytes per second for a link `speed` Mbps fast.
+    /// Multiply first, dividing the speed by 8 first would round down
+    /// for speeds not a multiple of 8, like 10 or 100 Mbps. u64 only
+    /// overflows in the exabit range, usize already does for 100G+
+    /// on 32bit.
+    fn max_bytes(speed: u64) -> u64 {
+        speed * 1_000_000 / 8
+    }
+
+    /// The munin threshold for `pct` percent utilization of a link of
+    /// `speed` Mbps. Like the max, in bytes per second, that is what
+    /// munin stores, also when graphing bits.
+    fn threshold(speed: u64, pct: u64) -> u64 {
+        InterfacePlugin::max_bytes(speed) * pct / 100
+    }
+
+    /// The graph_data_size of our graphs, as set, or fitting the
+    /// update rate
+    fn graph_data_size(&self) -> String {
+        self.data_size
+            .clone()
+            .unwrap_or_else(|| data_size(self.update_rate))
+    }
+
+    /// Write the graph level config for one of our multigraphs
+    fn config_graph<W: Write>(
+        &self,
+        handle: &mut BufWriter<W>,
+        category: &str,
+        graph: &str,
+        title: &str,
+        vlabel: &str,
+        info: &str,
+    ) -> Result<()> {
+        writeln!(handle, "multigraph if1sec_{}_{}", graph, self.interface)?;
+        writeln!(
+            handle,
+            "graph_title Interface 1sec {} for {}",
+            title, self.interface
+        )?;
+        writeln!(handle, "graph_category {}", category)?;
+        writeln!(handle, "graph_args --base 1000")?;
+        writeln!(handle, "graph_data_size {}", self.graph_data_size())?;
+        writeln!(handle, "graph_vlabel {}", vlabel)?;
+        writeln!(
+            handle,
+            "graph_info This graph shows the {} of the {} network interface.",
+            info, self.interface
+        )?;
+        writeln!(handle, "update_rate {}", self.update_rate)?;
+        Ok(())
+    }
+
+    /// Write the field config for a rx/tx pair of counters. Received
+    /// goes down (-), transmitted goes up (+), same as in the main
+    /// graph. If only one of them exists, it is graphed on its own.
+    fn config_rxtx<W: Write>(
+        &self,
+        handle: &mut BufWriter<W>,
+        label: &str,
+        rx: bool,
+        tx: bool,
+    ) -> Result<()> {
+        if rx {
+            writeln!(handle, "{0}_rx.label {0} {1}", self.interface, label)?;
+            writeln!(handle, "{}_rx.type DERIVE", self.interface)?;
+            writeln!(handle, "{}_rx.min 0", self.interface)?;
+            if tx {
+                writeln!(handle, "{}_rx.graph no", self.interface)?;
+            }
+        }
+        if tx {
+            writeln!(handle, "{0}_tx.label {0} {1}", self.interface, label)?;
+            writeln!(handle, "{}_tx.type DERIVE", self.interface)?;
+            writeln!(handle, "{}_tx.min 0", self.interface)?;
+            if rx {
+                writeln!(handle, "{0}_tx.negative {0}_rx", self.interface)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write out the values of the `fields` of `graph` that have a
+    /// sysfs file, as read from it. Nothing if none of them has, or
+    /// the graph isn't wanted.
+    fn write_files<W: Write>(
+        &self,
+        handle: &mut BufWriter<W>,
+        graph: &str,
+        epoch: u64,
+        fields: &[(&str, &Option<PathBuf>)],
+    ) -> Result<()> {
+        if !self.fields.has(graph) || fields.iter().all(|(_, path)| path.is_none()) {
+            return Ok(());
+        }
+        writeln!(handle, "multigraph if1sec_{}_{}", graph, self.interface)?;
+        for (field, path) in fields {
+            if let Some(path) = path {
+                match source::read_counter(path) {
+                    Ok(value) => writeln!(
+                        handle,
+                        "{0}_{1}.value {2}:{3}",
+                        self.interface, field, epoch, value
+                    )?,
+                    Err(e) => debug!("Can not read {}: {}", path.display(), e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write out the values of a rx/tx pair of counters for `graph`,
+    /// as configured by [InterfacePlugin::config_rxtx].
+    fn write_rxtx<W: Write>(
+        &self,
+        handle: &mut BufWriter<W>,
+        graph: &str,
+        epoch: u64,
+        rx: Option<u64>,
+        tx: Option<u64>,
+    ) -> Result<()> {
+        writeln!(handle, "multigraph if1sec_{}_{}", graph, self.interface)?;
+        if let Some(value) = tx {
+            writeln!(handle, "{0}_tx.value {1}:{2}", self.interface, epoch, value)?;
+        }
+        if let Some(value) = rx {
+            writeln!(handle, "{0}_rx.value {1}:{2}", self.interface, epoch, value)?;
+        }
+        Ok(())
+    }
+
+    /// Write the field config for a single counter, named `field`
+    fn config_counter<W: Write>(
+        &self,
+        handle: &mut BufWriter<W>,
+        field: &str,
+        label: &str,
+    ) -> Result<()> {
+        writeln!(
+            handle,
+            "{0}_{1}.label {0} {2}",
+            self.interface, field, label
+        )?;
+        writeln!(handle, "{}_{}.type DERIVE", self.interface, field)?;
+        writeln!(handle, "{}_{}.min 0", self.interface, field)?;
+        Ok(())
+    }
+
+    /// Write the field config for a single gauge, named `field`,
+    /// ranging from `min` to `max`
+    fn config_gauge<W: Write>(
+        &self,
+        handle: &mut BufWriter<W>,
+        field: &str,
+        label: &str,
+        min: u64,
+        max: u64,
+    ) -> Result<()> {
+        writeln!(
+            handle,
+            "{0}_{1}.label {0} {2}",
+            self.interface, field, label
+        )?;
+        writeln!(handle, "{}_{}.type GAUGE", self.interface, field)?;
+        writeln!(handle, "{}_{}.min {}", self.interface, field, min)?;
+        writeln!(handle, "{}_{}.max {}", self.interface, field, max)?;
+        Ok(())
+    }
+
+    /// Write out the value of a single field for `graph`, as
+    /// configured by [InterfacePlugin::config_counter] or
+    /// [InterfacePlugin::config_gauge].
+    fn write_counter<W: Write>(
+        &self,
+        handle: &mut BufWriter<W>,
+        graph: &str,
+        field: &str,
+        epoch: u64,
+        value: impl std::fmt::Display,
+    ) -> Result<()> {
+        writeln!(handle, "multigraph if1sec_{}_{}", graph, self.interface)?;
+        writeln!(
+            handle,
+            "{0}_{1}.value {2}:{3}",
+            self.interface, field, epoch, value
+        )?;
+        Ok(())
+    }
+
+    /// The interface from IF1SEC_INTERFACE (`value`), if set. An
+    /// error if it is no valid interface name, if it exists gets
+    /// checked when opening it, see [InterfacePlugin::new].
+    fn interface_override(value: Option<String>) -> Option<Result<String>> {
+        let interface = value?;
+        Some(if valid_interface_name(&interface) {
+            Ok(interface)
+        } else {
+            Err(anyhow!(
+                "IF1SEC_INTERFACE {:?} is no valid interface name",
+                interface
+            ))
+        })
+    }
+
+    /// Extract the interface name from the name we are called
+    /// with. Only the first _ separates our plugin prefix from the
+    /// interface, so if1sec_br_lan -> br_lan. A name without any _
+    /// (or nothing after it) has no interface for us, nor has one
+    /// that isn't UTF-8, or no valid interface name (see
+    /// [valid_interface_name]).
+    pub fn interface_from_name(name: &OsStr) -> Result<String> {
+        // Only look at the filename, directories may contain _ too
+        let base = Path::new(name).file_name().unwrap_or(name);
+        let base = base
+            .to_str()
+            .ok_or_else(|| anyhow!("My name {:?} is not valid UTF-8", name))?;
+        match base.split_once('_') {
+            Some((_, interface)) if valid_interface_name(interface) => Ok(interface.to_string()),
+            Some((_, interface)) if !interface.is_empty() => Err(anyhow!(
+                "{:?} from my name {:?} is no valid interface name",
+                interface,
+                name
+            )),
+            _ => Err(anyhow!(
+                "Can not tell the interface from my name {:?}, symlink me as if1sec_<iface>",
+                name
+            )),
+        }
+    }
+}
+
+/// Could `name` be the name of a network interface? Like the kernel
+/// checks it (dev_valid_name): not empty, shorter than IFNAMSIZ, not
+/// . or .., and without /, : or whitespace. Anything else would end up
+/// in paths and in our munin output.
+fn valid_interface_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() < libc::IFNAMSIZ
+        && name != "."
+        && name != ".."
+        && !name.contains(['/', ':', '\0'])
+        && !name.chars().any(char::is_whitespace)
+}
+
+/// List the interfaces below `root` that have readable rx/tx byte
+/// counters, ignoring the loopback.
+fn usable_interfaces(root: &Path) -> Result<Vec<String>> {
+    let mut interfaces: Vec<String> = std::fs::read_dir(root)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|interface| interface != "lo")
+        .filter(|interface| has_counters(root, interface))
+        .collect();
+    interfaces.sort();
+    Ok(interfaces)
+}
+
+/// Can we read the rx/tx byte counters of `interface` below `root`?
+fn has_counters(root: &Path, interface: &str) -> bool {
+    let stats = root.join(interface).join("statistics");
+    File::open(stats.join("rx_bytes")).is_ok() && File::open(stats.join("tx_bytes")).is_ok()
+}
+
+/// List the interfaces below `root` that are worth monitoring. That
+/// is every usable interface that isn't currently down.
+pub fn suggest_interfaces(root: &Path) -> Result<Vec<String>> {
+    Ok(usable_interfaces(root)?
+        .into_iter()
+        .filter(|interface| {
+            std::fs::read_to_string(root.join(interface).join("operstate"))
+                .map(|state| state.trim() != "down")
+                .unwrap_or(true)
+        })
+        .collect())
+}
+
+/// Write a table of all the interfaces below `root` to `out`, with
+/// their speed, operstate, if their counters are readable and if
+/// suggest lists them. For finding out why suggest and autoconf say
+/// what they say.
+pub fn list_interfaces(out: &mut impl Write, root: &Path) -> Result<()> {
+    let mut interfaces: Vec<String> = std::fs::read_dir(root)
+        .map_err(|e| anyhow!("Can not read {}: {}", root.display(), e))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    interfaces.sort();
+    let suggested = suggest_interfaces(root)?;
+    let yes_no = |yes| if yes { "yes" } else { "no" };
+
+    writeln!(
+        out,
+        "{:<16} {:>12} {:<16} {:<9} suggested",
+        "interface", "speed", "operstate", "counters"
+    )?;
+    for interface in interfaces {
+        let speed = format!(
+            "{} Mbps",
+            InterfacePlugin::link_speed(root, &interface).unwrap_or(1000)
+        );
+        let state = std::fs::read_to_string(root.join(&interface).join("operstate"))
+            .map_or_else(|_| String::from("-"), |state| state.trim().to_string());
+        writeln!(
+            out,
+            "{:<16} {:>12} {:<16} {:<9} {}",
+            interface,
+            speed,
+            state,
+            yes_no(has_counters(root, &interface)),
+            yes_no(suggested.contains(&interface))
+        )?;
+    }
+    Ok(())
+}
+
+/// Answer munins autoconf question for `root`: yes, or no with the
+/// reason why not.
+pub fn autoconf_answer(root: &Path) -> String {
+    if !Path::exists(root) {
+        return format!("no ({} does not exist)", root.display());
+    }
+    match usable_interfaces(root) {
+        Ok(interfaces) if !interfaces.is_empty() => String::from("yes"),
+        Ok(_) => format!("no (no usable interfaces in {})", root.display()),
+        Err(e) => format!("no (can not read {}: {})", root.display(), e),
+    }
+}
+
+/// Check if we can work for the interface we are called for (`name`,
+/// see [InterfacePlugin::get_interface]) below `root`, writing PASS or
+/// FAIL and the why for every step to `out`. Like autoconf, but for
+/// one interface, and more thorough. True if all passed.
+pub fn check(out: &mut impl Write, root: &Path, name: Result<String>) -> Result<bool> {
+    let mut passed = true;
+    let mut result = |out: &mut dyn Write, item: &str, outcome: Result<String>| {
+        let (word, detail) = match outcome {
+            Ok(detail) => ("PASS", detail),
+            Err(e) => {
+                passed = false;
+                ("FAIL", e.to_string())
+            }
+        };
+        writeln!(out, "{} {:<10} {}", word, item, detail)
+    };
+
+    let interface = match name {
+        Ok(interface) => interface,
+        Err(e) => {
+            result(out, "interface", Err(e))?;
+            return Ok(false);
+        }
+    };
+    result(out, "interface", Ok(interface.clone()))?;
+    result(
+        out,
+        "sysfs",
+        std::fs::read_dir(root)
+            .map(|_| root.display().to_string())
+            .map_err(|e| anyhow!("Can not read {}: {}", root.display(), e)),
+    )?;
+    result(
+        out,
+        "counters",
+        if has_counters(root, &interface) {
+            Ok(format!("{}/statistics", root.join(&interface).display()))
+        } else {
+            Err(anyhow!(
+                "Can not read the rx/tx byte counters of {} in {}",
+                interface,
+                root.display()
+            ))
+        },
+    )?;
+    // Everything munin would do, short of the daemon
+    let setup = InterfacePlugin::new(&interface, root).and_then(|iface| {
+        if iface.interface_present {
+            Ok(String::from("Reading the counters works"))
+        } else {
+            Err(anyhow!("Interface {} not there yet", interface))
+        }
+    });
+    result(out, "setup", setup)?;
+    result(
+        out,
+        "speed",
+        InterfacePlugin::link_speed(root, &interface)
+            .map(|speed| format!("{} Mbps", speed))
+            .ok_or_else(|| anyhow!("Unknown, set IF1SEC_MAX_SPEED_MBPS")),
+    )?;
+    Ok(passed)
+}
+
+impl Default for InterfacePlugin {
+    /// Setup the plugin for the interface from our name, in the real
+    /// sysfs. Exits if that doesn't work out.
+    fn default() -> Self {
+        match InterfacePlugin::get_interface() {
+            Ok(interface) => InterfacePlugin::for_interface(interface),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+impl InterfacePlugin {
+    /// Setup the plugin for `interface` below `sysfs_root`. Fails if
+    /// the interface doesn't exist, unless told to wait for it.
+    pub fn new(interface: &str, sysfs_root: &Path) -> Result<Self> {
+        InterfacePlugin::with_state_file(interface, sysfs_root, state::state_file(interface, false))
+    }
+
+    /// [InterfacePlugin::new], keeping the counters over a restart in
+    /// `state_file`, if any
+    fn with_state_file(
+        interface: &str,
+        sysfs_root: &Path,
+        state_file: Option<PathBuf>,
+    ) -> Result<Self> {
+        // Usually a missing interface is fatal, but it may be wanted
+        // to wait for it to appear (USB, VMs, VPNs, ...)
+        let wait = std::env::var("IF1SEC_WAIT_FOR_IFACE").as_deref() == Ok("1");
+        let (source, available) =
+            match InterfacePlugin::open_source_retry(sysfs_root, interface, open_retries()) {
+                Ok((source, available)) => (Some(source), available),
+                Err(e) if wait => {
+                    warn!("{}, waiting for interface {} to appear", e, interface);
+                    (None, Counters::default())
+                }
+                Err(e) => return Err(e),
+            };
+        let speed = InterfacePlugin::link_speed(sysfs_root, interface);
+        let mut plugin = Self {
+            if_link: None,
+            link_state: String::new(),
+            if_mtu: None,
+            if_carrier_changes: None,
+            if_rx_over_errors: None,
+            if_rx_missed_errors: None,
+            if_rx_frame_errors: None,
+            if_rx_crc_errors: None,
+            if_tx_carrier_errors: None,
+            if_rx_compressed: None,
+            if_tx_compressed: None,
+            if_rx_nohandler: None,
+            if_bonding: None,
+            #[cfg(feature = "wireless")]
+            wireless: false,
+            #[cfg(feature = "wireless")]
+            stations: None,
+            #[cfg(target_os = "linux")]
+            nftables: None,
+            if_txqueuelen: None,
+            tx_queues: Vec::new(),
+            if_queues: None,
+            softnet: None,
+            driver_stats: Vec::new(),
+            rings: None,
+            ring_full: None,
+            ethtool: None,
+            interface: interface.to_string(),
+            device: interface.to_string(),
+            ifindex: InterfacePlugin::read_ifindex(sysfs_root, interface),
+            follow_rename: follow_rename(),
+            sysfs_root: sysfs_root.to_path_buf(),
+            counter_width: CounterWidth::from_env(),
+            unwrapper: Unwrapper::default(),
+            last_counters: None,
+            glitched: false,
+            state_file,
+            clock: SampleClock::new(update_rate()),
+            speed: speed.unwrap_or(1000),
+            speed_known: speed.is_some(),
+            hide_addr: hide_addr(),
+            warn_pct: threshold_pct("IF1SEC_WARN_PCT"),
+            crit_pct: threshold_pct("IF1SEC_CRIT_PCT"),
+            update_rate: update_rate(),
+            data_size: data_size_override(),
+            show_total: show_total(),
+            units: Units::from_env(),
+            value_type: ValueType::from_env(),
+            last_rx: None,
+            last_tx: None,
+            rx_peak: 0.0,
+            tx_peak: 0.0,
+            base: graph_base(),
+            overrides: GraphOverrides::from_env(),
+            fields: Fields::from_env(),
+            stop: Arc::new(AtomicBool::new(false)),
+            reload: Arc::new(AtomicBool::new(false)),
+            interface_present: source.is_some(),
+            source,
+            available,
+            retry_at: 0,
+            retry_delay: 1,
+            skipped: 0,
+            notifier: None,
+            debug: None,
+            daemon_started: false,
+        };
+        plugin.find_device_files();
+        if let Some(path) = &plugin.state_file {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_secs());
+            if let Some(state) = State::load(path, now) {
+                debug!("Carrying on with the counters from {}", state.epoch);
+                plugin.unwrapper.set_bytes(state.rx_bytes, state.tx_bytes);
+            }
+        }
+        Ok(plugin)
+    }
+
+    /// Setup the plugin for `interface` in the real sysfs, see
+    /// [InterfacePlugin::new]. Exits if that fails.
+    pub fn for_interface(interface: String) -> Self {
+        InterfacePlugin::new(&interface, &source::sysfs_root()).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(2);
+        })
+    }
+
+    /// Setup the signal handlers: stop on SIGTERM/SIGINT, reload on
+    /// SIGHUP
+    pub fn register_signals(&self) -> Result<()> {
+        register_signals(&self.stop, &self.reload)
+    }
+
+    /// Does our source keep its files open? See
+    /// [StatSource::keeps_open].
+    pub fn keeps_files_open(&self) -> bool {
+        self.source
+            .as_ref()
+            .is_some_and(|source| source.keeps_open())
+    }
+
+    /// We are the acquire daemon, see [StatSource::daemon_running]
+    fn daemon_running(&mut self) {
+        if let Some(source) = self.source.as_mut() {
+            source.daemon_running();
+        }
+    }
+}
+
+impl MuninPlugin for InterfacePlugin {
+    fn config<W: Write>(&self, handle: &mut BufWriter<W>) -> Result<()> {
+        let speed = self.speed;
+        let category = env_line("IF1SEC_CATEGORY")
+            .unwrap_or_else(|| String::from(options::default("IF1SEC_CATEGORY")));
+        let max = InterfacePlugin::max_bytes(speed);
+        // Our own rates are in the units of the graph already
+        let (scale, field_type) = match self.value_type {
+            ValueType::Derive => (1, "DERIVE"),
+            ValueType::Gauge => (self.units.per_byte(), "GAUGE"),
+        };
+        let derive_bits = self.units == Units::Bits && self.value_type == ValueType::Derive;
+
+        // The traffic, what we always had
+        if self.fields.has("bytes") {
+            writeln!(handle, "multigraph if1sec_{}", self.interface)?;
+            writeln!(
+                handle,
+                "graph_title Interface 1sec stats for {}",
+                self.interface
+            )?;
+            writeln!(handle, "graph_category {}", category)?;
+            match &self.overrides.args {
+                Some(args) => writeln!(handle, "graph_args {}", args)?,
+                None => writeln!(handle, "graph_args --base {}", self.base)?,
+            }
+            writeln!(handle, "graph_data_size {}", self.graph_data_size())?;
+            let (units, other) = match self.units {
+                Units::Bits => ("bits", "bytes"),
+                Units::Bytes => ("bytes", "bits"),
+            };
+            match &self.overrides.vlabel {
+                Some(vlabel) => writeln!(handle, "graph_vlabel {}", vlabel)?,
+                None => writeln!(handle, "graph_vlabel {} in (-) / out (+)", units)?,
+            }
+            if let Some(order) = &self.overrides.order {
+                writeln!(handle, "graph_order {}", order)?;
+            }
+            write!(handle, "graph_info This graph shows the traffic of the {} network interface. Please note that the traffic is shown in {} per second, not {}.", self.interface, units, other)?;
+            if let Some(hardware) = InterfacePlugin::hardware_info(&self.sysfs_root, &self.device) {
+                write!(handle, " {}", hardware)?;
+            }
+            if !self.hide_addr {
+                if let Some(addresses) =
+                    InterfacePlugin::address_info(&self.sysfs_root, &self.device)
+                {
+                    write!(handle, " {}", addresses)?;
+                }
+            }
+            writeln!(handle)?;
+            writeln!(handle, "update_rate {}", self.update_rate)?;
+            writeln!(handle, "{0}_rx.label {0} {1}", self.interface, units)?;
+            // Nothing to compute for bytes, that is what we read
+            if derive_bits {
+                writeln!(handle, "{0}_rx.cdef {0}_rx,8,*", self.interface)?;
+            }
+            writeln!(handle, "{}_rx.type {}", self.interface, field_type)?;
+            writeln!(handle, "{}_rx.min 0", self.interface)?;
+            writeln!(handle, "{}_rx.graph no", self.interface)?;
+            writeln!(handle, "{0}_tx.label {0} {1}", self.interface, units)?;
+            if derive_bits {
+                writeln!(handle, "{0}_tx.cdef {0}_tx,8,*", self.interface)?;
+            }
+            writeln!(handle, "{}_tx.type {}", self.interface, field_type)?;
+            writeln!(handle, "{}_tx.min 0", self.interface)?;
+            writeln!(handle, "{0}_tx.negative {0}_rx", self.interface)?;
+            writeln!(handle, "{}_rx.max {}", self.interface, max * scale)?;
+            writeln!(handle, "{}_tx.max {}", self.interface, max * scale)?;
+            // A percentage of a guessed speed would only give false alarms
+            if self.speed_known {
+                for (level, pct) in [("warning", self.warn_pct), ("critical", self.crit_pct)] {
+                    if let Some(pct) = pct {
+                        let limit = InterfacePlugin::threshold(speed, pct) * scale;
+                        writeln!(handle, "{}_rx.{} {}", self.interface, level, limit)?;
+                        writeln!(handle, "{}_tx.{} {}", self.interface, level, limit)?;
+                    }
+                }
+            } else if self.warn_pct.is_some() || self.crit_pct.is_some() {
+                info!(
+                    "Speed of {} unknown, set IF1SEC_MAX_SPEED_MBPS for thresholds",
+                    self.interface
+                );
+            }
+            writeln!(
+                handle,
+                "{0}_rx.info Received traffic on the {0} interface. Maximum speed is {1} Mbps.",
+                self.interface, speed
+            )?;
+            writeln!(
+                handle,
+                "{0}_tx.info Transmitted traffic on the {0} interface. Maximum speed is {1} Mbps.",
+                self.interface, speed
+            )?;
+            // Computed from the others, no extra value to read
+            if self.show_total {
+                writeln!(
+                    handle,
+                    "{0}_total.label {0} total {1}",
+                    self.interface, units
+                )?;
+                writeln!(
+                    handle,
+                    "{0}_total.cdef {0}_rx,{0}_tx,+{1}",
+                    self.interface,
+                    if derive_bits { self.units.cdef() } else { "" }
+                )?;
+                writeln!(
+                    handle,
+                    "{0}_total.info Received and transmitted traffic on the {0} interface.",
+                    self.interface
+                )?;
+            }
+        }
+
+        // The highest rate of a single second, what the averaging of
+        // the coarser munin resolutions smooths away
+        if self.fields.has("peak") {
+            let units = match self.units {
+                Units::Bits => "bits",
+                Units::Bytes => "bytes",
+            };
+            self.config_graph(
+                handle,
+                &category,
+                "peak",
+                "peak traffic",
+                &format!("{} in (-) / out (+)", units),
+                "highest traffic in any second since the start (or reload)",
+            )?;
+            for dir in ["rx", "tx"] {
+                writeln!(
+                    handle,
+                    "{0}_{1}_peak.label {0} peak {2}",
+                    self.interface, dir, units
+                )?;
+                writeln!(handle, "{}_{}_peak.type GAUGE", self.interface, dir)?;
+                writeln!(handle, "{}_{}_peak.min 0", self.interface, dir)?;
+            }
+            writeln!(handle, "{}_rx_peak.graph no", self.interface)?;
+            writeln!(handle, "{0}_tx_peak.negative {0}_rx_peak", self.interface)?;
+        }
+
+        // Second graph, packets per second
+        if self.fields.has("packets") {
+            self.config_graph(
+                handle,
+                &category,
+                "packets",
+                "packets",
+                "packets in (-) / out (+)",
+                "packets per second",
+            )?;
+            self.config_rxtx(handle, "packets", true, true)?;
+        }
+
+        // Average packet size, bytes by packets. Munin can only
+        // compute within one graph, so the counters go in here again.
+        // Packets are always there, see source::Counters.
+        if self.fields.has("pktsize") {
+            self.config_graph(
+                handle,
+                &category,
+                "pktsize",
+                "average packet size",
+                "bytes/packet in (-) / out (+)",
+                "average size of the received and transmitted packets",
+            )?;
+            for field in ["rxbytes", "rxpackets", "txbytes", "txpackets"] {
+                writeln!(handle, "{0}_{1}.label {0} {1}", self.interface, field)?;
+                writeln!(handle, "{}_{}.type DERIVE", self.interface, field)?;
+                writeln!(handle, "{}_{}.min 0", self.interface, field)?;
+                writeln!(handle, "{}_{}.graph no", self.interface, field)?;
+            }
+            for dir in ["rx", "tx"] {
+                writeln!(
+                    handle,
+                    "{0}_{1}.label {0} bytes/packet",
+                    self.interface, dir
+                )?;
+                // Nothing to average without packets
+                writeln!(
+                    handle,
+                    "{0}_{1}.cdef {0}_{1}packets,0,EQ,UNKN,{0}_{1}bytes,{0}_{1}packets,/,IF",
+                    self.interface, dir
+                )?;
+            }
+            writeln!(handle, "{}_rx.graph no", self.interface)?;
+            writeln!(handle, "{0}_tx.negative {0}_rx", self.interface)?;
+        }
+
+        // Errors, if the interface knows about them
+        if self.fields.has("errors")
+            && (self.available.rx_errors.is_some() || self.available.tx_errors.is_some())
+        {
+            self.config_graph(
+                handle,
+                &category,
+                "errors",
+                "errors",
+                "errors in (-) / out (+)",
+                "receive and transmit errors per second",
+            )?;
+            self.config_rxtx(
+                handle,
+                "errors",
+                self.available.rx_errors.is_some(),
+                self.available.tx_errors.is_some(),
+            )?;
+        }
+
+        // Receiver overruns, the host not taking packets from the NIC
+        // fast enough, a CPU or interrupt bottleneck
+        if self.fields.has("overruns")
+            && (self.if_rx_over_errors.is_some() || self.if_rx_missed_errors.is_some())
+        {
+            self.config_graph(
+                handle,
+                &category,
+                "overruns",
+                "receive overruns",
+                "packets per second",
+                "receiver overruns (rx_over_errors) and packets the NIC missed for lack of room (rx_missed_errors) per second",
+            )?;
+            if self.if_rx_over_errors.is_some() {
+                self.config_counter(handle, "over", "overruns")?;
+            }
+            if self.if_rx_missed_errors.is_some() {
+                self.config_counter(handle, "missed", "missed")?;
+            }
+        }
+
+        // Frame and CRC errors, pointing at bad cabling or optics
+        if self.fields.has("linkerrors")
+            && (self.if_rx_frame_errors.is_some() || self.if_rx_crc_errors.is_some())
+        {
+            self.config_graph(
+                handle,
+                &category,
+                "linkerrors",
+                "link errors",
+                "errors per second",
+                "received frames with bad alignment (rx_frame_errors) or checksum (rx_crc_errors) per second",
+            )?;
+            if self.if_rx_frame_errors.is_some() {
+                self.config_counter(handle, "frame", "frame errors")?;
+            }
+            if self.if_rx_crc_errors.is_some() {
+                self.config_counter(handle, "crc", "CRC errors")?;
+            }
+        }
+
+        // Carrier errors when sending, mostly a duplex or
+        // autonegotiation mismatch
+        if self.fields.has("carrier") && self.if_tx_carrier_errors.is_some() {
+            self.config_graph(
+                handle,
+                &category,
+                "carrier",
+                "carrier errors",
+                "errors per second",
+                "transmit carrier errors (tx_carrier_errors) per second",
+            )?;
+            self.config_counter(handle, "carrier", "carrier errors")?;
+        }
+
+        // Received packets no protocol wanted, like a VLAN tag without
+        // its VLAN interface
+        if self.fields.has("nohandler") && self.if_rx_nohandler.is_some() {
+            self.config_graph(
+                handle,
+                &category,
+                "nohandler",
+                "unhandled packets",
+                "packets per second",
+                "received packets dropped as no protocol handler took them (rx_nohandler) per second",
+            )?;
+            self.config_counter(handle, "nohandler", "unhandled packets")?;
+        }
+
+        // Compressed packets, for PPP and some tunnels
+        if self.fields.has("compressed")
+            && (self.if_rx_compressed.is_some() || self.if_tx_compressed.is_some())
+        {
+            self.config_graph(
+                handle,
+                &category,
+                "compressed",
+                "compressed packets",
+                "packets in (-) / out (+)",
+                "compressed packets per second",
+            )?;
+            self.config_rxtx(
+                handle,
+                "compressed",
+                self.if_rx_compressed.is_some(),
+                self.if_tx_compressed.is_some(),
+            )?;
+        }
+
+        // Dropped packets, graphing whichever direction is available
+        if self.fields.has("drops")
+            && (self.available.rx_dropped.is_some() || self.available.tx_dropped.is_some())
+        {
+            self.config_graph(
+                handle,
+                &category,
+                "drops",
+                "dropped packets",
+                "packets in (-) / out (+)",
+                "dropped packets per second",
+            )?;
+            self.config_rxtx(
+                handle,
+                "dropped",
+                self.available.rx_dropped.is_some(),
+                self.available.tx_dropped.is_some(),
+            )?;
+            // A too small ring is a classic reason for drops
+            if let Some(rings) = self.rings {
+                if self.available.rx_dropped.is_some() {
+                    writeln!(
+                        handle,
+                        "{}_rx.info Receive ring of {} descriptors, at most {}.",
+                        self.interface, rings.rx, rings.rx_max
+                    )?;
+                }
+                if self.available.tx_dropped.is_some() {
+                    writeln!(
+                        handle,
+                        "{}_tx.info Transmit ring of {} descriptors, at most {}.",
+                        self.interface, rings.tx, rings.tx_max
+                    )?;
+                }
+            }
+        }
+
+        // Packets dropped before any interface counter sees them, as
+        // the backlog of a CPU was full. The same for every interface,
+        // so one graph for the whole host, named for none of them.
+        if self.fields.has("softnet") && self.softnet.is_some() {
+            writeln!(handle, "multigraph if1sec_softnet")?;
+            writeln!(
+                handle,
+                "graph_title Interface 1sec backlog drops (whole host)"
+            )?;
+            writeln!(handle, "graph_category {}", category)?;
+            writeln!(handle, "graph_args --base 1000")?;
+            writeln!(handle, "graph_data_size {}", self.graph_data_size())?;
+            writeln!(handle, "graph_vlabel packets per second")?;
+            writeln!(
+                handle,
+                "graph_info This graph shows the packets dropped per second as a CPU \
+                 backlog was full (softnet_stat), summed over all CPUs and interfaces."
+            )?;
+            writeln!(handle, "update_rate {}", self.update_rate)?;
+            writeln!(handle, "softnet.label backlog drops")?;
+            writeln!(handle, "softnet.type DERIVE")?;
+            writeln!(handle, "softnet.min 0")?;
+        }
+
+        // Received multicast packets, if the interface counts them
+        if self.fields.has("multicast") && self.available.multicast.is_some() {
+            self.config_graph(
+                handle,
+                &category,
+                "multicast",
+                "multicast",
+                "packets per second",
+                "received multicast packets per second",
+            )?;
+            self.config_counter(handle, "multicast", "multicast packets")?;
+        }
+
+        // Collisions, only interesting on half-duplex links, but
+        // cheap enough to always graph when available
+        if self.fields.has("collisions") && self.available.collisions.is_some() {
+            self.config_graph(
+                handle,
+                &category,
+                "collisions",
+                "collisions",
+                "collisions per second",
+                "collisions per second",
+            )?;
+            self.config_counter(handle, "collisions", "collisions")?;
+        }
+
+        // Link state, to explain gaps and drops in the other graphs
+        if self.fields.has("link") && self.if_link.is_some() {
+            self.config_graph(
+                handle,
+                &category,
+                "link",
+                "link state",
+                "up (1) / down (0)",
+                "link state (1 up, 0 down, 0.5 anything else, like dormant)",
+            )?;
+            self.config_gauge(handle, "link", "link", 0, 1)?;
+        }
+
+        // MTU, changes are rare, but worth seeing when they happen
+        if self.fields.has("mtu") && self.if_mtu.is_some() {
+            self.config_graph(handle, &category, "mtu", "MTU", "bytes", "MTU")?;
+            self.config_gauge(handle, "mtu", "MTU", 0, 65536)?;
+        }
+
+        // Carrier changes, every link flap counts up, a flaky cable
+        // shows up here
+        if self.fields.has("flaps") && self.if_carrier_changes.is_some() {
+            self.config_graph(
+                handle,
+                &category,
+                "flaps",
+                "link flaps",
+                "link flaps per second",
+                "link flaps (carrier changes)",
+            )?;
+            self.config_counter(handle, "flaps", "link flaps")?;
+        }
+
+        // Bonds lose throughput with every slave going away
+        if let Some(dir) = self.if_bonding.as_ref().filter(|_| self.fields.has("bond")) {
+            self.config_graph(
+                handle,
+                &category,
+                "bond",
+                "bond slaves",
+                "slaves",
+                "active slaves of the bond",
+            )?;
+            writeln!(handle, "{0}_active.label {0} active slaves", self.interface)?;
+            writeln!(handle, "{}_active.type GAUGE", self.interface)?;
+            writeln!(handle, "{}_active.min 0", self.interface)?;
+            // Like "active-backup 1", the name is enough
+            if let Some(mode) = std::fs::read_to_string(dir.join("mode"))
+                .ok()
+                .as_deref()
+                .and_then(|mode| mode.split_whitespace().next())
+            {
+                writeln!(
+                    handle,
+                    "{}_active.info Slaves with MII status up, bonding mode {}.",
+                    self.interface, mode
+                )?;
+            }
+        }
+
+        // Radio trouble doesn't show in the throughput alone
+        #[cfg(feature = "wireless")]
+        if self.wireless && self.fields.has("wireless") {
+            self.config_graph(handle, &category, "signal", "signal", "dBm", "signal level")?;
+            writeln!(handle, "{0}_signal.label {0} signal", self.interface)?;
+            writeln!(handle, "{}_signal.type GAUGE", self.interface)?;
+            self.config_graph(
+                handle,
+                &category,
+                "bitrate",
+                "bitrate",
+                "Mbps",
+                "current link bitrate",
+            )?;
+            writeln!(handle, "{0}_bitrate.label {0} bitrate", self.interface)?;
+            writeln!(handle, "{}_bitrate.type GAUGE", self.interface)?;
+            writeln!(handle, "{}_bitrate.min 0", self.interface)?;
+        }
+        // The stations of an access point, whoever is there right
+        // now. Munin asks for the config often enough to pick up new
+        // ones, the values of those gone just stop.
+        #[cfg(feature = "wireless")]
+        if let Some(stations) = self
+            .stations
+            .as_ref()
+            .filter(|_| self.fields.has("stations"))
+        {
+            match stations.dump() {
+                Ok(stations) => {
+                    let units = match self.units {
+                        Units::Bits => "bits",
+                        Units::Bytes => "bytes",
+                    };
+                    self.config_graph(
+                        handle,
+                        &category,
+                        "stations",
+                        "traffic per station",
+                        &format!("{} in (-) / out (+)", units),
+                        "traffic per associated station",
+                    )?;
+                    for station in stations {
+                        let field = station.field();
+                        for dir in ["rx", "tx"] {
+                            writeln!(
+                                handle,
+                                "{0}_{1}_{2}.label {3}",
+                                self.interface,
+                                field,
+                                dir,
+                                station.address()
+                            )?;
+                            if self.units == Units::Bits {
+                                writeln!(
+                                    handle,
+                                    "{0}_{1}_{2}.cdef {0}_{1}_{2},8,*",
+                                    self.interface, field, dir
+                                )?;
+                            }
+                            writeln!(handle, "{}_{}_{}.type DERIVE", self.interface, field, dir)?;
+                            writeln!(handle, "{}_{}_{}.min 0", self.interface, field, dir)?;
+                        }
+                        writeln!(handle, "{}_{}_rx.graph no", self.interface, field)?;
+                        writeln!(
+                            handle,
+                            "{0}_{1}_tx.negative {0}_{1}_rx",
+                            self.interface, field
+                        )?;
+                    }
+                }
+                Err(e) => debug!("Can not get the stations of {}: {}", self.interface, e),
+            }
+        }
+
+        // The traffic split by IP version, as far as the nftables
+        // counters for it are set up
+        #[cfg(target_os = "linux")]
+        if let Some(nftables) = self.nftables.as_ref().filter(|_| self.fields.has("ipver")) {
+            match nftables.traffic() {
+                Ok(traffic) if traffic.is_empty() => {
+                    debug!("No nftables counters for {} set up", self.interface)
+                }
+                Ok(traffic) => {
+                    let units = match self.units {
+                        Units::Bits => "bits",
+                        Units::Bytes => "bytes",
+                    };
+                    self.config_graph(
+                        handle,
+                        &category,
+                        "ipver",
+                        "traffic per IP version",
+                        &format!("{} in (-) / out (+)", units),
+                        "traffic per IP version, as counted by nftables,",
+                    )?;
+                    for (version, label, rx, tx) in traffic.versions() {
+                        for (dir, value) in [("rx", rx), ("tx", tx)] {
+                            if value.is_none() {
+                                continue;
+                            }
+                            writeln!(
+                                handle,
+                                "{0}_{1}_{2}.label {3}",
+                                self.interface, version, dir, label
+                            )?;
+                            if self.units == Units::Bits {
+                                writeln!(
+                                    handle,
+                                    "{0}_{1}_{2}.cdef {0}_{1}_{2},8,*",
+                                    self.interface, version, dir
+                                )?;
+                            }
+                            writeln!(handle, "{}_{}_{}.type DERIVE", self.interface, version, dir)?;
+                            writeln!(handle, "{}_{}_{}.min 0", self.interface, version, dir)?;
+                        }
+                        if rx.is_some() && tx.is_some() {
+                            writeln!(handle, "{}_{}_rx.graph no", self.interface, version)?;
+                            writeln!(
+                                handle,
+                                "{0}_{1}_tx.negative {0}_{1}_rx",
+                                self.interface, version
+                            )?;
+                        }
+                    }
+                }
+                Err(e) => debug!(
+                    "Can not read the nftables counters of {}: {}",
+                    self.interface, e
+                ),
+            }
+        }
+
+        // The transmit queue, its length and how much is waiting in
+        // each of the hardware queues, for qdisc starvation
+        if self.fields.has("txqueuelen") && self.if_txqueuelen.is_some() {
+            self.config_graph(
+                handle,
+                &category,
+                "txqueuelen",
+                "tx queue length",
+                "packets",
+                "transmit queue length",
+            )?;
+            writeln!(
+                handle,
+                "{0}_txqueuelen.label {0} tx_queue_len",
+                self.interface
+            )?;
+            writeln!(handle, "{}_txqueuelen.type GAUGE", self.interface)?;
+            writeln!(handle, "{}_txqueuelen.min 0", self.interface)?;
+        }
+        if self.fields.has("txbacklog") && !self.tx_queues.is_empty() {
+            self.config_graph(
+                handle,
+                &category,
+                "txbacklog",
+                "tx queue backlog",
+                "bytes",
+                "bytes waiting in each transmit queue",
+            )?;
+            for (queue, _) in &self.tx_queues {
+                writeln!(
+                    handle,
+                    "{0}_txq{1}.label {0} queue {1}",
+                    self.interface, queue
+                )?;
+                writeln!(handle, "{}_txq{}.type GAUGE", self.interface, queue)?;
+                writeln!(handle, "{}_txq{}.min 0", self.interface, queue)?;
+            }
+        }
+
+        // The number of queues, changed with ethtool -L, for what
+        // the traffic looked like before and after
+        if self.fields.has("queues") && self.if_queues.is_some() {
+            self.config_graph(
+                handle,
+                &category,
+                "queues",
+                "queues",
+                "queues",
+                "number of receive and transmit queues",
+            )?;
+            for (field, label) in [("rx", "rx queues"), ("tx", "tx queues")] {
+                writeln!(
+                    handle,
+                    "{0}_{1}.label {0} {2}",
+                    self.interface, field, label
+                )?;
+                writeln!(handle, "{}_{}.type GAUGE", self.interface, field)?;
+                writeln!(handle, "{}_{}.min 0", self.interface, field)?;
+            }
+        }
+
+        // What the driver counts on its own, by default the pause
+        // frames (flow control), for congestion upstream
+        if self.fields.has("ethtool") && !self.driver_stats.is_empty() {
+            self.config_graph(
+                handle,
+                &category,
+                "ethtool",
+                "driver statistics",
+                "per second",
+                "driver statistics (see ethtool -S), counters per second, gauges as they are",
+            )?;
+            for stat in &self.driver_stats {
+                if stat.gauge {
+                    writeln!(
+                        handle,
+                        "{0}_{1}.label {0} {2}",
+                        self.interface, stat.field, stat.name
+                    )?;
+                    writeln!(handle, "{}_{}.type GAUGE", self.interface, stat.field)?;
+                    writeln!(handle, "{}_{}.min 0", self.interface, stat.field)?;
+                } else {
+                    self.config_counter(handle, &stat.field, &stat.name)?;
+                }
+            }
+        }
+
+        // Packets the driver dropped as the receive ring was full
+        if let (Some(rings), Some((name, _))) = (
+            self.rings,
+            self.ring_full.filter(|_| self.fields.has("rings")),
+        ) {
+            self.config_graph(
+                handle,
+                &category,
+                "rings",
+                "ring buffer drops",
+                "packets per second",
+                &format!(
+                    "packets dropped for a full receive ring ({} of {} descriptors, see ethtool -g)",
+                    rings.rx, rings.rx_max
+                ),
+            )?;
+            self.config_counter(handle, "ringfull", &format!("ring full ({})", name))?;
+        }
+
+        // Ourself, all the samples we had to leave out
+        if self.fields.has("skipped") {
+            self.config_graph(
+                handle,
+                &category,
+                "skipped",
+                "skipped samples",
+                "samples per second",
+                "samples this plugin could not take",
+            )?;
+            self.config_counter(handle, "skipped", "skipped samples")?;
+        }
+
+        Ok(())
+    }
+
+    /// Hand out the data gathered by the daemon, like the default
+    /// implementation does. Except if there is nothing gathered yet,
+    /// as happens for a dirtyconfig fetch right after the first
+    /// config: then read and hand out one sample directly, instead of
+    /// failing (and getting no data at all).
+    fn fetch<W: Write>(&mut self, handle: &mut BufWriter<W>, config: &Config) -> Result<()> {
+        fetch_cached(handle, config, |handle, epoch| {
+            self.write_values(handle, epoch)
+        })
+    }
+
+    fn acquire<W: Write>(
+        &mut self,
+        handle: &mut BufWriter<W>,
+        _config: &Config,
+        epoch: u64,
+    ) -> Result<()> {
+        if self.reload.swap(false, Ordering::Relaxed) {
+            self.reload();
+        }
+        if !self.daemon_started {
+            self.daemon_started = true;
+            self.debug = debug_socket::start_from_env();
+            self.notifier = Notifier::from_env();
+        }
+        // Also for a source reopened by reload()
+        self.daemon_running();
+        match self.clock.check(Instant::now(), epoch) {
+            Tick::Sample => self.write_values(handle, epoch)?,
+            Tick::Early => {}
+            Tick::Gap => self.skip(),
+        }
+        if let Some(notifier) = self.notifier.as_mut() {
+            notifier.alive(Instant::now());
+        }
+        stop_if_asked(&self.stop, handle)
+    }
+}
+
+/// Hand out the data acquire collected, or, without any, one live
+/// sample from `live`. See InterfacePlugin::fetch().
+fn fetch_cached<W: Write>(
+    handle: &mut BufWriter<W>,
+    config: &Config,
+    live: impl FnOnce(&mut BufWriter<W>, u64) -> Result<()>,
+) -> Result<()> {
+    if config.daemonize && Path::exists(&config.plugin_cache) {
+        // Rename the cache file, to ensure that acquire doesn't add data
+        // between us outputting data and deleting the file
+        let fetchpath = tempfile::NamedTempFile::new_in(&config.plugin_statedir)?;
+        std::fs::rename(&config.plugin_cache, &fetchpath)?;
+        let mut fetchfile = File::open(&fetchpath)?;
+        std::io::copy(&mut fetchfile, handle)?;
+    } else {
+        debug!("No cached data, reading one sample now");
+        let epoch = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        live(handle, epoch)?;
+    }
+    Ok(())
+}
+
+/// Asked to stop? Do so now, between two complete samples, instead of
+/// getting killed in the middle of one.
+fn stop_if_asked<W: Write>(stop: &AtomicBool, handle: &mut BufWriter<W>) -> Result<()> {
+    if stop.load(Ordering::Relaxed) {
+        handle.flush()?;
+        info!("if1sec stopping");
+        std::process::exit(0);
+    }
+    Ok(())
+}
+
+/// Setup the signal handlers, setting `stop` on SIGTERM/SIGINT and
+/// `reload` on SIGHUP.
+fn register_signals(stop: &Arc<AtomicBool>, reload: &Arc<AtomicBool>) -> Result<()> {
+    for signal in [SIGTERM, SIGINT] {
+        signal_hook::flag::register(signal, Arc::clone(stop))?;
+    }
+    signal_hook::flag::register(SIGHUP, Arc::clone(reload))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A source that never has anything to say
+    #[derive(Debug)]
+    struct NullSource;
+    impl StatSource for NullSource {
+        fn read_counters(&mut self) -> Result<Counters> {
+            Ok(Counters::default())
+        }
+    }
+
+    #[test]
+    fn test_interface_from_name() {
+        let parse = |name: &str| InterfacePlugin::interface_from_name(OsStr::new(name));
+        assert_eq!(parse("if1sec_eth0").unwrap(), "eth0");
+        assert_eq!(parse("if1sec_br_lan").unwrap(), "br_lan");
+        assert_eq!(
+            parse("/etc/munin/plugins/if1sec_vlan_100").unwrap(),
+            "vlan_100"
+        );
+        assert_eq!(parse("if1sec___").unwrap(), "__");
+        // Nothing to go by
+        assert!(parse("").is_err());
+        assert!(parse("if1sec").is_err());
+        assert!(parse("/usr/bin/munin-if1sec").is_err());
+        assert!(parse("if1sec_").is_err());
+        assert!(parse("/").is_err());
+        // Nothing an interface could be called
+        for name in [
+            "if1sec_..",
+            "if1sec_.",
+            "if1sec_eth0:1",
+            "if1sec_eth 0",
+            "if1sec_eth0\nlabel injected",
+            "if1sec_eth\0x",
+            "if1sec_averyveryverylongname",
+        ] {
+            assert!(parse(name).is_err(), "{:?}", name);
+        }
+        // Not UTF-8
+        use std::os::unix::ffi::OsStrExt;
+        assert!(
+            InterfacePlugin::interface_from_name(OsStr::from_bytes(b"if1sec_eth\xff")).is_err()
+        );
+    }
+
+    #[test]
+    fn test_interface_override() {
+        assert!(InterfacePlugin::interface_override(None).is_none());
+        assert_eq!(
+            InterfacePlugin::interface_override(Some(String::from("eth0")))
+                .unwrap()
+                .unwrap(),
+            "eth0"
+        );
+        for bad in ["", "eth0/../lo", "eth 0"] {
+            assert!(InterfacePlugin::interface_override(Some(bad.to_string()))
+                .unwrap()
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_speed() {
+        assert_eq!(InterfacePlugin::parse_speed("10000\n"), Some(10000));
+        assert_eq!(InterfacePlugin::parse_speed("-1\n"), None);
+        assert_eq!(InterfacePlugin::parse_speed("0"), None);
+        // While the driver negotiates
+        assert_eq!(InterfacePlugin::parse_speed(""), None);
+        assert_eq!(InterfacePlugin::parse_speed(" \n\t"), None);
+        assert_eq!(InterfacePlugin::parse_speed("foo\n"), None);
+
+        // Unknown, so the default, no error
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("if1sectest0")).unwrap();
+        for junk in ["", "  \n", "foo\n"] {
+            std::fs::write(root.join("if1sectest0/speed"), junk).unwrap();
+            assert_eq!(InterfacePlugin::sysfs_speed(root, "if1sectest0"), None);
+            assert_eq!(InterfacePlugin::link_speed(root, "if1sectest0"), None);
+        }
+    }
+
+    #[test]
+    fn test_speed_override() {
+        assert_eq!(
+            InterfacePlugin::parse_speed_override(Some("25000")),
+            Some(25000)
+        );
+        assert_eq!(InterfacePlugin::parse_speed_override(Some("0")), None);
+        assert_eq!(InterfacePlugin::parse_speed_override(Some("fast")), None);
+        assert_eq!(InterfacePlugin::parse_speed_override(None), None);
+
+        // Wins over sysfs, driver and the default
+        let wanted = InterfacePlugin::parse_speed_override(Some("25000"));
+        assert_eq!(
+            InterfacePlugin::choose_speed(wanted, || None, || Some(10000), || Some(100)),
+            Some(25000)
+        );
+        assert_eq!(
+            InterfacePlugin::choose_speed(wanted, || None, || None, || None),
+            Some(25000)
+        );
+
+        // Without, the usual order
+        assert_eq!(
+            InterfacePlugin::choose_speed(None, || None, || Some(10000), || Some(100)),
+            Some(10000)
+        );
+        assert_eq!(
+            InterfacePlugin::choose_speed(None, || None, || None, || Some(100)),
+            Some(100)
+        );
+        assert_eq!(
+            InterfacePlugin::choose_speed(None, || None, || None, || None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_bond_speed() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("bond0").join("bonding")).unwrap();
+        std::fs::write(root.join("bond0/bonding/slaves"), "eth0 eth1 eth2\n").unwrap();
+        std::fs::write(root.join("bond0/speed"), "10000\n").unwrap();
+        for (slave, speed) in [("eth0", "10000\n"), ("eth1", "10000\n"), ("eth2", "-1\n")] {
+            std::fs::create_dir_all(root.join(slave)).unwrap();
+            std::fs::write(root.join(slave).join("speed"), speed).unwrap();
+        }
+        assert_eq!(
+            InterfacePlugin::bond_aggregate_speed(root, "bond0"),
+            Some(20000)
+        );
+        // No bond
+        assert_eq!(InterfacePlugin::bond_aggregate_speed(root, "eth0"), None);
+        // A bond without a known speed
+        std::fs::write(root.join("bond0/bonding/slaves"), "eth2\n").unwrap();
+        assert_eq!(InterfacePlugin::bond_aggregate_speed(root, "bond0"), None);
+
+        // Wins over sysfs, not over the admin
+        assert_eq!(
+            InterfacePlugin::choose_speed(None, || Some(20000), || Some(10000), || None),
+            Some(20000)
+        );
+        assert_eq!(
+            InterfacePlugin::choose_speed(Some(5000), || Some(20000), || None, || None),
+            Some(5000)
+        );
+    }
+
+    #[test]
+    fn test_bond_slaves() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let bonding = root.join("bond0/bonding");
+        std::fs::create_dir_all(&bonding).unwrap();
+        std::fs::write(bonding.join("slaves"), "eth0 eth1 eth2\n").unwrap();
+        std::fs::write(bonding.join("mode"), "802.3ad 4\n").unwrap();
+        for (slave, status) in [("eth0", "up\n"), ("eth1", "down\n"), ("eth2", "up\n")] {
+            std::fs::create_dir_all(root.join(slave).join("bonding_slave")).unwrap();
+            std::fs::write(root.join(slave).join("bonding_slave/mii_status"), status).unwrap();
+        }
+        assert_eq!(
+            InterfacePlugin::bond_active_slaves(root, &bonding).unwrap(),
+            2
+        );
+
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("bond0");
+        iface.sysfs_root = root.to_path_buf();
+        iface.if_bonding = Some(bonding);
+        let config = config_output(&iface);
+        assert!(config.contains("multigraph if1sec_bond_bond0\n"));
+        assert!(
+            config.contains("bond0_active.info Slaves with MII status up, bonding mode 802.3ad.\n")
+        );
+        assert!(acquire_output(&mut iface, 1)
+            .contains("multigraph if1sec_bond_bond0\nbond0_active.value 1:2\n"));
+    }
+
+    #[test]
+    fn test_bridge_speed() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("br0/bridge")).unwrap();
+        std::fs::create_dir_all(root.join("br0/brif")).unwrap();
+        for (port, speed) in [("eth0", "1000\n"), ("eth1", "10000\n"), ("vnet0", "-1\n")] {
+            std::fs::create_dir_all(root.join(port)).unwrap();
+            std::fs::write(root.join(port).join("speed"), speed).unwrap();
+            std::os::unix::fs::symlink(root.join(port), root.join("br0/brif").join(port)).unwrap();
+        }
+        assert_eq!(
+            InterfacePlugin::bridge_speed(root, "br0", false),
+            Some(10000)
+        );
+        assert_eq!(
+            InterfacePlugin::bridge_speed(root, "br0", true),
+            Some(11000)
+        );
+        // No bridge
+        assert_eq!(InterfacePlugin::bridge_speed(root, "eth0", false), None);
+        assert_eq!(InterfacePlugin::bridge_speed(root, "missing0", false), None);
+    }
+
+    #[test]
+    fn test_defaults() {
+        // Only with nothing set here, as a munin admin has it
+        if std::env::vars().any(|(name, _)| name.starts_with("IF1SEC")) {
+            return;
+        }
+        let default = |name| options::default(name).to_string();
+        assert_eq!(update_rate().to_string(), default("IF1SEC_UPDATE_RATE"));
+        assert_eq!(fetch_size().to_string(), default("IF1SEC_FETCHSIZE"));
+        assert_eq!(open_retries().to_string(), default("IF1SEC_OPEN_RETRIES"));
+        assert_eq!(graph_base().to_string(), default("IF1SEC_BASE"));
+        assert_eq!(Units::from_env(), Units::Bits);
+        assert_eq!(ValueType::from_env(), ValueType::Derive);
+        assert!(!InterfacePlugin::bridge_sum());
+        assert_eq!(Fields::from_env(), Fields::parse("bytes"));
+        let timeout = source::read_timeout().map(|timeout| timeout.as_millis().to_string());
+        assert_eq!(timeout, Some(default("IF1SEC_READ_TIMEOUT_MS")));
+        assert_eq!(CounterWidth::from_env(), CounterWidth::Auto);
+        assert_eq!(
+            source::sysfs_root(),
+            Path::new(&default("IF1SEC_SYSFS_ROOT"))
+        );
+    }
+
+    #[test]
+    fn test_data_size() {
+        assert_eq!(data_size(1), options::default("IF1SEC_DATA_SIZE"));
+        assert_eq!(
+            data_size(1),
+            "custom 1d, 1s for 1d, 5s for 2d, 10s for 7d, 1m for 1t, 5m for 1y"
+        );
+        assert_eq!(data_size(2), "custom 1d, 10s for 7d, 1m for 1t, 5m for 1y");
+        assert_eq!(
+            data_size(5),
+            "custom 1d, 5s for 2d, 10s for 7d, 1m for 1t, 5m for 1y"
+        );
+        assert_eq!(parse_data_size(" huge ").as_deref(), Some("huge"));
+        assert_eq!(
+            parse_data_size("custom 1d, 1m for 1y").as_deref(),
+            Some("custom 1d, 1m for 1y")
+        );
+        assert_eq!(parse_data_size("custom "), None);
+        assert_eq!(parse_data_size("1d"), None);
+        let mut iface = test_plugin(Box::new(NullSource));
+        let graphs = config_output(&iface).matches("graph_data_size ").count();
+        iface.data_size = Some(String::from("normal"));
+        let output = config_output(&iface);
+        assert!(!output.contains("graph_data_size custom"));
+        assert_eq!(output.matches("graph_data_size normal\n").count(), graphs);
+        assert_eq!(parse_positive("TEST", Some("2")), Some(2));
+        assert_eq!(parse_positive("TEST", Some("0")), None);
+        assert_eq!(parse_positive("TEST", Some("-2")), None);
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        assert_eq!(InterfacePlugin::max_bytes(100), 12_500_000);
+        assert_eq!(InterfacePlugin::max_bytes(1000), 125_000_000);
+        assert_eq!(InterfacePlugin::max_bytes(40000), 5_000_000_000);
+        assert_eq!(InterfacePlugin::max_bytes(100000), 12_500_000_000);
+        assert_eq!(InterfacePlugin::max_bytes(400000), 50_000_000_000);
+    }
+
+    /// A source reading the rx bytes from a file, failing when it is
+    /// gone, like a vanished interface
+    #[derive(Debug)]
+    struct FileSource(PathBuf);
+    impl StatSource for FileSource {
+        fn read_counters(&mut self) -> Result<Counters> {
+            Ok(Counters {
+                rx_bytes: source::read_counter(&self.0)?,
+                ..Default::default()
+            })
+        }
+    }
+
+    /// A source handing out scripted counters, one per read, failing
+    /// once it runs out
+    #[derive(Debug)]
+    struct ScriptedSource(std::collections::VecDeque<Result<Counters>>);
+    impl StatSource for ScriptedSource {
+        fn read_counters(&mut self) -> Result<Counters> {
+            self.0
+                .pop_front()
+                .unwrap_or_else(|| Err(anyhow!("Script ran out")))
+        }
+    }
+
+    /// Setup a plugin for a test interface, reading from `source`
+    fn test_plugin(source: Box<dyn StatSource>) -> InterfacePlugin {
+        InterfacePlugin {
+            interface: String::from("if1sectest0"),
+            device: String::from("if1sectest0"),
+            ifindex: None,
+            follow_rename: false,
+            // Nothing of the host may end up in the output
+            sysfs_root: PathBuf::from("/nonexistent"),
+            source: Some(source),
+            available: Counters::default(),
+            counter_width: CounterWidth::Auto,
+            unwrapper: Unwrapper::default(),
+            last_counters: None,
+            glitched: false,
+            state_file: None,
+            clock: SampleClock::default(),
+            speed: 1000,
+            speed_known: true,
+            hide_addr: false,
+            warn_pct: None,
+            crit_pct: None,
+            update_rate: 1,
+            data_size: None,
+            show_total: false,
+            units: Units::Bits,
+            value_type: ValueType::Derive,
+            last_rx: None,
+            last_tx: None,
+            rx_peak: 0.0,
+            tx_peak: 0.0,
+            base: 1000,
+            overrides: GraphOverrides::default(),
+            // Every graph, to see them all in the tests
+            fields: Fields::all(),
+            if_link: None,
+            link_state: String::new(),
+            if_mtu: None,
+            if_carrier_changes: None,
+            if_rx_over_errors: None,
+            if_rx_missed_errors: None,
+            if_rx_frame_errors: None,
+            if_rx_crc_errors: None,
+            if_tx_carrier_errors: None,
+            if_rx_compressed: None,
+            if_tx_compressed: None,
+            if_rx_nohandler: None,
+            if_bonding: None,
+            #[cfg(feature = "wireless")]
+            wireless: false,
+            #[cfg(feature = "wireless")]
+            stations: None,
+            #[cfg(target_os = "linux")]
+            nftables: None,
+            if_txqueuelen: None,
+            tx_queues: Vec::new(),
+            if_queues: None,
+            softnet: None,
+            driver_stats: Vec::new(),
+            rings: None,
+            ring_full: None,
+            ethtool: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            reload: Arc::new(AtomicBool::new(false)),
+            interface_present: true,
+            retry_at: 0,
+            retry_delay: 1,
+            skipped: 0,
+            notifier: None,
+            debug: None,
+            daemon_started: false,
+        }
+    }
+
+    /// Run acquire on `iface` and return what it wrote
+    fn acquire_output(iface: &mut InterfacePlugin, epoch: u64) -> String {
+        let mut handle = BufWriter::new(Vec::new());
+        iface
+            .acquire(&mut handle, &Config::new(String::from("if1sec")), epoch)
+            .unwrap();
+        handle.flush().unwrap();
+        let (recovered_writer, _buffered_data) = handle.into_parts();
+        String::from_utf8(recovered_writer).unwrap()
+    }
+
+    #[test]
+    fn test_interface_vanishes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rx_bytes");
+        std::fs::write(&path, "100\n").unwrap();
+        let mut iface = test_plugin(Box::new(FileSource(path.clone())));
+
+        assert!(acquire_output(&mut iface, 1).contains("if1sectest0_rx.value 1:100\n"));
+
+        // Gone, nothing to say, but no error either
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(acquire_output(&mut iface, 2), "");
+        assert!(!iface.interface_present);
+        assert_eq!(acquire_output(&mut iface, 3), "");
+
+        // And back again
+        std::fs::write(&path, "300\n").unwrap();
+        assert!(acquire_output(&mut iface, 4).contains("if1sectest0_rx.value 4:300\n"));
+        assert!(iface.interface_present);
+    }
+
+    #[test]
+    fn test_interface_reopened() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rx_bytes");
+        std::fs::write(&path, "3000000000\n").unwrap();
+        let mut iface = test_plugin(Box::new(FileSource(path.clone())));
+        iface.sysfs_root = dir.path().join("net");
+        assert!(acquire_output(&mut iface, 1).contains("if1sectest0_rx.value 1:3000000000\n"));
+
+        // Gone, and not there for a fresh source yet
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(acquire_output(&mut iface, 2), "");
+
+        // Back, counting from scratch, which is no 32bit wrap
+        let stats = iface.sysfs_root.join("if1sectest0/statistics");
+        std::fs::create_dir_all(&stats).unwrap();
+        for (counter, value) in [
+            ("rx_bytes", "100\n"),
+            ("tx_bytes", "0\n"),
+            ("rx_packets", "1\n"),
+            ("tx_packets", "0\n"),
+        ] {
+            std::fs::write(stats.join(counter), value).unwrap();
+        }
+        let output = acquire_output(&mut iface, 3);
+        assert!(
+            output.contains("if1sectest0_rx.value 3:3000000000\n"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("if1sectest0_rx_peak.value 3:0\n"),
+            "{}",
+            output
+        );
+        std::fs::write(stats.join("rx_bytes"), "150\n").unwrap();
+        let output = acquire_output(&mut iface, 4);
+        assert!(
+            output.contains("if1sectest0_rx.value 4:3000000050\n"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("if1sectest0_rx_peak.value 4:400\n"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_bad_sample() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rx_bytes");
+        let mut iface = test_plugin(Box::new(FileSource(path.clone())));
+
+        for (epoch, garbage) in ["", "\n", "12ab\n", "\u{0}\u{0}"].iter().enumerate() {
+            std::fs::write(&path, garbage).unwrap();
+            assert_eq!(acquire_output(&mut iface, epoch as u64), "");
+            // Not a vanished interface
+            assert!(iface.interface_present);
+        }
+        std::fs::write(&path, "42\n").unwrap();
+        assert!(acquire_output(&mut iface, 4).contains("if1sectest0_rx.value 4:42\n"));
+    }
+
+    #[test]
+    fn test_scripted_source() {
+        let bytes = |rx_bytes| {
+            Ok(Counters {
+                rx_bytes,
+                tx_bytes: 7,
+                rx_packets: 1,
+                tx_packets: 2,
+                ..Default::default()
+            })
+        };
+        let script = vec![
+            bytes(4_294_967_000),
+            // A 32bit wrap
+            bytes(704),
+            Err(BadSample {
+                raw: String::from("junk"),
+            }
+            .into()),
+            bytes(1704),
+        ];
+        let mut iface = test_plugin(Box::new(ScriptedSource(script.into())));
+
+        assert_eq!(
+            acquire_output(&mut iface, 1),
+            "multigraph if1sec_if1sectest0\n\
+             if1sectest0_tx.value 1:7\n\
+             if1sectest0_rx.value 1:4294967000\n\
+             multigraph if1sec_peak_if1sectest0\n\
+             if1sectest0_tx_peak.value 1:0\n\
+             if1sectest0_rx_peak.value 1:0\n\
+             multigraph if1sec_packets_if1sectest0\n\
+             if1sectest0_tx.value 1:2\n\
+             if1sectest0_rx.value 1:1\n\
+             multigraph if1sec_pktsize_if1sectest0\n\
+             if1sectest0_txbytes.value 1:7\n\
+             if1sectest0_txpackets.value 1:2\n\
+             if1sectest0_rxbytes.value 1:4294967000\n\
+             if1sectest0_rxpackets.value 1:1\n\
+             multigraph if1sec_skipped_if1sectest0\n\
+             if1sectest0_skipped.value 1:0\n"
+        );
+        assert!(acquire_output(&mut iface, 2).contains("if1sectest0_rx.value 2:4294968000\n"));
+        // Bad sample, skipped
+        assert_eq!(acquire_output(&mut iface, 3), "");
+        assert!(iface.interface_present);
+        let output = acquire_output(&mut iface, 4);
+        assert!(output.contains("if1sectest0_rx.value 4:4294969000\n"));
+        assert!(output.contains("if1sectest0_skipped.value 4:1\n"));
+        // Out of script, like a vanished interface
+        assert_eq!(acquire_output(&mut iface, 5), "");
+        assert!(!iface.interface_present);
+    }
+
+    #[test]
+    fn test_same_epoch() {
+        let bytes = |rx_bytes| {
+            Ok(Counters {
+                rx_bytes,
+                ..Default::default()
+            })
+        };
+        let script = vec![bytes(1000), bytes(2000)];
+        let mut iface = test_plugin(Box::new(ScriptedSource(script.into())));
+
+        let output = acquire_output(&mut iface, 7);
+        assert_eq!(output.matches("if1sectest0_rx.value 7:1000\n").count(), 1);
+        // Twice in the same second, munin would reject the second
+        // value, so there is none, and the counters stay unread
+        assert_eq!(acquire_output(&mut iface, 7), "");
+        assert_eq!(iface.skipped, 0);
+        assert!(acquire_output(&mut iface, 8).contains("if1sectest0_rx.value 8:2000\n"));
+    }
+
+    #[test]
+    fn test_counter_glitch() {
+        let bytes = |rx_bytes| {
+            Ok(Counters {
+                rx_bytes,
+                ..Default::default()
+            })
+        };
+        let script = vec![
+            bytes(10_000),
+            // The driver goes back a bit, and recovers
+            bytes(9_900),
+            bytes(10_500),
+            // Down for two samples, that is for real
+            bytes(10_200),
+            bytes(10_100),
+            bytes(10_300),
+        ];
+        let mut iface = test_plugin(Box::new(ScriptedSource(script.into())));
+
+        assert!(acquire_output(&mut iface, 1).contains("if1sectest0_rx.value 1:10000\n"));
+        assert_eq!(acquire_output(&mut iface, 2), "");
+        assert_eq!(iface.skipped, 1);
+        // Counted from before the glitch, not twice
+        assert!(acquire_output(&mut iface, 3).contains("if1sectest0_rx.value 3:10500\n"));
+        assert_eq!(acquire_output(&mut iface, 4), "");
+        let output = acquire_output(&mut iface, 5);
+        assert!(output.contains("if1sectest0_rx.value 5:10500\n"));
+        assert!(output.contains("if1sectest0_skipped.value 5:2\n"));
+        assert!(acquire_output(&mut iface, 6).contains("if1sectest0_rx.value 6:10700\n"));
+    }
+
+    #[test]
+    fn test_prometheus() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rx_bytes");
+        std::fs::write(&path, "12345\n").unwrap();
+        let mut iface = test_plugin(Box::new(FileSource(path)));
+
+        let mut handle = BufWriter::new(Vec::new());
+        iface.write_prometheus(&mut handle).unwrap();
+        handle.flush().unwrap();
+        let (recovered_writer, _buffered_data) = handle.into_parts();
+        let output = String::from_utf8(recovered_writer).unwrap();
+        assert!(output.contains("# TYPE if1sec_rx_bytes_total counter\n"));
+        assert!(output.contains("if1sec_rx_bytes_total{interface=\"if1sectest0\"} 12345\n"));
+        assert!(output.contains("if1sec_tx_bytes_total{interface=\"if1sectest0\"} 0\n"));
+    }
+
+    #[test]
+    fn test_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rx_bytes");
+        std::fs::write(&path, "12345\n").unwrap();
+        let mut iface = test_plugin(Box::new(FileSource(path)));
+
+        let mut handle = BufWriter::new(Vec::new());
+        iface.write_json(&mut handle, 42).unwrap();
+        handle.flush().unwrap();
+        let (recovered_writer, _buffered_data) = handle.into_parts();
+        let output = String::from_utf8(recovered_writer).unwrap();
+        assert_eq!(
+            output,
+            "{\"interface\":\"if1sectest0\",\"epoch\":42,\"rx_bytes\":12345,\"tx_bytes\":0,\"rx_packets\":0,\"tx_packets\":0}\n"
+        );
+    }
+
+    #[test]
+    fn test_fetch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rx_bytes");
+        std::fs::write(&path, "12345\n").unwrap();
+        let mut iface = test_plugin(Box::new(FileSource(path)));
+        let mut config = Config::new_daemon(String::from("if1sectest"));
+        config.plugin_statedir = dir.path().to_path_buf();
+        config.plugin_cache = dir.path().join("cache");
+
+        // Nothing cached yet, as on a dirtyconfig fetch before the
+        // daemon got going
+        let mut handle = BufWriter::new(Vec::new());
+        iface.fetch(&mut handle, &config).unwrap();
+        handle.flush().unwrap();
+        let (recovered_writer, _buffered_data) = handle.into_parts();
+        let output = String::from_utf8(recovered_writer).unwrap();
+        assert!(output.starts_with("multigraph if1sec_if1sectest0\n"));
+        assert!(output.contains("if1sectest0_rx.value "));
+
+        // With cached data, that is handed out, and gone afterwards
+        std::fs::write(&config.plugin_cache, "cached\n").unwrap();
+        let mut handle = BufWriter::new(Vec::new());
+        iface.fetch(&mut handle, &config).unwrap();
+        handle.flush().unwrap();
+        let (recovered_writer, _buffered_data) = handle.into_parts();
+        assert_eq!(String::from_utf8(recovered_writer).unwrap(), "cached\n");
+        assert!(!config.plugin_cache.exists());
+    }
+
+    #[test]
+    fn test_link_state() {
+        assert_eq!(link_value("up"), 1.0);
+        assert_eq!(link_value("down"), 0.0);
+        assert_eq!(link_value("lowerlayerdown"), 0.0);
+        assert_eq!(link_value("dormant"), LINK_OTHER);
+        assert_eq!(link_value("unknown"), LINK_OTHER);
+        // carrier
+        assert_eq!(link_value("1"), 1.0);
+        assert_eq!(link_value("0"), 0.0);
+        assert_eq!(LinkState::of("notpresent"), LinkState::Down);
+        assert_eq!(LinkState::of("testing"), LinkState::Other);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("operstate");
+        std::fs::write(&path, "up\n").unwrap();
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.if_link = Some(path.clone());
+        let output = acquire_output(&mut iface, 1);
+        assert!(output.contains("multigraph if1sec_link_if1sectest0\nif1sectest0_link.value 1:1\n"));
+        std::fs::write(&path, "dormant\n").unwrap();
+        assert!(acquire_output(&mut iface, 2).contains("if1sectest0_link.value 2:0.5\n"));
+        assert_eq!(iface.link_state, "dormant");
+
+        assert_eq!(
+            link_change("eth0", "", "up", 1),
+            (Level::Debug, String::from("Link of eth0 is up"))
+        );
+        assert_eq!(
+            link_change("eth0", "up", "down", 7),
+            (
+                Level::Warn,
+                String::from("Link of eth0 went down at 7 (from up to down)")
+            )
+        );
+        assert_eq!(
+            link_change("eth0", "0", "1", 9),
+            (
+                Level::Info,
+                String::from("Link of eth0 went up at 9 (from 0 to 1)")
+            )
+        );
+        assert_eq!(link_change("eth0", "up", "dormant", 9).0, Level::Warn);
+    }
+
+    #[test]
+    fn test_mtu() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mtu");
+        std::fs::write(&path, "1500\n").unwrap();
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.if_mtu = Some(path.clone());
+        assert!(acquire_output(&mut iface, 1)
+            .contains("multigraph if1sec_mtu_if1sectest0\nif1sectest0_mtu.value 1:1500\n"));
+        std::fs::write(&path, "9000\n").unwrap();
+        assert!(acquire_output(&mut iface, 2).contains("if1sectest0_mtu.value 2:9000\n"));
+    }
+
+    #[test]
+    fn test_carrier_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("carrier_changes");
+        std::fs::write(&path, "3\n").unwrap();
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.if_carrier_changes = Some(path);
+        assert!(acquire_output(&mut iface, 1)
+            .contains("multigraph if1sec_flaps_if1sectest0\nif1sectest0_flaps.value 1:3\n"));
+
+        let mut handle = BufWriter::new(Vec::new());
+        iface.config(&mut handle).unwrap();
+        handle.flush().unwrap();
+        let (recovered_writer, _buffered_data) = handle.into_parts();
+        let output = String::from_utf8(recovered_writer).unwrap();
+        assert!(output.contains("if1sectest0_flaps.label if1sectest0 link flaps\n"));
+        assert!(output.contains("if1sectest0_flaps.type DERIVE\n"));
+    }
+
+    #[test]
+    fn test_config_multigraph() {
+        let iface = test_plugin(Box::new(NullSource));
+
+        let mut handle = BufWriter::new(Vec::new());
+        iface.config(&mut handle).unwrap();
+        handle.flush().unwrap();
+        let (recovered_writer, _buffered_data) = handle.into_parts();
+        let output = String::from_utf8(recovered_writer).unwrap();
+
+        assert!(output.contains("multigraph if1sec_if1sectest0\n"));
+        assert!(output.contains("multigraph if1sec_packets_if1sectest0\n"));
+    }
+
+    #[test]
+    fn test_new() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let stats = root.join("fake0").join("statistics");
+        std::fs::create_dir_all(&stats).unwrap();
+        for counter in [
+            "rx_bytes",
+            "tx_bytes",
+            "rx_packets",
+            "tx_packets",
+            "rx_errors",
+        ] {
+            std::fs::write(stats.join(counter), "5\n").unwrap();
+        }
+        std::fs::write(root.join("fake0/mtu"), "1500\n").unwrap();
+        std::fs::write(root.join("fake0/speed"), "2500\n").unwrap();
+
+        let iface = InterfacePlugin::new("fake0", root).unwrap();
+        assert_eq!(iface.speed, 2500);
+        assert_eq!(iface.available.rx_errors, Some(5));
+        assert_eq!(iface.available.tx_errors, None);
+        assert_eq!(iface.if_mtu, Some(root.join("fake0/mtu")));
+        assert_eq!(iface.if_link, None);
+
+        // Missing, an error, no exit
+        assert!(InterfacePlugin::new("fake1", root).is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let stats = root.join("fake0").join("statistics");
+        std::fs::create_dir_all(&stats).unwrap();
+        for counter in ["rx_bytes", "tx_bytes", "rx_packets", "tx_packets"] {
+            std::fs::write(stats.join(counter), "5\n").unwrap();
+        }
+        std::fs::write(root.join("fake0/speed"), "2500\n").unwrap();
+        let run = |name: Result<String>| {
+            let mut out = Vec::new();
+            let passed = check(&mut out, root, name).unwrap();
+            (passed, String::from_utf8(out).unwrap())
+        };
+
+        let (passed, out) = run(Ok(String::from("fake0")));
+        assert!(passed, "{}", out);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines.iter().all(|line| line.starts_with("PASS ")));
+        assert_eq!(lines[0], "PASS interface  fake0");
+        assert_eq!(lines[4], "PASS speed      2500 Mbps");
+
+        // Without speed, and while at it, without counters
+        std::fs::remove_file(root.join("fake0/speed")).unwrap();
+        std::fs::remove_file(stats.join("tx_bytes")).unwrap();
+        let (passed, out) = run(Ok(String::from("fake0")));
+        assert!(!passed);
+        assert!(out.contains("\nFAIL counters   "), "{}", out);
+        assert!(out.contains("\nFAIL speed      Unknown"), "{}", out);
+
+        let (passed, out) = run(Err(anyhow!("No name")));
+        assert!(!passed);
+        assert_eq!(out, "FAIL interface  No name\n");
+    }
+
+    #[test]
+    fn test_describe_addresses() {
+        let addresses: Vec<IpAddr> = [
+            "192.0.2.7",
+            "2001:db8::7",
+            "fe80::1",
+            "10.0.0.1",
+            "10.0.0.2",
+        ]
+        .iter()
+        .map(|addr| addr.parse().unwrap())
+        .collect();
+        assert_eq!(
+            InterfacePlugin::describe_addresses(Some("52:54:00:12:34:56\n"), &addresses[..2]),
+            Some(String::from(
+                "MAC 52:54:00:12:34:56, addresses 192.0.2.7, 2001:db8::7."
+            ))
+        );
+        assert_eq!(
+            InterfacePlugin::describe_addresses(Some("00:00:00:00:00:00\n"), &addresses[..1]),
+            Some(String::from("Address 192.0.2.7."))
+        );
+        assert_eq!(
+            InterfacePlugin::describe_addresses(None, &addresses),
+            Some(String::from(
+                "Addresses 192.0.2.7, 2001:db8::7, fe80::1, 10.0.0.1, 1 more."
+            ))
+        );
+        assert_eq!(InterfacePlugin::describe_addresses(Some("\n"), &[]), None);
+
+        // Only the MAC from sysfs, the test interface has no addresses
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("if1sectest0")).unwrap();
+        std::fs::write(
+            dir.path().join("if1sectest0/address"),
+            "02:00:00:00:00:01\n",
+        )
+        .unwrap();
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.sysfs_root = dir.path().to_path_buf();
+        assert!(config_output(&iface).contains(
+            "Please note that the traffic is shown in bits per second, not bytes. \
+             MAC 02:00:00:00:00:01.\n"
+        ));
+        iface.hide_addr = true;
+        assert!(!config_output(&iface).contains("MAC"));
+    }
+
+    #[test]
+    fn test_hardware_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("net");
+        let devices = dir.path().join("devices");
+        let drivers = dir.path().join("drivers");
+        std::fs::create_dir_all(drivers.join("e1000e")).unwrap();
+        std::fs::create_dir_all(drivers.join("r8152")).unwrap();
+        for (interface, device, driver, uevent) in [
+            (
+                "eth0",
+                "0000:00:1f.6",
+                "e1000e",
+                "DRIVER=e1000e\nPCI_CLASS=20000\nPCI_SLOT_NAME=0000:00:1f.6\n",
+            ),
+            (
+                "usb0",
+                "2-1:1.0",
+                "r8152",
+                "DEVTYPE=usb_interface\nDRIVER=r8152\nPRODUCT=bda/8153/3101\n",
+            ),
+        ] {
+            let device = devices.join(device);
+            std::fs::create_dir_all(&device).unwrap();
+            std::fs::write(device.join("uevent"), uevent).unwrap();
+            std::os::unix::fs::symlink(drivers.join(driver), device.join("driver")).unwrap();
+            std::fs::create_dir_all(root.join(interface)).unwrap();
+            std::os::unix::fs::symlink(&device, root.join(interface).join("device")).unwrap();
+        }
+        std::fs::create_dir_all(root.join("br0")).unwrap();
+
+        assert_eq!(
+            InterfacePlugin::hardware_info(&root, "eth0").as_deref(),
+            Some("Driver e1000e, PCI 0000:00:1f.6.")
+        );
+        assert_eq!(
+            InterfacePlugin::hardware_info(&root, "usb0").as_deref(),
+            Some("Driver r8152, USB 2-1:1.0.")
+        );
+        assert_eq!(InterfacePlugin::hardware_info(&root, "br0"), None);
+
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("eth0");
+        iface.device = String::from("eth0");
+        iface.sysfs_root = root;
+        // The addresses would be those of the host eth0
+        iface.hide_addr = true;
+        assert!(config_output(&iface).contains(
+            "Please note that the traffic is shown in bits per second, not bytes. Driver e1000e, PCI 0000:00:1f.6.\n"
+        ));
+    }
+
+    #[test]
+    fn test_open_source_retry() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+
+        // No retries, no waiting
+        let start = Instant::now();
+        assert!(InterfacePlugin::open_source_retry(&root, "fake0", 0).is_err());
+        assert!(start.elapsed() < OPEN_RETRY_DELAY);
+
+        // Shows up while we wait
+        let creator = {
+            let stats = root.join("fake0").join("statistics");
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                std::fs::create_dir_all(&stats).unwrap();
+                for counter in ["rx_bytes", "tx_bytes", "rx_packets", "tx_packets"] {
+                    std::fs::write(stats.join(counter), "5\n").unwrap();
+                }
+            })
+        };
+        assert!(InterfacePlugin::open_source_retry(&root, "fake0", 3).is_ok());
+        creator.join().unwrap();
+    }
+
+    #[test]
+    fn test_follow_rename() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        let stats = root.join("fake0").join("statistics");
+        std::fs::create_dir_all(&stats).unwrap();
+        for counter in ["rx_bytes", "tx_bytes", "rx_packets", "tx_packets"] {
+            std::fs::write(stats.join(counter), "5\n").unwrap();
+        }
+        std::fs::write(root.join("fake0").join("ifindex"), "7\n").unwrap();
+        std::fs::create_dir_all(root.join("lo")).unwrap();
+        std::fs::write(root.join("lo").join("ifindex"), "1\n").unwrap();
+        assert_eq!(InterfacePlugin::read_ifindex(&root, "fake0"), Some(7));
+        assert_eq!(InterfacePlugin::read_ifindex(&root, "fake1"), None);
+
+        let gone = || {
+            Box::new(ScriptedSource(
+                [Err(anyhow!("No such device"))].into_iter().collect(),
+            ))
+        };
+        let mut iface = test_plugin(gone());
+        iface.interface = String::from("fake0");
+        iface.device = String::from("fake0");
+        iface.sysfs_root = root.clone();
+        iface.ifindex = Some(7);
+        std::fs::rename(root.join("fake0"), root.join("eth7")).unwrap();
+        assert_eq!(
+            InterfacePlugin::find_ifindex(&root, 7).as_deref(),
+            Some("eth7")
+        );
+
+        // Not asked to, so it just looks gone
+        assert_eq!(acquire_output(&mut iface, 1), "");
+        assert_eq!(iface.device, "fake0");
+
+        iface.follow_rename = true;
+        iface.source = Some(gone());
+        let output = acquire_output(&mut iface, 2);
+        assert_eq!(iface.device, "eth7");
+        // Same graphs as before
+        assert!(output.contains("fake0_rx.value 2:5\n"), "{}", output);
+    }
+
+    /// Run config on `iface` and return what it wrote
+    fn config_output(iface: &InterfacePlugin) -> String {
+        let mut handle = BufWriter::new(Vec::new());
+        iface.config(&mut handle).unwrap();
+        handle.flush().unwrap();
+        let (recovered_writer, _buffered_data) = handle.into_parts();
+        String::from_utf8(recovered_writer).unwrap()
+    }
+
+    #[test]
+    fn test_config_snapshot() {
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("eth0");
+        let output = config_output(&iface);
+
+        assert!(!output.contains("self.interface"));
+        assert_eq!(
+            output,
+            "multigraph if1sec_eth0
+graph_title Interface 1sec stats for eth0
+graph_category network
+graph_args --base 1000
+graph_data_size custom 1d, 1s for 1d, 5s for 2d, 10s for 7d, 1m for 1t, 5m for 1y
+graph_vlabel bits in (-) / out (+)
+graph_info This graph shows the traffic of the eth0 network interface. Please note that the traffic is shown in bits per second, not bytes.
+update_rate 1
+eth0_rx.label eth0 bits
+eth0_rx.cdef eth0_rx,8,*
+eth0_rx.type DERIVE
+eth0_rx.min 0
+eth0_rx.graph no
+eth0_tx.label eth0 bits
+eth0_tx.cdef eth0_tx,8,*
+eth0_tx.type DERIVE
+eth0_tx.min 0
+eth0_tx.negative eth0_rx
+eth0_rx.max 125000000
+eth0_tx.max 125000000
+eth0_rx.info Received traffic on the eth0 interface. Maximum speed is 1000 Mbps.
+eth0_tx.info Transmitted traffic on the eth0 interface. Maximum speed is 1000 Mbps.
+multigraph if1sec_peak_eth0
+graph_title Interface 1sec peak traffic for eth0
+graph_category network
+graph_args --base 1000
+graph_data_size custom 1d, 1s for 1d, 5s for 2d, 10s for 7d, 1m for 1t, 5m for 1y
+graph_vlabel bits in (-) / out (+)
+graph_info This graph shows the highest traffic in any second since the start (or reload) of the eth0 network interface.
+update_rate 1
+eth0_rx_peak.label eth0 peak bits
+eth0_rx_peak.type GAUGE
+eth0_rx_peak.min 0
+eth0_tx_peak.label eth0 peak bits
+eth0_tx_peak.type GAUGE
+eth0_tx_peak.min 0
+eth0_rx_peak.graph no
+eth0_tx_peak.negative eth0_rx_peak
+multigraph if1sec_packets_eth0
+graph_title Interface 1sec packets for eth0
+graph_category network
+graph_args --base 1000
+graph_data_size custom 1d, 1s for 1d, 5s for 2d, 10s for 7d, 1m for 1t, 5m for 1y
+graph_vlabel packets in (-) / out (+)
+graph_info This graph shows the packets per second of the eth0 network interface.
+update_rate 1
+eth0_rx.label eth0 packets
+eth0_rx.type DERIVE
+eth0_rx.min 0
+eth0_rx.graph no
+eth0_tx.label eth0 packets
+eth0_tx.type DERIVE
+eth0_tx.min 0
+eth0_tx.negative eth0_rx
+multigraph if1sec_pktsize_eth0
+graph_title Interface 1sec average packet size for eth0
+graph_category network
+graph_args --base 1000
+graph_data_size custom 1d, 1s for 1d, 5s for 2d, 10s for 7d, 1m for 1t, 5m for 1y
+graph_vlabel bytes/packet in (-) / out (+)
+graph_info This graph shows the average size of the received and transmitted packets of the eth0 network interface.
+update_rate 1
+eth0_rxbytes.label eth0 rxbytes
+eth0_rxbytes.type DERIVE
+eth0_rxbytes.min 0
+eth0_rxbytes.graph no
+eth0_rxpackets.label eth0 rxpackets
+eth0_rxpackets.type DERIVE
+eth0_rxpackets.min 0
+eth0_rxpackets.graph no
+eth0_txbytes.label eth0 txbytes
+eth0_txbytes.type DERIVE
+eth0_txbytes.min 0
+eth0_txbytes.graph no
+eth0_txpackets.label eth0 txpackets
+eth0_txpackets.type DERIVE
+eth0_txpackets.min 0
+eth0_txpackets.graph no
+eth0_rx.label eth0 bytes/packet
+eth0_rx.cdef eth0_rxpackets,0,EQ,UNKN,eth0_rxbytes,eth0_rxpackets,/,IF
+eth0_tx.label eth0 bytes/packet
+eth0_tx.cdef eth0_txpackets,0,EQ,UNKN,eth0_txbytes,eth0_txpackets,/,IF
+eth0_rx.graph no
+eth0_tx.negative eth0_rx
+multigraph if1sec_skipped_eth0
+graph_title Interface 1sec skipped samples for eth0
+graph_category network
+graph_args --base 1000
+graph_data_size custom 1d, 1s for 1d, 5s for 2d, 10s for 7d, 1m for 1t, 5m for 1y
+graph_vlabel samples per second
+graph_info This graph shows the samples this plugin could not take of the eth0 network interface.
+update_rate 1
+eth0_skipped.label eth0 skipped samples
+eth0_skipped.type DERIVE
+eth0_skipped.min 0
+"
+        );
+    }
+
+    /// The traffic graph in bytes and base 1024, the others don't
+    /// change
+    #[test]
+    fn test_config_snapshot_bytes() {
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("eth0");
+        iface.units = Units::Bytes;
+        iface.base = 1024;
+        iface.show_total = true;
+        let output = config_output(&iface);
+        let (traffic, _) = output.split_once("multigraph if1sec_peak_eth0\n").unwrap();
+
+        assert_eq!(
+            traffic,
+            "multigraph if1sec_eth0
+graph_title Interface 1sec stats for eth0
+graph_category network
+graph_args --base 1024
+graph_data_size custom 1d, 1s for 1d, 5s for 2d, 10s for 7d, 1m for 1t, 5m for 1y
+graph_vlabel bytes in (-) / out (+)
+graph_info This graph shows the traffic of the eth0 network interface. Please note that the traffic is shown in bytes per second, not bits.
+update_rate 1
+eth0_rx.label eth0 bytes
+eth0_rx.type DERIVE
+eth0_rx.min 0
+eth0_rx.graph no
+eth0_tx.label eth0 bytes
+eth0_tx.type DERIVE
+eth0_tx.min 0
+eth0_tx.negative eth0_rx
+eth0_rx.max 125000000
+eth0_tx.max 125000000
+eth0_rx.info Received traffic on the eth0 interface. Maximum speed is 1000 Mbps.
+eth0_tx.info Transmitted traffic on the eth0 interface. Maximum speed is 1000 Mbps.
+eth0_total.label eth0 total bytes
+eth0_total.cdef eth0_rx,eth0_tx,+
+eth0_total.info Received and transmitted traffic on the eth0 interface.
+"
+        );
+    }
+
+    /// Our own graph_args, graph_vlabel and graph_order for the
+    /// traffic graph, everything else stays
+    #[test]
+    fn test_config_snapshot_overrides() {
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("eth0");
+        iface.overrides = GraphOverrides {
+            args: Some(String::from("--base 1000 --logarithmic --units=si")),
+            vlabel: Some(String::from("bits per ${graph_period}")),
+            order: Some(String::from("eth0_tx eth0_rx")),
+        };
+        let output = config_output(&iface);
+        let (traffic, _) = output.split_once("update_rate 1\n").unwrap();
+
+        assert_eq!(
+            traffic,
+            "multigraph if1sec_eth0
+graph_title Interface 1sec stats for eth0
+graph_category network
+graph_args --base 1000 --logarithmic --units=si
+graph_data_size custom 1d, 1s for 1d, 5s for 2d, 10s for 7d, 1m for 1t, 5m for 1y
+graph_vlabel bits per ${graph_period}
+graph_order eth0_tx eth0_rx
+graph_info This graph shows the traffic of the eth0 network interface. Please note that the traffic is shown in bits per second, not bytes.
+"
+        );
+        // Only the traffic graph
+        assert_eq!(output.matches("graph_order").count(), 1);
+        assert!(output.contains("graph_vlabel packets in (-) / out (+)\n"));
+    }
+
+    #[test]
+    fn test_thresholds() {
+        assert_eq!(InterfacePlugin::threshold(1000, 100), 125_000_000);
+        assert_eq!(InterfacePlugin::threshold(1000, 80), 100_000_000);
+        assert_eq!(InterfacePlugin::threshold(10000, 95), 1_187_500_000);
+        assert_eq!(InterfacePlugin::threshold(100, 1), 125_000);
+        assert_eq!(InterfacePlugin::threshold(400_000, 90), 45_000_000_000);
+
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("eth0");
+        iface.speed = 10000;
+        iface.warn_pct = Some(80);
+        iface.crit_pct = Some(95);
+        let output = config_output(&iface);
+        assert!(output.contains(
+            "eth0_rx.max 1250000000\n\
+             eth0_tx.max 1250000000\n\
+             eth0_rx.warning 1000000000\n\
+             eth0_tx.warning 1000000000\n\
+             eth0_rx.critical 1187500000\n\
+             eth0_tx.critical 1187500000\n"
+        ));
+        iface.crit_pct = None;
+        assert!(!config_output(&iface).contains(".critical"));
+        // Not for a speed we only assume
+        iface.speed_known = false;
+        assert!(!config_output(&iface).contains(".warning"));
+    }
+
+    #[test]
+    fn test_gauge() {
+        assert_eq!(rate(None, 2, 1000, 8), None);
+        assert_eq!(rate(Some((1, 1000)), 2, 126_000, 8), Some(1_000_000.0));
+        assert_eq!(rate(Some((10, 1000)), 15, 3500, 1), Some(500.0));
+        assert_eq!(rate(Some((1, 1000)), 3, 1001, 1), Some(0.5));
+        // Same second again, or backwards
+        assert_eq!(rate(Some((2, 1000)), 2, 2000, 8), None);
+        assert_eq!(rate(Some((1, 1000)), 2, 500, 8), None);
+
+        let bytes = |rx_bytes, tx_bytes| {
+            Ok(Counters {
+                rx_bytes,
+                tx_bytes,
+                ..Default::default()
+            })
+        };
+        let script = vec![bytes(1000, 500), bytes(126_000, 500), bytes(251_000, 1500)];
+        let mut iface = test_plugin(Box::new(ScriptedSource(script.into())));
+        iface.value_type = ValueType::Gauge;
+        iface.warn_pct = Some(80);
+        let output = config_output(&iface);
+        assert!(output.contains("if1sectest0_rx.type GAUGE\n"));
+        assert!(!output.contains(",8,*"));
+        // In bits, what we hand out
+        assert!(output.contains("if1sectest0_rx.max 1000000000\n"));
+        assert!(output.contains("if1sectest0_rx.warning 800000000\n"));
+
+        // Nothing to compare the first sample with
+        assert!(!acquire_output(&mut iface, 1).contains("multigraph if1sec_if1sectest0\n"));
+        assert!(acquire_output(&mut iface, 2).contains(
+            "multigraph if1sec_if1sectest0\n\
+             if1sectest0_tx.value 2:0\n\
+             if1sectest0_rx.value 2:1000000\n"
+        ));
+        iface.units = Units::Bytes;
+        assert!(acquire_output(&mut iface, 4).contains(
+            "if1sectest0_tx.value 4:500\n\
+             if1sectest0_rx.value 4:62500\n"
+        ));
+    }
+
+    #[test]
+    fn test_peak() {
+        let bytes = |rx_bytes| {
+            Ok(Counters {
+                rx_bytes,
+                ..Default::default()
+            })
+        };
+        let script = [0, 1000, 3000, 3500, 10_000, 10_100, 10_200]
+            .into_iter()
+            .map(bytes)
+            .collect();
+        let mut iface = test_plugin(Box::new(ScriptedSource(script)));
+        assert!(config_output(&iface).contains(
+            "if1sectest0_tx_peak.type GAUGE\n\
+             if1sectest0_tx_peak.min 0\n\
+             if1sectest0_rx_peak.graph no\n\
+             if1sectest0_tx_peak.negative if1sectest0_rx_peak\n"
+        ));
+
+        let peak = |output: String| -> f64 {
+            let line = output
+                .lines()
+                .find(|line| line.starts_with("if1sectest0_rx_peak.value"))
+                .unwrap()
+                .to_string();
+            line.rsplit(':').next().unwrap().parse().unwrap()
+        };
+        let mut peaks = Vec::new();
+        for epoch in 1..=5 {
+            peaks.push(peak(acquire_output(&mut iface, epoch)));
+        }
+        // In bits, never going down, nothing to compare the first with
+        assert_eq!(peaks, vec![0.0, 8000.0, 16000.0, 16000.0, 52000.0]);
+        assert!(peaks.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        // A reload starts over
+        iface.reload();
+        iface.fields = Fields::all();
+        assert_eq!(peak(acquire_output(&mut iface, 6)), 800.0);
+        assert_eq!(peak(acquire_output(&mut iface, 7)), 800.0);
+    }
+
+    #[test]
+    fn test_fields() {
+        let fields = |names: &[&'static str]| Fields(names.iter().copied().collect());
+        assert_eq!(Fields::default(), fields(&["bytes"]));
+        assert_eq!(
+            Fields::parse("bytes, packets,,drops,nonsense"),
+            fields(&["bytes", "drops", "packets"])
+        );
+        assert_eq!(Fields::parse("errors,all"), Fields::all());
+        assert!(Fields::all().has("skipped"));
+        // The same for every interface, only if asked for
+        assert!(!Fields::all().has("softnet"));
+        assert!(Fields::parse("all,softnet").has("softnet"));
+        assert!(Fields::parse("softnet,all").has("skipped"));
+        assert!(!Fields::parse("").has("bytes"));
+
+        // Only what is asked for, in config and fetch
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("eth0");
+        iface.available.rx_errors = Some(0);
+        iface.fields = Fields::default();
+        let output = config_output(&iface);
+        assert_eq!(output.matches("multigraph").count(), 1);
+        assert!(output.starts_with("multigraph if1sec_eth0\n"));
+        let output = acquire_output(&mut iface, 1);
+        assert_eq!(output.matches("multigraph").count(), 1);
+        assert!(output.starts_with("multigraph if1sec_eth0\n"));
+
+        iface.fields = fields(&["errors", "skipped"]);
+        let output = config_output(&iface);
+        assert!(output.starts_with("multigraph if1sec_errors_eth0\n"));
+        assert!(output.contains("multigraph if1sec_skipped_eth0\n"));
+        assert_eq!(output.matches("multigraph").count(), 2);
+    }
+
+    #[test]
+    fn test_config_total() {
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("eth0");
+        assert!(!config_output(&iface).contains("eth0_total"));
+        iface.show_total = true;
+        let output = config_output(&iface);
+        assert!(output.contains(
+            "eth0_tx.info Transmitted traffic on the eth0 interface. Maximum speed is 1000 Mbps.\n\
+             eth0_total.label eth0 total bits\n\
+             eth0_total.cdef eth0_rx,eth0_tx,+,8,*\n"
+        ));
+        // Nothing extra to fetch
+        assert!(!acquire_output(&mut iface, 1).contains("eth0_total"));
+    }
+
+    #[test]
+    fn test_config_ethtool() {
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("eth0");
+        assert!(!config_output(&iface).contains("if1sec_ethtool_eth0"));
+        let has: Vec<String> = ["rx_packets", "rx_pause", "tx_pause", "rx_queue_0_fill"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        iface.driver_stats = ethtool::pick(Some("rx_pause,rx_queue_0_fill:gauge"), &has);
+        let output = config_output(&iface);
+        assert!(output.contains(
+            "multigraph if1sec_ethtool_eth0\n\
+             graph_title Interface 1sec driver statistics for eth0\n"
+        ));
+        assert!(output.contains(
+            "eth0_rx_pause.label eth0 rx_pause\n\
+             eth0_rx_pause.type DERIVE\n\
+             eth0_rx_pause.min 0\n\
+             eth0_rx_queue_0_fill.label eth0 rx_queue_0_fill\n\
+             eth0_rx_queue_0_fill.type GAUGE\n\
+             eth0_rx_queue_0_fill.min 0\n"
+        ));
+        // Before the skipped samples, which stay last
+        assert!(output.find("if1sec_ethtool_eth0") < output.find("if1sec_skipped_eth0"));
+    }
+
+    #[test]
+    fn test_overruns() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("eth0");
+        assert!(!config_output(&iface).contains("if1sec_overruns_eth0"));
+        assert!(!acquire_output(&mut iface, 1).contains("if1sec_overruns_eth0"));
+
+        let path = dir.path().join("rx_missed_errors");
+        std::fs::write(&path, "42\n").unwrap();
+        iface.if_rx_missed_errors = Some(path);
+        let output = config_output(&iface);
+        assert!(output.contains(
+            "eth0_missed.label eth0 missed\n\
+             eth0_missed.type DERIVE\n\
+             eth0_missed.min 0\n"
+        ));
+        assert!(!output.contains("eth0_over."));
+        assert!(acquire_output(&mut iface, 2).contains(
+            "multigraph if1sec_overruns_eth0\n\
+             eth0_missed.value 2:42\n"
+        ));
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("eth0");
+        assert!(!config_output(&iface).contains("if1sec_linkerrors_eth0"));
+        assert!(!acquire_output(&mut iface, 1).contains("if1sec_linkerrors_eth0"));
+
+        for (name, value) in [("rx_frame_errors", "3\n"), ("rx_crc_errors", "7\n")] {
+            std::fs::write(dir.path().join(name), value).unwrap();
+        }
+        iface.if_rx_frame_errors = Some(dir.path().join("rx_frame_errors"));
+        iface.if_rx_crc_errors = Some(dir.path().join("rx_crc_errors"));
+        let output = config_output(&iface);
+        assert!(output.contains(
+            "multigraph if1sec_linkerrors_eth0\n\
+             graph_title Interface 1sec link errors for eth0\n"
+        ));
+        assert!(output.contains(
+            "eth0_frame.label eth0 frame errors\n\
+             eth0_frame.type DERIVE\n\
+             eth0_frame.min 0\n\
+             eth0_crc.label eth0 CRC errors\n"
+        ));
+        // A file going away, say with the driver, leaves the other
+        std::fs::remove_file(dir.path().join("rx_frame_errors")).unwrap();
+        assert!(acquire_output(&mut iface, 2).contains(
+            "multigraph if1sec_linkerrors_eth0\n\
+             eth0_crc.value 2:7\n"
+        ));
+    }
+
+    #[test]
+    fn test_carrier_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("eth0");
+        assert!(!config_output(&iface).contains("if1sec_carrier_eth0"));
+
+        let path = dir.path().join("tx_carrier_errors");
+        std::fs::write(&path, "12\n").unwrap();
+        iface.if_tx_carrier_errors = Some(path);
+        assert!(config_output(&iface).contains(
+            "update_rate 1\n\
+             eth0_carrier.label eth0 carrier errors\n\
+             eth0_carrier.type DERIVE\n\
+             eth0_carrier.min 0\n"
+        ));
+        assert!(acquire_output(&mut iface, 3).contains(
+            "multigraph if1sec_carrier_eth0\n\
+             eth0_carrier.value 3:12\n"
+        ));
+    }
+
+    #[test]
+    fn test_nohandler() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("eth0");
+        assert!(!config_output(&iface).contains("if1sec_nohandler_eth0"));
+
+        let path = dir.path().join("rx_nohandler");
+        std::fs::write(&path, "9\n").unwrap();
+        iface.if_rx_nohandler = Some(path);
+        assert!(config_output(&iface).contains(
+            "eth0_nohandler.label eth0 unhandled packets\n\
+             eth0_nohandler.type DERIVE\n\
+             eth0_nohandler.min 0\n"
+        ));
+        assert!(acquire_output(&mut iface, 5).contains(
+            "multigraph if1sec_nohandler_eth0\n\
+             eth0_nohandler.value 5:9\n"
+        ));
+    }
+
+    #[test]
+    fn test_softnet() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("eth0");
+        assert!(!config_output(&iface).contains("if1sec_softnet"));
+
+        let path = dir.path().join("softnet_stat");
+        std::fs::write(
+            &path,
+            "00000001 00000010 00000000\n00000002 0000000a 00000000\n",
+        )
+        .unwrap();
+        iface.softnet = Some(path);
+        // Not with all, every plugin would have it
+        assert!(!config_output(&iface).contains("if1sec_softnet"));
+        assert!(!acquire_output(&mut iface, 4).contains("if1sec_softnet"));
+
+        iface.fields = Fields::parse("all,softnet");
+        let config = config_output(&iface);
+        // Not about eth0 at all
+        assert!(config.contains(
+            "multigraph if1sec_softnet\n\
+             graph_title Interface 1sec backlog drops (whole host)\n"
+        ));
+        assert!(config.contains(
+            "update_rate 1\n\
+             softnet.label backlog drops\n\
+             softnet.type DERIVE\n\
+             softnet.min 0\n"
+        ));
+        assert!(acquire_output(&mut iface, 5).contains(
+            "multigraph if1sec_softnet\n\
+             softnet.value 5:26\n"
+        ));
+        iface.fields = Fields::parse("bytes");
+        assert!(!config_output(&iface).contains("if1sec_softnet"));
+        assert!(!acquire_output(&mut iface, 6).contains("if1sec_softnet"));
+    }
+
+    #[test]
+    fn test_compressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let stats = dir.path().join("ppp0/statistics");
+        std::fs::create_dir_all(&stats).unwrap();
+        std::fs::write(stats.join("rx_compressed"), "0\n").unwrap();
+        std::fs::write(stats.join("tx_compressed"), "0\n").unwrap();
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("ppp0");
+        iface.device = String::from("ppp0");
+        iface.sysfs_root = dir.path().to_path_buf();
+        // Not compressing, nothing to see
+        iface.find_device_files();
+        assert_eq!(iface.if_rx_compressed, None);
+        assert!(!config_output(&iface).contains("if1sec_compressed_ppp0"));
+
+        std::fs::write(stats.join("tx_compressed"), "5\n").unwrap();
+        iface.find_device_files();
+        assert_eq!(iface.if_rx_compressed, Some(stats.join("rx_compressed")));
+        assert!(config_output(&iface).contains(
+            "ppp0_rx.label ppp0 compressed\n\
+             ppp0_rx.type DERIVE\n\
+             ppp0_rx.min 0\n\
+             ppp0_rx.graph no\n\
+             ppp0_tx.label ppp0 compressed\n"
+        ));
+        assert!(acquire_output(&mut iface, 4).contains(
+            "multigraph if1sec_compressed_ppp0\n\
+             ppp0_tx.value 4:5\n\
+             ppp0_rx.value 4:0\n"
+        ));
+    }
+
+    #[test]
+    fn test_queues() {
+        let dir = tempfile::tempdir().unwrap();
+        let queues = dir.path().join("eth0/queues");
+        for queue in ["rx-0", "rx-1", "tx-0", "tx-1"] {
+            std::fs::create_dir_all(queues.join(queue)).unwrap();
+        }
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("eth0");
+        assert!(!config_output(&iface).contains("if1sec_queues_eth0"));
+
+        iface.if_queues = Some(queues.clone());
+        assert!(config_output(&iface).contains(
+            "eth0_rx.label eth0 rx queues\n\
+             eth0_rx.type GAUGE\n\
+             eth0_rx.min 0\n\
+             eth0_tx.label eth0 tx queues\n"
+        ));
+        assert!(acquire_output(&mut iface, 1).contains(
+            "multigraph if1sec_queues_eth0\n\
+             eth0_tx.value 1:2\n\
+             eth0_rx.value 1:2\n"
+        ));
+        // ethtool -L eth0 combined 1
+        std::fs::remove_dir(queues.join("rx-1")).unwrap();
+        std::fs::remove_dir(queues.join("tx-1")).unwrap();
+        assert!(acquire_output(&mut iface, 2).contains(
+            "multigraph if1sec_queues_eth0\n\
+             eth0_tx.value 2:1\n\
+             eth0_rx.value 2:1\n"
+        ));
+    }
+
+    #[test]
+    fn test_config_rings() {
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("eth0");
+        iface.available.rx_dropped = Some(0);
+        iface.rings = Some(Rings {
+            rx: 256,
+            rx_max: 4096,
+            tx: 512,
+            tx_max: 4096,
+        });
+        let output = config_output(&iface);
+        assert!(output.contains("eth0_rx.info Receive ring of 256 descriptors, at most 4096.\n"));
+        // Nothing sent dropped, nothing about its ring
+        assert!(!output.contains("eth0_tx.info Transmit ring"));
+        // No counter for a full ring, no graph
+        assert!(!output.contains("if1sec_rings_eth0"));
+
+        iface.ring_full = Some(("rx_no_buffer_count", 3));
+        let output = config_output(&iface);
+        assert!(output.contains(
+            "graph_info This graph shows the packets dropped for a full receive ring \
+             (256 of 4096 descriptors, see ethtool -g) of the eth0 network interface.\n\
+             update_rate 1\n\
+             eth0_ringfull.label eth0 ring full (rx_no_buffer_count)\n\
+             eth0_ringfull.type DERIVE\n"
+        ));
+    }
+
+    /// The full config of a 10G interface with most of the optional
+    /// graphs, against the golden tests/data/config_eth0_full.txt
+    #[test]
+    fn test_config_snapshot_full() {
+        let mut iface = test_plugin(Box::new(NullSource));
+        iface.interface = String::from("eth0");
+        iface.speed = 10000;
+        iface.available = Counters {
+            rx_errors: Some(0),
+            tx_errors: Some(0),
+            rx_dropped: Some(0),
+            multicast: Some(0),
+            ..Default::default()
+        };
+        iface.if_link = Some(PathBuf::from("/sys/class/net/eth0/operstate"));
+        iface.if_mtu = Some(PathBuf::from("/sys/class/net/eth0/mtu"));
+        iface.if_carrier_changes = Some(PathBuf::from("/sys/class/net/eth0/carrier_changes"));
+        let output = config_output(&iface);
+
+        // Everything munin depends on, field names, DERIVE with
+        // negative stacking, graph_data_size for the 1s resolution
+        assert_eq!(output, include_str!("../tests/data/config_eth0_full.txt"));
+    }
+}
@@ -0,0 +1,142 @@
+//! Log as JSON, one object per line
+//!
+//! For shipping our logs off to Loki, ELK and friends, which would
+//! otherwise have to pick apart the human readable lines. Every line
+//! carries the time, level, interface and message.
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use serde::Serialize;
+use std::{
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// One log line
+#[derive(Debug, Serialize)]
+struct Line<'a> {
+    /// When, RFC 3339 in UTC
+    timestamp: String,
+    /// The level, like INFO
+    level: &'a str,
+    /// Which interface(s) this plugin is for
+    interface: &'a str,
+    /// The module logging
+    target: &'a str,
+    /// What got logged
+    message: String,
+}
+
+/// Format `millis` since the epoch as RFC 3339 timestamp in UTC
+fn rfc3339(millis: u64) -> String {
+    let secs = millis / 1000;
+    let days = secs / 86400;
+    let time = secs % 86400;
+    // Days to the civil date, Howard Hinnant's civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        millis % 1000
+    )
+}
+
+/// A [Log] writing JSON lines to stderr. munin reads our stdout.
+#[derive(Debug)]
+pub struct JsonLogger {
+    /// The interface(s) we are for
+    interface: String,
+
+    /// Log up to this level
+    level: LevelFilter,
+}
+
+impl JsonLogger {
+    /// Log for `interface`, up to `level`
+    pub fn new(interface: &str, level: LevelFilter) -> Self {
+        JsonLogger {
+            interface: interface.to_string(),
+            level,
+        }
+    }
+
+    /// Make this the logger
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        log::set_max_level(self.level);
+        log::set_logger(Box::leak(Box::new(self)))
+    }
+
+    /// The JSON line for `record`, logged at `millis` since the epoch
+    fn format(&self, record: &Record, millis: u64) -> String {
+        let line = Line {
+            timestamp: rfc3339(millis),
+            level: record.level().as_str(),
+            interface: &self.interface,
+            target: record.target(),
+            message: record.args().to_string(),
+        };
+        // Nothing in there can fail to serialize
+        serde_json::to_string(&line).unwrap_or_default()
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_millis() as u64);
+        // Nowhere to complain to if stderr is gone
+        let _ = writeln!(std::io::stderr().lock(), "{}", self.format(record, millis));
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(rfc3339(951_782_400_000), "2000-02-29T00:00:00.000Z");
+        assert_eq!(rfc3339(1_792_060_496_789), "2026-10-15T10:34:56.789Z");
+    }
+
+    #[test]
+    fn test_format() {
+        let logger = JsonLogger::new("eth0", LevelFilter::Info);
+        let record = Record::builder()
+            .args(format_args!("Link of eth0 is now \"up\""))
+            .level(log::Level::Info)
+            .target("munin_if1sec")
+            .build();
+        assert_eq!(
+            logger.format(&record, 0),
+            "{\"timestamp\":\"1970-01-01T00:00:00.000Z\",\"level\":\"INFO\",\"interface\":\"eth0\",\"target\":\"munin_if1sec\",\"message\":\"Link of eth0 is now \\\"up\\\"\"}"
+        );
+        assert!(!logger.enabled(&Metadata::builder().level(log::Level::Debug).build()));
+    }
+}
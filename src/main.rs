@@ -2,169 +2,322 @@
 //!
 //! Use as munin plugin, it expects to be symlinked per interface. So
 //! a symlink if1sec_eth0 to this plugin will collect data for the eth0
-//! interface.
+//! interface. See the library for all the details.
 // SPDX-License-Identifier:  GPL-3.0-only
 
 #![warn(missing_docs)]
 
 use anyhow::Result;
-use log::{debug, error, info, warn};
+use log::{debug, error, info, warn, LevelFilter};
+use munin_if1sec::{
+    autoconf_answer, check, fetch_size, install, list_interfaces,
+    logging::JsonLogger,
+    multi::{MultiPlugin, Selection},
+    netns, notify, options, privs, source, suggest_interfaces, update_rate, InterfacePlugin,
+};
 use munin_plugin::{Config, MuninPlugin};
 use simple_logger::SimpleLogger;
 use std::{
+    ffi::{OsStr, OsString},
     io::{BufWriter, Write},
-    path::{Path, PathBuf},
+    os::unix::process::CommandExt,
+    path::PathBuf,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-/// The struct for our plugin, so we can easily store some values over
-/// the lifetime of our plugin.
-struct InterfacePlugin {
-    /// For which interface we should gather data
-    interface: String,
+/// What --help tells, before the environment variables, see
+/// [options::write_help]
+const USAGE: &str = "\
+Usage: if1sec_<interface> [command]
+       if1sec --interface <interface> [command]
 
-    /// Where to get TXBytes from
-    if_txbytes: PathBuf,
+Collects the data of a network interface every second, for munin.
+Symlink it per interface, if1sec_eth0 collects data for eth0. As
+if1sec_all, data for all interfaces gets collected in one process.
+Without a symlink, --interface (or IF1SEC_INTERFACE) names it.
 
-    /// Where to get RXBytes from
-    if_rxbytes: PathBuf,
-}
+Without arguments, hands out the data collected since the last call,
+starting the collecting daemon if needed.
+
+  config      Output the munin graph config
+  autoconf    Tell munin if we can work on this system
+  check       Tell if we can work for this interface, step by step,
+              exits non-zero if not
+  suggest     List interfaces worth monitoring
+  list-interfaces
+              Show all interfaces, with speed, state and if suggested
+  install     Symlink us for every suggested interface, into the
+              plugins directory given or IF1SEC_PLUGINS_DIR
+  uninstall   Remove all our symlinks from the plugins directory
+  acquire     Run the collecting daemon
+  prometheus  Output the current counters in Prometheus format
+  --json      Output the current counters as JSON
+  --oneshot   Read and output one sample, without any daemon
+  --dump-config
+              Print a sample munin plugin configuration with all the
+              settings below
+  --version   Print the version
+  --help      Print this help
+
+";
 
-impl InterfacePlugin {
-    /// Check the name we are called with, split it on _ and return
-    /// the last part. So if1sec_eth0 -> eth0.
-    fn get_interface() -> String {
-        std::env::args()
-            .next()
-            .expect("Couldn't get program arguments")
-            .split('_')
-            .last()
-            .expect("Couldn't split arguments into parts")
-            .to_string()
+/// Run `plugin` the munin-plugin way, except for acquire under
+/// systemd, which stays in the foreground, see [notify]
+fn start<P: MuninPlugin>(plugin: &mut P, config: Config) -> Result<()> {
+    if std::env::args().nth(1).as_deref() == Some("acquire") && notify::under_systemd() {
+        info!("Running acquire in the foreground for systemd");
+        return notify::acquire_foreground(plugin, &config);
     }
+    plugin.start(config)?;
+    Ok(())
 }
 
-impl Default for InterfacePlugin {
-    /// Set defaults
-    fn default() -> Self {
-        let interface = InterfacePlugin::get_interface();
-        let if_rxbytes = Path::new("/sys/class/net")
-            .join(&interface)
-            .join("statistics/rx_bytes");
-        let if_txbytes = Path::new("/sys/class/net")
-            .join(&interface)
-            .join("statistics/tx_bytes");
-        if !Path::exists(&if_txbytes) {
-            error!("Can not find TX input file: {:?}", if_txbytes);
-            std::process::exit(2);
-        }
-        if !Path::exists(&if_rxbytes) {
-            error!("Can not find RX input file: {:?}", if_rxbytes);
-            std::process::exit(2);
-        }
-        Self {
-            interface,
-            if_rxbytes,
-            if_txbytes,
+/// What to log: only errors with IF1SEC_QUIET=1, RUST_LOG if set to a
+/// level (like `debug`), else debug if munin runs us with
+/// MUNIN_DEBUG=1, else only warnings and errors.
+fn log_level() -> LevelFilter {
+    if std::env::var("IF1SEC_QUIET").as_deref() == Ok("1") {
+        return LevelFilter::Error;
+    }
+    if let Ok(level) = std::env::var("RUST_LOG") {
+        match level.parse() {
+            Ok(level) => return level,
+            // No logger yet to tell
+            Err(_) => eprintln!("Ignoring invalid RUST_LOG {:?}", level),
         }
     }
+    if std::env::var("MUNIN_DEBUG").as_deref() == Ok("1") {
+        LevelFilter::Debug
+    } else {
+        let level = options::default("RUST_LOG");
+        level.parse().expect("the default RUST_LOG is a level")
+    }
 }
 
-impl MuninPlugin for InterfacePlugin {
-    fn config<W: Write>(&self, handle: &mut BufWriter<W>) -> Result<()> {
-        // Check network "speed" as shown by VM
-        let speedpath = Path::new("/sys/class/net/")
-            .join(&self.interface)
-            .join("speed");
-        debug!("speed: {:#?}", speedpath);
-        let speed: usize = if Path::exists(&speedpath) {
-            let rspeed: usize = std::fs::read_to_string(&speedpath)
-                .unwrap_or_else(|_| "0".to_owned())
-                .trim()
-                .parse()?;
-            if rspeed <= 0 {
-                1000
-            } else {
-                rspeed as usize
-            }
-        } else {
-            1000
-        };
-        let max = speed / 8 * 1000000;
-
-        writeln!(
-            handle,
-            "graph_title Interface 1sec stats for {}",
-            self.interface
-        )?;
-        writeln!(handle, "graph_category network")?;
-        writeln!(handle, "graph_args --base 1000")?;
-        writeln!(
-            handle,
-            "graph_data_size custom 1d, 1s for 1d, 5s for 2d, 10s for 7d, 1m for 1t, 5m for 1y"
-        )?;
-        writeln!(handle, "graph_vlabel bits in (-) / out (+)")?;
-        writeln!(handle, "graph_info This graph shows the traffic of the {} network self.interface. Please note that the traffic is shown in bits per second, not bytes.", self.interface)?;
-        writeln!(handle, "update_rate 1")?;
-        writeln!(handle, "{0}_rx.label {0} bits", self.interface)?;
-        writeln!(handle, "{0}_rx.cdef {0}_rx,8,*", self.interface)?;
-        writeln!(handle, "{}_rx.type DERIVE", self.interface)?;
-        writeln!(handle, "{}_rx.min 0", self.interface)?;
-        writeln!(handle, "{}_rx.graph no", self.interface)?;
-        writeln!(handle, "{}_tx.label bps", self.interface)?;
-        writeln!(handle, "{0}_tx.cdef {0}_tx,8,*", self.interface)?;
-        writeln!(handle, "{}_tx.type DERIVE", self.interface)?;
-        writeln!(handle, "{}_tx.min 0", self.interface)?;
-        writeln!(handle, "{0}_tx.negative {0}_rx", self.interface)?;
-        writeln!(handle, "{}_rx.max {}", self.interface, max)?;
-        writeln!(handle, "{}_tx.max {}", self.interface, max)?;
-        writeln!(
-            handle,
-            "{0}_rx.info Received traffic on the {0} self.interface. Maximum speed is {1} Mbps.",
-            self.interface, speed
-        )?;
-        writeln!(
-            handle,
-            "{0}_tx.info Transmitted traffic on the {0} self.interface. Maximum speed {1} Mbps.",
-            self.interface, speed
-        )?;
-
-        Ok(())
+/// Switch to the user from IF1SEC_RUN_AS, if set, now that the counter
+/// files are open. Exits if that doesn't work out.
+fn drop_privileges(keeps_files_open: bool) {
+    if let Some(user) = privs::run_as() {
+        if let Err(e) = privs::drop_to(&user, keeps_files_open) {
+            error!("Can not run as {}: {}", user, e);
+            std::process::exit(1);
+        }
     }
+}
 
-    fn acquire<W: Write>(
-        &mut self,
-        handle: &mut BufWriter<W>,
-        _config: &Config,
-        epoch: u64,
-    ) -> Result<()> {
-        // Read in the received and transferred bytes, store as u64
-        let rx: u64 = std::fs::read_to_string(&self.if_rxbytes)?.trim().parse()?;
-        let tx: u64 = std::fs::read_to_string(&self.if_txbytes)?.trim().parse()?;
-
-        // And now write out values
-        writeln!(handle, "{0}_tx.value {1}:{2}", self.interface, epoch, tx)?;
-        writeln!(handle, "{0}_rx.value {1}:{2}", self.interface, epoch, rx)?;
-
-        Ok(())
+/// With --interface <name> first, run us again with the rest of the
+/// arguments, and the interface in IF1SEC_INTERFACE. Then munin-plugin,
+/// and the daemon it starts, see the arguments they expect.
+fn reexec_for_interface() {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    if args.get(1).map(OsString::as_os_str) != Some(OsStr::new("--interface")) {
+        return;
     }
+    let Some(interface) = args.get(2) else {
+        eprintln!("--interface needs the name of the interface");
+        std::process::exit(1);
+    };
+    let e = Command::new(&args[0])
+        .args(&args[3..])
+        .env("IF1SEC_INTERFACE", interface)
+        .exec();
+    eprintln!("Can not run {:?} again: {}", args[0], e);
+    std::process::exit(1);
 }
 
 fn main() -> Result<()> {
-    SimpleLogger::new().init().unwrap();
+    reexec_for_interface();
+
+    // Questions for us, not munin, answer them before anything else
+    match std::env::args().nth(1).as_deref() {
+        Some("--version") => {
+            println!("if1sec {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Some("--help") => {
+            let stdout = std::io::stdout();
+            let mut out = stdout.lock();
+            write!(out, "{}", USAGE)?;
+            options::write_help(&mut out)?;
+            return Ok(());
+        }
+        Some("--dump-config") => {
+            options::write_sample_config(&mut std::io::stdout().lock())?;
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // Human readable, or JSON for log collectors
+    let level = log_level();
+    let format = std::env::var("IF1SEC_LOG_FORMAT").ok();
+    match format
+        .as_deref()
+        .unwrap_or(options::default("IF1SEC_LOG_FORMAT"))
+    {
+        "json" => {
+            let interface = InterfacePlugin::get_interface().unwrap_or_default();
+            JsonLogger::new(&interface, level).init()?;
+        }
+        "text" => SimpleLogger::new().with_level(level).init()?,
+        other => {
+            SimpleLogger::new().with_level(level).init()?;
+            warn!("Unknown IF1SEC_LOG_FORMAT {}, using text", other);
+        }
+    }
     info!("if1sec started");
 
-    // Set out config
-    let mut config = Config::new_daemon(String::from("if1sec"));
-    // Fetchsize 64k is arbitary, but better than default 8k.
-    config.fetch_size = 65535;
+    // Everything from here on looks at the interfaces from within
+    // the namespace
+    if let Err(e) = netns::enter_from_env() {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+
+    // Some arguments are one-shot questions from munin, which do not
+    // need an interface (nor a daemon), answer them and be done
+    match std::env::args().nth(1).as_deref() {
+        Some("suggest") => {
+            for interface in suggest_interfaces(&source::sysfs_root())? {
+                println!("{}", interface);
+            }
+            return Ok(());
+        }
+        Some("autoconf") => {
+            println!("{}", autoconf_answer(&source::sysfs_root()));
+            return Ok(());
+        }
+        Some("check") => {
+            let stdout = std::io::stdout();
+            let root = source::sysfs_root();
+            if !check(&mut stdout.lock(), &root, InterfacePlugin::get_interface())? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some("list-interfaces") => {
+            let stdout = std::io::stdout();
+            list_interfaces(&mut stdout.lock(), &source::sysfs_root())?;
+            return Ok(());
+        }
+        Some(command @ ("install" | "uninstall")) => {
+            let dir = std::env::args()
+                .nth(2)
+                .map(PathBuf::from)
+                .unwrap_or_else(install::plugins_dir);
+            // Where the links point to, the real binary
+            let plugin = std::env::current_exe()?;
+            let stdout = std::io::stdout();
+            let mut out = stdout.lock();
+            if command == "install" {
+                let interfaces = suggest_interfaces(&source::sysfs_root())?;
+                install::install(&mut out, &dir, &plugin, &interfaces)?;
+            } else {
+                install::uninstall(&mut out, &dir, &plugin)?;
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // Which interface(s), going by our name
+    let name = match InterfacePlugin::get_interface() {
+        Ok(name) => name,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Without the daemon, every fetch reads one sample, for cron
+    // driven munin or debugging
+    let oneshot = std::env::var("IF1SEC_ONESHOT").as_deref() == Ok("1")
+        || std::env::args().nth(1).as_deref() == Some("--oneshot");
 
-    let mut iface = InterfacePlugin {
-        ..Default::default()
+    // Set out config, one state per plugin name, so multiple of us
+    // can run side by side
+    let mut config = if oneshot {
+        Config::new(format!("if1sec_{}", name))
+    } else {
+        Config::new_daemon(format!("if1sec_{}", name))
     };
+    config.fetch_size = fetch_size();
+    info!(
+        "Fetch size {} bytes, update rate {}s",
+        config.fetch_size,
+        update_rate()
+    );
+    // With dirtyconfig, munin-plugin runs fetch() right after config
+    debug!("Munin supports dirtyconfig: {}", config.dirtyconfig);
+
+    // Many interfaces from one process
+    if let Some(selection) = Selection::from_env(&name) {
+        let mut plugin = MultiPlugin::new(selection)?;
+        plugin.register_signals()?;
+        drop_privileges(plugin.keeps_files_open());
+        debug!("Interfaces: {:#?}", plugin);
+        match std::env::args().nth(1).as_deref() {
+            Some("prometheus") => {
+                let stdout = std::io::stdout();
+                let mut handle = BufWriter::new(stdout.lock());
+                plugin.write_prometheus(&mut handle)?;
+                handle.flush()?;
+            }
+            Some("--json") => {
+                let epoch = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                let stdout = std::io::stdout();
+                let mut handle = BufWriter::new(stdout.lock());
+                plugin.write_json(&mut handle, epoch)?;
+                handle.flush()?;
+            }
+            Some("--oneshot") => {
+                let stdout = std::io::stdout();
+                let mut handle = BufWriter::new(stdout.lock());
+                plugin.fetch(&mut handle, &config)?;
+                handle.flush()?;
+            }
+            _ => start(&mut plugin, config)?,
+        }
+        return Ok(());
+    }
+
+    let mut iface = InterfacePlugin::for_interface(name);
+
+    // Stop cleanly when asked to, see acquire(), and re-read our
+    // settings, see reload()
+    iface.register_signals()?;
+    drop_privileges(iface.keeps_files_open());
 
     debug!("Interface: {:#?}", iface);
+
+    // Not for munin, but the same data for Prometheus or scripts, once
+    match std::env::args().nth(1).as_deref() {
+        Some("prometheus") => {
+            let stdout = std::io::stdout();
+            let mut handle = BufWriter::new(stdout.lock());
+            iface.write_prometheus(&mut handle)?;
+            handle.flush()?;
+            return Ok(());
+        }
+        Some("--json") => {
+            let epoch = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            let stdout = std::io::stdout();
+            let mut handle = BufWriter::new(stdout.lock());
+            iface.write_json(&mut handle, epoch)?;
+            handle.flush()?;
+            return Ok(());
+        }
+        Some("--oneshot") => {
+            let stdout = std::io::stdout();
+            let mut handle = BufWriter::new(stdout.lock());
+            iface.fetch(&mut handle, &config)?;
+            handle.flush()?;
+            return Ok(());
+        }
+        _ => {}
+    }
+
     // Get running
-    iface.start(config)?;
-    Ok(())
+    start(&mut iface, config)
 }
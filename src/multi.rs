@@ -0,0 +1,371 @@
+//! Monitor several interfaces from one process
+//!
+//! A host with dozens of interfaces doesn't need dozens of daemons.
+//! Invoked as if1sec_all, or with IF1SEC_INTERFACES=eth0,eth1 set,
+//! one acquire loop reads all of them, each interface getting its own
+//! multigraphs, exactly as a single interface plugin would output.
+//! The list may contain globs (en*,wg*), evaluated again on SIGHUP.
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use anyhow::Result;
+use log::{debug, info, warn};
+use munin_plugin::{Config, MuninPlugin};
+use std::{
+    io::{BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use crate::{
+    debug_socket::{self, DebugStats},
+    fetch_cached, has_counters,
+    notify::Notifier,
+    register_signals, source, state, stop_if_asked,
+    timing::{SampleClock, Tick},
+    update_rate, usable_interfaces, InterfacePlugin,
+};
+
+/// Split a comma separated list of interfaces, ignoring empty entries.
+fn parse_interface_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|interface| !interface.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Which interfaces got asked for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selection {
+    /// Every usable interface
+    All,
+    /// Interface names or shell style globs, like en*
+    List(Vec<String>),
+}
+
+impl Selection {
+    /// Which interfaces to monitor for the plugin `name`, if more
+    /// than the one from our name. IF1SEC_INTERFACES wins, else the
+    /// name `all` means every usable interface.
+    pub fn from_env(name: &str) -> Option<Self> {
+        if let Ok(list) = std::env::var("IF1SEC_INTERFACES") {
+            return Some(Selection::List(parse_interface_list(&list)));
+        }
+        if name == "all" {
+            return Some(Selection::All);
+        }
+        None
+    }
+
+    /// The concrete interfaces this selection means right now, for
+    /// the interfaces below `root`.
+    pub fn expand(&self, root: &Path) -> Result<Vec<String>> {
+        match self {
+            Selection::All => usable_interfaces(root),
+            Selection::List(patterns) => expand_globs(root, patterns),
+        }
+    }
+}
+
+/// Expand the shell style globs in `patterns` against the interfaces
+/// below `root`, those with counters (not bonding_masters and the
+/// like). Plain names are kept as they are, existing or not, a glob
+/// without any match expands to nothing. Keeps the order of
+/// `patterns`, dropping duplicates.
+fn expand_globs(root: &Path, patterns: &[String]) -> Result<Vec<String>> {
+    let is_glob = |pattern: &String| pattern.contains(['*', '?', '[']);
+    // Only list the directory if there is anything to match
+    let mut available: Vec<String> = Vec::new();
+    if patterns.iter().any(is_glob) {
+        available = std::fs::read_dir(root)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| has_counters(root, name))
+            .collect();
+        available.sort();
+    }
+
+    let mut interfaces: Vec<String> = Vec::new();
+    for pattern in patterns {
+        let matches = if is_glob(pattern) {
+            let glob = glob::Pattern::new(pattern)?;
+            let matches: Vec<String> = available
+                .iter()
+                .filter(|interface| glob.matches(interface))
+                .cloned()
+                .collect();
+            if matches.is_empty() {
+                debug!("No interface matches {}", pattern);
+            }
+            matches
+        } else {
+            vec![pattern.clone()]
+        };
+        for interface in matches {
+            if !interfaces.contains(&interface) {
+                interfaces.push(interface);
+            }
+        }
+    }
+    Ok(interfaces)
+}
+
+/// Many interfaces, one plugin
+///
+/// All state, including the one DERIVE continuity depends on, is per
+/// concrete interface. An interface matching a glob only after a
+/// reload starts fresh, one that stops matching is dropped.
+#[derive(Debug)]
+pub struct MultiPlugin {
+    /// Which interfaces got asked for, evaluated again on reload
+    selection: Selection,
+
+    /// The interfaces we work on, in config output order
+    interfaces: Vec<InterfacePlugin>,
+
+    /// When we sampled last
+    clock: SampleClock,
+
+    /// Set when we got asked to stop, see acquire()
+    stop: Arc<AtomicBool>,
+
+    /// Set when we got asked to reload, see acquire()
+    reload: Arc<AtomicBool>,
+
+    /// Tells systemd we are alive, if it runs us
+    notifier: Option<Notifier>,
+
+    /// What all interfaces tell the debug socket, if there is one
+    debug: Option<DebugStats>,
+
+    /// Whether acquire already set up what only lives in the daemon,
+    /// the debug socket and the systemd notifier
+    daemon_started: bool,
+}
+
+/// Setup the plugin for `interface`, None (with a warning) if that
+/// fails. One broken interface shouldn't take all others with it, a
+/// reload tries it again.
+fn open_interface(interface: &str) -> Option<InterfacePlugin> {
+    let state_file = state::state_file(interface, true);
+    match InterfacePlugin::with_state_file(interface, &source::sysfs_root(), state_file) {
+        Ok(iface) => Some(iface),
+        Err(e) => {
+            warn!("Skipping interface {}: {}", interface, e);
+            None
+        }
+    }
+}
+
+impl MultiPlugin {
+    /// Setup a plugin for every interface in `selection`
+    pub fn new(selection: Selection) -> Result<Self> {
+        let interfaces: Vec<InterfacePlugin> = selection
+            .expand(&source::sysfs_root())?
+            .iter()
+            .filter_map(|interface| open_interface(interface))
+            .collect();
+        let names: Vec<&str> = interfaces
+            .iter()
+            .map(|iface| iface.interface.as_str())
+            .collect();
+        info!("Monitoring interfaces {}", names.join(", "));
+        let mut plugin = MultiPlugin {
+            selection,
+            interfaces,
+            clock: SampleClock::new(update_rate()),
+            stop: Arc::new(AtomicBool::new(false)),
+            reload: Arc::new(AtomicBool::new(false)),
+            notifier: None,
+            debug: None,
+            daemon_started: false,
+        };
+        plugin.host_graphs_once();
+        Ok(plugin)
+    }
+
+    /// Evaluate the selection again, picking up new interfaces and
+    /// dropping gone ones. Those we keep get reloaded.
+    fn reload(&mut self) {
+        let wanted = match self.selection.expand(&source::sysfs_root()) {
+            Ok(wanted) => wanted,
+            Err(e) => {
+                warn!("Can not evaluate interface selection: {}", e);
+                self.interfaces
+                    .iter()
+                    .map(|iface| iface.interface.clone())
+                    .collect()
+            }
+        };
+        let mut old = std::mem::take(&mut self.interfaces);
+        for interface in wanted {
+            match old.iter().position(|iface| iface.interface == interface) {
+                Some(pos) => {
+                    let mut iface = old.swap_remove(pos);
+                    iface.reload();
+                    self.interfaces.push(iface);
+                }
+                None => {
+                    if let Some(iface) = open_interface(&interface) {
+                        info!("Now also monitoring {}", interface);
+                        self.interfaces.push(iface);
+                    }
+                }
+            }
+        }
+        for iface in old {
+            info!("No longer monitoring {}", iface.interface);
+        }
+        self.host_graphs_once();
+    }
+
+    /// The graphs about the whole host, not an interface, only come
+    /// from the first of our interfaces having them
+    fn host_graphs_once(&mut self) {
+        let mut softnet = false;
+        for iface in self.interfaces.iter_mut() {
+            if softnet {
+                iface.softnet = None;
+            }
+            softnet |= iface.softnet.is_some();
+        }
+    }
+
+    /// Setup the signal handlers: stop on SIGTERM/SIGINT, reload on
+    /// SIGHUP
+    pub fn register_signals(&self) -> Result<()> {
+        register_signals(&self.stop, &self.reload)
+    }
+
+    /// Do all our interfaces keep their files open? See
+    /// [InterfacePlugin::keeps_files_open].
+    pub fn keeps_files_open(&self) -> bool {
+        self.interfaces
+            .iter()
+            .all(InterfacePlugin::keeps_files_open)
+    }
+
+    /// Write the current byte counters of every interface in the
+    /// Prometheus text exposition format
+    pub fn write_prometheus<W: Write>(&mut self, handle: &mut BufWriter<W>) -> Result<()> {
+        for iface in self.interfaces.iter_mut() {
+            iface.write_prometheus(handle)?;
+        }
+        Ok(())
+    }
+
+    /// Write the current counters of every interface, one JSON
+    /// object per line
+    pub fn write_json<W: Write>(&mut self, handle: &mut BufWriter<W>, epoch: u64) -> Result<()> {
+        for iface in self.interfaces.iter_mut() {
+            iface.write_json(handle, epoch)?;
+        }
+        Ok(())
+    }
+
+    /// Sample every interface once, see InterfacePlugin::write_values()
+    fn write_values<W: Write>(&mut self, handle: &mut BufWriter<W>, epoch: u64) -> Result<()> {
+        for iface in self.interfaces.iter_mut() {
+            iface.write_values(handle, epoch)?;
+        }
+        Ok(())
+    }
+}
+
+impl MuninPlugin for MultiPlugin {
+    fn config<W: Write>(&self, handle: &mut BufWriter<W>) -> Result<()> {
+        for iface in &self.interfaces {
+            iface.config(handle)?;
+        }
+        Ok(())
+    }
+
+    fn fetch<W: Write>(&mut self, handle: &mut BufWriter<W>, config: &Config) -> Result<()> {
+        fetch_cached(handle, config, |handle, epoch| {
+            self.write_values(handle, epoch)
+        })
+    }
+
+    fn acquire<W: Write>(
+        &mut self,
+        handle: &mut BufWriter<W>,
+        _config: &Config,
+        epoch: u64,
+    ) -> Result<()> {
+        if self.reload.swap(false, Ordering::Relaxed) {
+            debug!("Reloading all interfaces");
+            self.reload();
+        }
+        if !self.daemon_started {
+            self.daemon_started = true;
+            self.debug = debug_socket::start_from_env();
+            self.notifier = Notifier::from_env();
+        }
+        if let Some(debug) = &self.debug {
+            // Including those new after a reload
+            for iface in self
+                .interfaces
+                .iter_mut()
+                .filter(|iface| iface.debug.is_none())
+            {
+                iface.debug = Some(debug.clone());
+            }
+        }
+        for iface in self.interfaces.iter_mut() {
+            iface.daemon_running();
+        }
+        match self.clock.check(Instant::now(), epoch) {
+            Tick::Sample => self.write_values(handle, epoch)?,
+            Tick::Early => {}
+            Tick::Gap => {
+                for iface in self.interfaces.iter_mut() {
+                    iface.skip();
+                }
+            }
+        }
+        if let Some(notifier) = self.notifier.as_mut() {
+            notifier.alive(Instant::now());
+        }
+        stop_if_asked(&self.stop, handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_interface_list() {
+        assert_eq!(
+            parse_interface_list("eth0, eth1,,wg0 "),
+            vec!["eth0", "eth1", "wg0"]
+        );
+        assert!(parse_interface_list("").is_empty());
+    }
+
+    #[test]
+    fn test_expand_globs() {
+        let dir = tempfile::tempdir().unwrap();
+        for interface in ["eno1", "enp3s0", "lo", "wg0", "wlan0"] {
+            let stats = dir.path().join(interface).join("statistics");
+            std::fs::create_dir_all(&stats).unwrap();
+            std::fs::write(stats.join("rx_bytes"), "0\n").unwrap();
+            std::fs::write(stats.join("tx_bytes"), "0\n").unwrap();
+        }
+        // No interfaces, even if their names match
+        std::fs::write(dir.path().join("bonding_masters"), "bond0\n").unwrap();
+        std::fs::create_dir(dir.path().join("ennoise")).unwrap();
+        let expand = |list: &str| expand_globs(dir.path(), &parse_interface_list(list)).unwrap();
+        assert_eq!(expand("en*,wg*"), vec!["eno1", "enp3s0", "wg0"]);
+        // Order as asked, no duplicates
+        assert_eq!(expand("wg0,w*"), vec!["wg0", "wlan0"]);
+        assert_eq!(expand("en?1"), vec!["eno1"]);
+        assert_eq!(expand("*"), vec!["eno1", "enp3s0", "lo", "wg0", "wlan0"]);
+        // Plain names stay, even if not there (yet)
+        assert_eq!(expand("eth0,ppp*"), vec!["eth0"]);
+    }
+}
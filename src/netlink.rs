@@ -0,0 +1,404 @@
+//! Read the counters over rtnetlink
+//!
+//! Instead of one sysfs file per counter, one RTM_GETLINK request
+//! gets all of them (IFLA_STATS64) in a single round trip. Note that
+//! the kernel doesn't push counter updates, RTM_NEWLINK notifications
+//! only come for changes of the link itself, so we still ask once per
+//! sample. Like /proc/net/dev, and unlike sysfs, this follows the
+//! network namespace we are in.
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use anyhow::{anyhow, Result};
+use std::{
+    ffi::CString,
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+use crate::source::{Counters, StatSource};
+
+/// The routing netlink protocol, from linux/netlink.h
+const NETLINK_ROUTE: libc::c_int = 0;
+
+/// Message types and flags from linux/netlink.h and linux/rtnetlink.h
+pub(crate) const NLMSG_ERROR: u16 = 2;
+const RTM_GETLINK: u16 = 18;
+pub(crate) const NLM_F_REQUEST: u16 = 1;
+
+/// The end of a dump, and the flags for one, from linux/netlink.h
+pub(crate) const NLMSG_DONE: u16 = 3;
+pub(crate) const NLM_F_MULTI: u16 = 2;
+pub(crate) const NLM_F_DUMP: u16 = 0x300;
+
+/// The type of an attribute, without the nested and byte order flags
+const NLA_TYPE_MASK: u16 = 0x3fff;
+
+/// Size of struct nlmsghdr
+pub(crate) const NLMSG_HDRLEN: usize = 16;
+
+/// Size of struct ifinfomsg
+const IFINFOMSG_LEN: usize = 16;
+
+/// The rtattr with the struct rtnl_link_stats64 of the link
+const IFLA_STATS64: u16 = 23;
+
+/// The counters we use, the first ten u64 of struct
+/// rtnl_link_stats64
+const STATS64_LEN: usize = 10 * 8;
+
+/// Enough for the RTM_NEWLINK reply of one link, with all its
+/// attributes
+const RECV_BUF: usize = 32 * 1024;
+
+/// Round `len` up to the 4 byte alignment of netlink messages and
+/// attributes
+pub(crate) fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// The native endian u16 at `at` in `buf`, if there
+pub(crate) fn u16_at(buf: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_ne_bytes(buf.get(at..at + 2)?.try_into().ok()?))
+}
+
+/// The native endian u32 at `at` in `buf`, if there
+pub(crate) fn u32_at(buf: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(buf.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// The attributes in `buf`, as type (without flags) and payload
+pub(crate) fn attributes(buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut found = Vec::new();
+    let mut at = 0;
+    while let (Some(len), Some(kind)) = (u16_at(buf, at), u16_at(buf, at + 2)) {
+        let len = len as usize;
+        let Some(payload) = buf.get(at + 4..at + len).filter(|_| len >= 4) else {
+            break;
+        };
+        found.push((kind & NLA_TYPE_MASK, payload));
+        at += align(len);
+    }
+    found
+}
+
+/// The attribute `kind` with `payload`, padded, for building the
+/// messages of the kernel in tests
+#[cfg(test)]
+pub(crate) fn attribute(kind: u16, payload: &[u8]) -> Vec<u8> {
+    let mut attr = Vec::new();
+    attr.extend_from_slice(&((4 + payload.len()) as u16).to_ne_bytes());
+    attr.extend_from_slice(&kind.to_ne_bytes());
+    attr.extend_from_slice(payload);
+    attr.resize(align(attr.len()), 0);
+    attr
+}
+
+/// Hand the attributes of every message answering `seq` in `buf` to
+/// `each`, after the `header` of the family (genlmsghdr, nfgenmsg).
+/// Some once the answer is complete (or failed), None if more of it
+/// is to come.
+pub(crate) fn parse_dump(
+    buf: &[u8],
+    seq: u32,
+    header: usize,
+    each: &mut impl FnMut(&[u8]),
+) -> Option<io::Result<()>> {
+    let mut at = 0;
+    while let (Some(len), Some(kind), Some(flags)) =
+        (u32_at(buf, at), u16_at(buf, at + 4), u16_at(buf, at + 6))
+    {
+        let len = len as usize;
+        if len < NLMSG_HDRLEN || at + len > buf.len() {
+            return Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Truncated netlink message",
+            )));
+        }
+        if u32_at(buf, at + 8) == Some(seq) {
+            let payload = &buf[at + NLMSG_HDRLEN..at + len];
+            match kind {
+                NLMSG_ERROR => {
+                    return match u32_at(payload, 0).map_or(0, |e| e as i32) {
+                        0 => Some(Ok(())),
+                        errno => Some(Err(io::Error::from_raw_os_error(-errno))),
+                    }
+                }
+                NLMSG_DONE => return Some(Ok(())),
+                _ => {
+                    each(payload.get(header..).unwrap_or_default());
+                    if flags & NLM_F_MULTI == 0 {
+                        return Some(Ok(()));
+                    }
+                }
+            }
+        }
+        at += align(len);
+    }
+    None
+}
+
+/// Send `request` numbered `seq` on `socket`, handing the attributes
+/// of every message of the answer to `each`, see [parse_dump]
+pub(crate) fn transact(
+    socket: &OwnedFd,
+    request: &[u8],
+    seq: u32,
+    header: usize,
+    each: impl FnMut(&[u8]),
+) -> io::Result<()> {
+    transact_with(socket, request, seq, header, &mut vec![0; RECV_BUF], each)
+}
+
+/// [transact], receiving into `buf`, for those asking every sample
+pub(crate) fn transact_with(
+    socket: &OwnedFd,
+    request: &[u8],
+    seq: u32,
+    header: usize,
+    buf: &mut [u8],
+    mut each: impl FnMut(&[u8]),
+) -> io::Result<()> {
+    // SAFETY: the socket is ours, request outlives the call.
+    // Without an address, it goes to the kernel.
+    let sent = unsafe {
+        libc::send(
+            socket.as_raw_fd(),
+            request.as_ptr() as *const libc::c_void,
+            request.len(),
+            0,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    loop {
+        // SAFETY: buf is ours and as large as we say
+        let len = unsafe {
+            libc::recv(
+                socket.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if let Some(result) = parse_dump(&buf[..len as usize], seq, header, &mut each) {
+            return result;
+        }
+    }
+}
+
+/// Build the RTM_GETLINK request for `ifindex`, numbered `seq`
+fn getlink_request(ifindex: u32, seq: u32) -> Vec<u8> {
+    let len = NLMSG_HDRLEN + IFINFOMSG_LEN;
+    let mut msg = Vec::with_capacity(len);
+    // struct nlmsghdr
+    msg.extend_from_slice(&(len as u32).to_ne_bytes());
+    msg.extend_from_slice(&RTM_GETLINK.to_ne_bytes());
+    msg.extend_from_slice(&NLM_F_REQUEST.to_ne_bytes());
+    msg.extend_from_slice(&seq.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    // struct ifinfomsg: family, padding, type, index, flags, change
+    msg.extend_from_slice(&[libc::AF_UNSPEC as u8, 0]);
+    msg.extend_from_slice(&0u16.to_ne_bytes());
+    msg.extend_from_slice(&(ifindex as i32).to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg
+}
+
+/// Find the counters in the IFLA_STATS64 attribute of a RTM_NEWLINK
+/// `payload` (starting with its struct ifinfomsg)
+fn parse_newlink(payload: &[u8]) -> Result<Counters> {
+    let mut at = IFINFOMSG_LEN;
+    while let (Some(len), Some(kind)) = (u16_at(payload, at), u16_at(payload, at + 2)) {
+        let len = len as usize;
+        if len < 4 {
+            break;
+        }
+        if kind == IFLA_STATS64 {
+            let stats = payload
+                .get(at + 4..at + len)
+                .filter(|stats| stats.len() >= STATS64_LEN)
+                .ok_or_else(|| anyhow!("Short IFLA_STATS64 attribute of {} bytes", len))?;
+            let value =
+                |n: usize| u64::from_ne_bytes(stats[n * 8..n * 8 + 8].try_into().expect("8 bytes"));
+            return Ok(Counters {
+                rx_packets: value(0),
+                tx_packets: value(1),
+                rx_bytes: value(2),
+                tx_bytes: value(3),
+                rx_errors: Some(value(4)),
+                tx_errors: Some(value(5)),
+                rx_dropped: Some(value(6)),
+                tx_dropped: Some(value(7)),
+                multicast: Some(value(8)),
+                collisions: Some(value(9)),
+            });
+        }
+        at += align(len);
+    }
+    Err(anyhow!("No IFLA_STATS64 in the netlink reply"))
+}
+
+/// Read the counters of an interface with RTM_GETLINK
+#[derive(Debug)]
+pub struct Netlink {
+    /// Which interface, for messages
+    interface: String,
+
+    /// Its ifindex, what we ask the kernel for
+    ifindex: u32,
+
+    /// Our rtnetlink socket
+    socket: OwnedFd,
+
+    /// Number of the last request
+    seq: u32,
+
+    /// Buffer for the reply, reused
+    buf: Vec<u8>,
+}
+
+impl Netlink {
+    /// Setup reading the counters of `interface`, failing if netlink
+    /// isn't available, or doesn't know the interface.
+    pub fn new(interface: &str) -> Result<Self> {
+        let name = CString::new(interface)?;
+        // SAFETY: name is NUL terminated
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(anyhow!("Can not find interface {}", interface));
+        }
+        // SAFETY: plain socket call, the fd gets owned (and closed)
+        // below
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(anyhow!(
+                "Can not open netlink socket: {}",
+                io::Error::last_os_error()
+            ));
+        }
+        // SAFETY: fd is a freshly opened socket nobody else owns
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+        let mut source = Self {
+            interface: interface.to_string(),
+            ifindex,
+            socket,
+            seq: 0,
+            buf: vec![0; RECV_BUF],
+        };
+        source.read_counters()?;
+        Ok(source)
+    }
+}
+
+impl StatSource for Netlink {
+    fn read_counters(&mut self) -> Result<Counters> {
+        self.seq = self.seq.wrapping_add(1);
+        let request = getlink_request(self.ifindex, self.seq);
+        let mut counters = None;
+        transact_with(
+            &self.socket,
+            &request,
+            self.seq,
+            0,
+            &mut self.buf,
+            |payload| {
+                counters = Some(parse_newlink(payload));
+            },
+        )
+        .map_err(|e| anyhow!("Netlink request for {} failed: {}", self.interface, e))?;
+        counters.unwrap_or_else(|| Err(anyhow!("No netlink reply for {}", self.interface)))
+    }
+
+    fn keeps_open(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The message type of the reply to RTM_GETLINK
+    const RTM_NEWLINK: u16 = 16;
+
+    /// A RTM_NEWLINK reply numbered `seq`, with an IFNAME attribute
+    /// and the stats 1, 2, 3, ...
+    fn newlink_reply(seq: u32) -> Vec<u8> {
+        let mut payload = vec![0; IFINFOMSG_LEN];
+        payload.extend(attribute(3, b"eth0\0"));
+        // All 24 counters of the kernel
+        let stats: Vec<u8> = (1..=24u64).flat_map(u64::to_ne_bytes).collect();
+        payload.extend(attribute(IFLA_STATS64, &stats));
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&((NLMSG_HDRLEN + payload.len()) as u32).to_ne_bytes());
+        msg.extend_from_slice(&RTM_NEWLINK.to_ne_bytes());
+        msg.extend_from_slice(&0u16.to_ne_bytes());
+        msg.extend_from_slice(&seq.to_ne_bytes());
+        msg.extend_from_slice(&0u32.to_ne_bytes());
+        msg.extend_from_slice(&payload);
+        msg
+    }
+
+    /// The counters in the reply to `seq` in `buf`, as read_counters
+    /// finds them
+    fn parse_reply(buf: &[u8], seq: u32) -> Option<io::Result<Option<Result<Counters>>>> {
+        let mut counters = None;
+        let result = parse_dump(buf, seq, 0, &mut |payload| {
+            counters = Some(parse_newlink(payload));
+        })?;
+        Some(result.map(|()| counters))
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        let request = getlink_request(7, 1);
+        assert_eq!(request.len(), 32);
+        assert_eq!(u16_at(&request, 4), Some(RTM_GETLINK));
+        assert_eq!(u32_at(&request, 20), Some(7));
+
+        let counters = parse_reply(&newlink_reply(3), 3).unwrap().unwrap();
+        assert_eq!(
+            counters.unwrap().unwrap(),
+            Counters {
+                rx_packets: 1,
+                tx_packets: 2,
+                rx_bytes: 3,
+                tx_bytes: 4,
+                rx_errors: Some(5),
+                tx_errors: Some(6),
+                rx_dropped: Some(7),
+                tx_dropped: Some(8),
+                multicast: Some(9),
+                collisions: Some(10),
+            }
+        );
+        // A late reply to an earlier request
+        assert!(parse_reply(&newlink_reply(2), 3).is_none());
+
+        // ENODEV
+        let mut error = Vec::new();
+        error.extend_from_slice(&((NLMSG_HDRLEN + 4) as u32).to_ne_bytes());
+        error.extend_from_slice(&NLMSG_ERROR.to_ne_bytes());
+        error.extend_from_slice(&0u16.to_ne_bytes());
+        error.extend_from_slice(&3u32.to_ne_bytes());
+        error.extend_from_slice(&0u32.to_ne_bytes());
+        error.extend_from_slice(&(-libc::ENODEV).to_ne_bytes());
+        let e = parse_reply(&error, 3).unwrap().unwrap_err();
+        assert!(e.to_string().contains("No such device"), "{}", e);
+
+        // Cut short
+        let reply = newlink_reply(3);
+        assert!(parse_reply(&reply[..reply.len() - 8], 3).unwrap().is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! Collect from within another network namespace
+//!
+//! To watch the interface of a container from the host, we join its
+//! network namespace at startup, as named in IF1SEC_NETNS: a name
+//! from `ip netns` (/var/run/netns/<name>), or the path of any
+//! namespace file, like /proc/<pid>/ns/net. Linux only, and needs
+//! CAP_SYS_ADMIN.
+//!
+//! Note that sysfs shows the interfaces of the namespace it got
+//! mounted in, not the one we are in. Unless the plugin runs with a
+//! sysfs of the container mounted (say, IF1SEC_SYSFS_ROOT pointing to
+//! it), use IF1SEC_SOURCE=procnetdev or netlink, those always follow
+//! us.
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use anyhow::{anyhow, Result};
+use log::info;
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Where `ip netns` keeps the named namespaces
+const NETNS_DIR: &str = "/var/run/netns";
+
+/// The namespace file for the IF1SEC_NETNS `value`, a path if it has
+/// a slash, otherwise a name below [NETNS_DIR].
+fn netns_path(value: &OsStr) -> PathBuf {
+    let path = Path::new(value);
+    if path.components().count() > 1 {
+        path.to_path_buf()
+    } else {
+        Path::new(NETNS_DIR).join(path)
+    }
+}
+
+/// Join the network namespace of `file`
+#[cfg(target_os = "linux")]
+fn setns(file: &File) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    // SAFETY: setns only looks at the fd, which is open for the call
+    if unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Network namespaces are Linux only
+#[cfg(not(target_os = "linux"))]
+fn setns(_file: &File) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// Join the network namespace IF1SEC_NETNS names, if any. Has to
+/// happen before anything else, threads started earlier stay where
+/// they are.
+pub fn enter_from_env() -> Result<()> {
+    let Some(value) = std::env::var_os("IF1SEC_NETNS") else {
+        return Ok(());
+    };
+    let path = netns_path(&value);
+    let file = File::open(&path)
+        .map_err(|e| anyhow!("Can not open network namespace {}: {}", path.display(), e))?;
+    if let Err(e) = setns(&file) {
+        if e.raw_os_error() == Some(libc::EPERM) {
+            return Err(anyhow!(
+                "Not allowed to enter network namespace {}, that needs CAP_SYS_ADMIN",
+                path.display()
+            ));
+        }
+        return Err(anyhow!(
+            "Can not enter network namespace {}: {}",
+            path.display(),
+            e
+        ));
+    }
+    info!("Entered network namespace {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_netns_path() {
+        assert_eq!(
+            netns_path(OsStr::new("web")),
+            PathBuf::from("/var/run/netns/web")
+        );
+        assert_eq!(
+            netns_path(OsStr::new("/proc/1234/ns/net")),
+            PathBuf::from("/proc/1234/ns/net")
+        );
+        assert_eq!(netns_path(OsStr::new("./web")), PathBuf::from("./web"));
+    }
+}
@@ -0,0 +1,313 @@
+//! IPv4 and IPv6 traffic, counted by nftables
+//!
+//! Neither sysfs nor any of the other sources split the traffic by IP
+//! version, only the firewall can. So the admin sets up four named
+//! counter objects per interface, in any table, and rules feeding
+//! them, and we read those over nfnetlink (NFT_MSG_GETOBJ). For eth0:
+//!
+//! ```text
+//! table inet if1sec {
+//!     counter if1sec_eth0_v4_rx {}
+//!     counter if1sec_eth0_v4_tx {}
+//!     counter if1sec_eth0_v6_rx {}
+//!     counter if1sec_eth0_v6_tx {}
+//!     chain rx {
+//!         type filter hook prerouting priority -300; policy accept;
+//!         iifname "eth0" meta nfproto ipv4 counter name "if1sec_eth0_v4_rx"
+//!         iifname "eth0" meta nfproto ipv6 counter name "if1sec_eth0_v6_rx"
+//!     }
+//!     chain tx {
+//!         type filter hook postrouting priority 300; policy accept;
+//!         oifname "eth0" meta nfproto ipv4 counter name "if1sec_eth0_v4_tx"
+//!         oifname "eth0" meta nfproto ipv6 counter name "if1sec_eth0_v6_tx"
+//!     }
+//! }
+//! ```
+//!
+//! nftables counts the IP packets, without the link layer header, so
+//! the sum is a little below the traffic graph. Any counter missing is
+//! left out of the graph, none at all, no graph. Reading them needs
+//! CAP_NET_ADMIN, for the whole run, so not with IF1SEC_RUN_AS.
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use log::debug;
+use std::{
+    cell::Cell,
+    io,
+    os::fd::{FromRawFd, OwnedFd},
+};
+
+use crate::netlink::{align, attributes, transact, NLM_F_DUMP, NLM_F_REQUEST};
+
+/// The netfilter netlink protocol, from linux/netlink.h
+const NETLINK_NETFILTER: libc::c_int = 12;
+
+/// The nftables subsystem of nfnetlink, from
+/// linux/netfilter/nfnetlink.h
+const NFNL_SUBSYS_NFTABLES: u16 = 10;
+
+/// Size of struct nfgenmsg
+const NFGENMSG_LEN: usize = 4;
+
+/// Messages and attributes from linux/netfilter/nf_tables.h. Unlike
+/// the rest of netlink, nftables has its numbers in network byte
+/// order.
+const NFT_MSG_GETOBJ: u16 = 19;
+const NFTA_OBJ_NAME: u16 = 2;
+const NFTA_OBJ_TYPE: u16 = 3;
+const NFTA_OBJ_DATA: u16 = 4;
+const NFT_OBJECT_COUNTER: u32 = 1;
+const NFTA_COUNTER_BYTES: u16 = 1;
+
+/// The bytes the counters of an interface have seen, None for those
+/// not set up
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IpTraffic {
+    /// IPv4 received
+    pub v4_rx: Option<u64>,
+    /// IPv4 sent
+    pub v4_tx: Option<u64>,
+    /// IPv6 received
+    pub v6_rx: Option<u64>,
+    /// IPv6 sent
+    pub v6_tx: Option<u64>,
+}
+
+impl IpTraffic {
+    /// Per IP version its field name, label and the received and sent
+    /// bytes
+    pub fn versions(&self) -> [(&'static str, &'static str, Option<u64>, Option<u64>); 2] {
+        [
+            ("v4", "IPv4", self.v4_rx, self.v4_tx),
+            ("v6", "IPv6", self.v6_rx, self.v6_tx),
+        ]
+    }
+
+    /// Is there no counter at all?
+    pub fn is_empty(&self) -> bool {
+        *self == IpTraffic::default()
+    }
+
+    /// Note the `bytes` of the counter object `name`, if it is one of
+    /// the counters of `interface`. The first one wins, should there
+    /// be more tables with it.
+    fn add(&mut self, interface: &str, name: &str, bytes: u64) {
+        let Some(field) = name
+            .strip_prefix("if1sec_")
+            .and_then(|name| name.strip_prefix(interface))
+        else {
+            return;
+        };
+        let counter = match field {
+            "_v4_rx" => &mut self.v4_rx,
+            "_v4_tx" => &mut self.v4_tx,
+            "_v6_rx" => &mut self.v6_rx,
+            "_v6_tx" => &mut self.v6_tx,
+            _ => return,
+        };
+        counter.get_or_insert(bytes);
+    }
+}
+
+/// The name and bytes of the counter object in the attributes of a
+/// NFT_MSG_NEWOBJ message, None for other objects
+fn parse_counter(attrs: &[u8]) -> Option<(String, u64)> {
+    let (mut name, mut kind, mut data) = (None, None, None);
+    for (attr, payload) in attributes(attrs) {
+        match attr {
+            NFTA_OBJ_NAME => {
+                let name_bytes = payload.split(|byte| *byte == 0).next()?;
+                name = Some(String::from_utf8_lossy(name_bytes).to_string());
+            }
+            NFTA_OBJ_TYPE => kind = Some(u32::from_be_bytes(payload.try_into().ok()?)),
+            NFTA_OBJ_DATA => data = Some(payload),
+            _ => {}
+        }
+    }
+    if kind? != NFT_OBJECT_COUNTER {
+        return None;
+    }
+    let bytes = attributes(data?)
+        .into_iter()
+        .find(|(attr, _)| *attr == NFTA_COUNTER_BYTES)
+        .and_then(|(_, payload)| Some(u64::from_be_bytes(payload.try_into().ok()?)))?;
+    Some((name?, bytes))
+}
+
+/// Build the request to dump all counter objects, of every family,
+/// numbered `seq`
+fn getobj_request(seq: u32) -> Vec<u8> {
+    let mut msg = Vec::new();
+    // struct nlmsghdr, the length goes in at the end
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg.extend_from_slice(&(NFNL_SUBSYS_NFTABLES << 8 | NFT_MSG_GETOBJ).to_ne_bytes());
+    msg.extend_from_slice(&(NLM_F_REQUEST | NLM_F_DUMP).to_ne_bytes());
+    msg.extend_from_slice(&seq.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    // struct nfgenmsg: family (all), version, resource id
+    msg.extend_from_slice(&[libc::AF_UNSPEC as u8, 0, 0, 0]);
+    // Only the counters
+    msg.extend_from_slice(&8u16.to_ne_bytes());
+    msg.extend_from_slice(&NFTA_OBJ_TYPE.to_ne_bytes());
+    msg.extend_from_slice(&NFT_OBJECT_COUNTER.to_be_bytes());
+    msg.resize(align(msg.len()), 0);
+    let len = msg.len() as u32;
+    msg[..4].copy_from_slice(&len.to_ne_bytes());
+    msg
+}
+
+/// Reads the nftables counters of an interface
+#[derive(Debug)]
+pub struct Nftables {
+    /// Which interface, its counters are named after it
+    interface: String,
+
+    /// Our nfnetlink socket
+    socket: OwnedFd,
+
+    /// Number of the last request. config() only gets to look, so
+    /// it has to change without a mutable borrow.
+    seq: Cell<u32>,
+}
+
+impl Nftables {
+    /// Setup reading the counters of `interface`. None if nftables
+    /// can't be asked, no nf_tables or not allowed to.
+    pub fn open(interface: &str) -> Option<Self> {
+        let nftables = match Nftables::new(interface) {
+            Ok(nftables) => nftables,
+            Err(e) => {
+                debug!("No nftables counters for {}: {}", interface, e);
+                return None;
+            }
+        };
+        match nftables.traffic() {
+            Ok(_) => Some(nftables),
+            Err(e) => {
+                debug!("Can not ask nftables about {}: {}", interface, e);
+                None
+            }
+        }
+    }
+
+    /// Open the socket
+    fn new(interface: &str) -> io::Result<Self> {
+        // SAFETY: plain socket call, the fd gets owned (and closed)
+        // below
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                NETLINK_NETFILTER,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Nftables {
+            interface: interface.to_string(),
+            // SAFETY: fd is a freshly opened socket nobody else owns
+            socket: unsafe { OwnedFd::from_raw_fd(fd) },
+            seq: Cell::new(0),
+        })
+    }
+
+    /// The counters of our interface right now. Those set up later
+    /// show up with them, those removed are gone.
+    pub fn traffic(&self) -> io::Result<IpTraffic> {
+        let seq = self.seq.get().wrapping_add(1);
+        self.seq.set(seq);
+        let mut traffic = IpTraffic::default();
+        transact(
+            &self.socket,
+            &getobj_request(seq),
+            seq,
+            NFGENMSG_LEN,
+            |attrs| {
+                if let Some((name, bytes)) = parse_counter(attrs) {
+                    traffic.add(&self.interface, &name, bytes);
+                }
+            },
+        )?;
+        Ok(traffic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::netlink::{attribute, parse_dump, u16_at, NLM_F_MULTI};
+
+    /// A NFT_MSG_NEWOBJ message of a dump, numbered `seq`, for the
+    /// object `name` of the type `kind`, with `bytes`
+    fn object_message(seq: u32, name: &str, kind: u32, bytes: u64) -> Vec<u8> {
+        let mut msg = getobj_request(seq);
+        msg.truncate(16 + NFGENMSG_LEN);
+        msg[6..8].copy_from_slice(&NLM_F_MULTI.to_ne_bytes());
+        msg.extend(attribute(1, b"if1sec\0"));
+        msg.extend(attribute(NFTA_OBJ_NAME, format!("{}\0", name).as_bytes()));
+        msg.extend(attribute(NFTA_OBJ_TYPE, &kind.to_be_bytes()));
+        let mut data = attribute(NFTA_COUNTER_BYTES, &bytes.to_be_bytes());
+        data.extend(attribute(2, &7u64.to_be_bytes()));
+        msg.extend(attribute(NFTA_OBJ_DATA | 0x8000, &data));
+        let len = msg.len() as u32;
+        msg[..4].copy_from_slice(&len.to_ne_bytes());
+        msg
+    }
+
+    #[test]
+    fn test_parse_counters() {
+        let request = getobj_request(5);
+        assert_eq!(request.len(), 28);
+        assert_eq!(u16_at(&request, 4), Some(10 << 8 | 19));
+        assert_eq!(u16_at(&request, 6), Some(NLM_F_REQUEST | NLM_F_DUMP));
+        assert_eq!(request[24..], NFT_OBJECT_COUNTER.to_be_bytes());
+
+        let mut buf = object_message(5, "if1sec_eth0_v4_rx", NFT_OBJECT_COUNTER, 1000);
+        buf.extend(object_message(
+            5,
+            "if1sec_eth0_v6_tx",
+            NFT_OBJECT_COUNTER,
+            60,
+        ));
+        // Another interface, an unrelated counter, a quota
+        buf.extend(object_message(
+            5,
+            "if1sec_eth0.5_v4_rx",
+            NFT_OBJECT_COUNTER,
+            1,
+        ));
+        buf.extend(object_message(5, "web", NFT_OBJECT_COUNTER, 2));
+        buf.extend(object_message(5, "if1sec_eth0_v4_tx", 2, 3));
+        // Again, in another table
+        buf.extend(object_message(
+            5,
+            "if1sec_eth0_v4_rx",
+            NFT_OBJECT_COUNTER,
+            4,
+        ));
+        let mut traffic = IpTraffic::default();
+        let mut each = |attrs: &[u8]| {
+            if let Some((name, bytes)) = parse_counter(attrs) {
+                traffic.add("eth0", &name, bytes);
+            }
+        };
+        assert!(parse_dump(&buf, 5, NFGENMSG_LEN, &mut each).is_none());
+        assert_eq!(
+            traffic,
+            IpTraffic {
+                v4_rx: Some(1000),
+                v4_tx: None,
+                v6_rx: None,
+                v6_tx: Some(60),
+            }
+        );
+        assert_eq!(traffic.versions()[1], ("v6", "IPv6", None, Some(60)));
+        assert!(!traffic.is_empty());
+        assert!(IpTraffic::default().is_empty());
+        assert_eq!(
+            parse_counter(&object_message(5, "if1sec_eth0_v4_tx", 2, 3)[20..]),
+            None
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! Tell systemd how we are doing
+//!
+//! When running as a systemd service (Type=notify, maybe with
+//! WatchdogSec=), systemd hands us a socket in NOTIFY_SOCKET. We say
+//! READY=1 once the acquire loop runs, and, with a watchdog set up in
+//! WATCHDOG_USEC, WATCHDOG=1 every other half of its timeout, so a
+//! wedged daemon gets restarted. Without those variables, nothing
+//! happens at all.
+//!
+//! systemd only listens to the main process of the service, so with
+//! NOTIFY_SOCKET set, acquire stays in the foreground instead of
+//! forking into the background, see [acquire_foreground]. A unit
+//! running it looks like this, with the plugin state directory of
+//! munin-node, so fetch finds the data:
+//!
+//! ```text
+//! [Service]
+//! Type=notify
+//! WatchdogSec=30
+//! Environment=MUNIN_PLUGSTATE=/var/lib/munin-node/plugin-state/nobody
+//! ExecStart=/etc/munin/plugins/if1sec_eth0 acquire
+//! User=nobody
+//! Restart=on-failure
+//! ```
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use anyhow::{anyhow, Result};
+use log::debug;
+use munin_plugin::{Config, MuninPlugin};
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+use std::{
+    fs::OpenOptions,
+    io::{BufWriter, Write},
+    os::unix::{
+        io::AsRawFd,
+        net::{SocketAddr, UnixDatagram},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Does systemd run us, and wants to hear from us?
+pub fn under_systemd() -> bool {
+    std::env::var_os("NOTIFY_SOCKET").is_some()
+}
+
+/// The acquire loop of munin-plugin, once a second, but without the
+/// fork into the background: for systemd we have to stay its main
+/// process. Like munin-plugin, this locks the pidfile while running,
+/// so fetch knows there is no need to start acquire.
+pub fn acquire_foreground<P: MuninPlugin>(plugin: &mut P, config: &Config) -> Result<()> {
+    let mut pidfile = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&config.pidfile)?;
+    // SAFETY: flock only looks at the descriptor, open for this call
+    if unsafe { libc::flock(pidfile.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        return Err(anyhow!(
+            "Can not lock {}, already running? ({})",
+            config.pidfile.display(),
+            std::io::Error::last_os_error()
+        ));
+    }
+    pidfile.set_len(0)?;
+    writeln!(pidfile, "{}", std::process::id())?;
+    loop {
+        let epoch = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        {
+            // Closed before we sleep, fetch may move it away
+            let cache = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&config.plugin_cache)?;
+            let mut handle = BufWriter::with_capacity(config.fetch_size, cache);
+            plugin.acquire(&mut handle, config, epoch)?;
+            handle.flush()?;
+        }
+        // On to the next full second
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        std::thread::sleep(
+            Duration::from_secs(1) - Duration::from_nanos(now.subsec_nanos().into()),
+        );
+    }
+}
+
+/// Talks to systemd
+#[derive(Debug)]
+pub struct Notifier {
+    /// Our end
+    socket: UnixDatagram,
+
+    /// Where systemd listens
+    addr: SocketAddr,
+
+    /// Ping the watchdog this often, if there is one
+    interval: Option<Duration>,
+
+    /// When we pinged last, None before READY=1 went out
+    last_ping: Option<Instant>,
+}
+
+impl Notifier {
+    /// Talk to systemd, if it runs us, going by NOTIFY_SOCKET and
+    /// WATCHDOG_USEC. The watchdog is only ours, if WATCHDOG_PID is
+    /// unset or our pid. So only call this in the process running the
+    /// acquire loop.
+    pub fn from_env() -> Option<Self> {
+        let socket = std::env::var("NOTIFY_SOCKET").ok()?;
+        let addr = match socket.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => SocketAddr::from_abstract_name(name),
+            // Abstract sockets are Linux only
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => Err(std::io::Error::from(std::io::ErrorKind::Unsupported)),
+            None => SocketAddr::from_pathname(&socket),
+        }
+        .map_err(|e| debug!("Unusable NOTIFY_SOCKET {}: {}", socket, e))
+        .ok()?;
+        let ours = std::env::var("WATCHDOG_PID")
+            .map_or(true, |pid| pid.trim() == std::process::id().to_string());
+        let watchdog = std::env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.trim().parse().ok())
+            .filter(|usec| ours && *usec > 0)
+            .map(Duration::from_micros);
+        Notifier::new(addr, watchdog)
+    }
+
+    /// Talk to systemd at `addr`, with a watchdog timeout of
+    /// `watchdog`, if any
+    fn new(addr: SocketAddr, watchdog: Option<Duration>) -> Option<Self> {
+        let socket = UnixDatagram::unbound()
+            .map_err(|e| debug!("Can not create notify socket: {}", e))
+            .ok()?;
+        Some(Notifier {
+            socket,
+            addr,
+            interval: watchdog.map(|timeout| timeout / 2),
+            last_ping: None,
+        })
+    }
+
+    /// Send `state` to systemd. Nothing to do about failure, but
+    /// systemd will notice.
+    fn send(&self, state: &str) {
+        if let Err(e) = self.socket.send_to_addr(state.as_bytes(), &self.addr) {
+            debug!("Can not notify systemd of {}: {}", state, e);
+        }
+    }
+
+    /// We made it through another acquire at `now`: READY=1 the first
+    /// time, then WATCHDOG=1 if one is due.
+    pub fn alive(&mut self, now: Instant) {
+        let Some(last) = self.last_ping else {
+            self.send("READY=1");
+            if self.interval.is_some() {
+                self.send("WATCHDOG=1");
+            }
+            self.last_ping = Some(now);
+            return;
+        };
+        if let Some(interval) = self.interval {
+            if now.saturating_duration_since(last) >= interval {
+                self.send("WATCHDOG=1");
+                self.last_ping = Some(now);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let systemd = UnixDatagram::bind(&path).unwrap();
+        systemd.set_nonblocking(true).unwrap();
+        let receive = || {
+            let mut buf = [0; 64];
+            match systemd.recv(&mut buf) {
+                Ok(len) => Some(String::from_utf8_lossy(&buf[..len]).to_string()),
+                Err(_) => None,
+            }
+        };
+
+        let addr = SocketAddr::from_pathname(&path).unwrap();
+        let mut notifier = Notifier::new(addr, Some(Duration::from_secs(10))).unwrap();
+        let start = Instant::now();
+        notifier.alive(start);
+        assert_eq!(receive().as_deref(), Some("READY=1"));
+        assert_eq!(receive().as_deref(), Some("WATCHDOG=1"));
+        // Not yet due
+        notifier.alive(start + Duration::from_secs(1));
+        assert_eq!(receive(), None);
+        notifier.alive(start + Duration::from_secs(5));
+        assert_eq!(receive().as_deref(), Some("WATCHDOG=1"));
+
+        // No watchdog, only ready
+        let addr = SocketAddr::from_pathname(&path).unwrap();
+        let mut notifier = Notifier::new(addr, None).unwrap();
+        notifier.alive(start);
+        notifier.alive(start + Duration::from_secs(60));
+        assert_eq!(receive().as_deref(), Some("READY=1"));
+        assert_eq!(receive(), None);
+    }
+}
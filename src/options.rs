@@ -0,0 +1,425 @@
+//! All the environment variables we look at, in one place
+//!
+//! Munin hands plugins their settings in the environment, from the
+//! env.* lines in /etc/munin/plugin-conf.d/. [OPTIONS] has each of
+//! ours, with what it does and its default, for the Environment part
+//! of --help and for the sample configuration of --dump-config. A
+//! test makes sure every variable the code reads is in there. The
+//! code takes the defaults from there too, see [default].
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use log::warn;
+use std::io::{self, Write};
+
+/// One environment variable
+#[derive(Debug, Clone, Copy)]
+pub struct Opt {
+    /// Its name
+    pub name: &'static str,
+    /// The value for the sample configuration, the default, or an
+    /// example where there is none. The code falls back to it, see
+    /// [default].
+    pub value: &'static str,
+    /// What it does, one line, wrapped on output
+    pub help: &'static str,
+}
+
+/// Every variable we read, in the order --help lists them
+pub const OPTIONS: &[Opt] = &[
+    Opt {
+        name: "IF1SEC_INTERFACE",
+        value: "eth0",
+        help: "The interface to collect, instead of the one from our name",
+    },
+    Opt {
+        name: "IF1SEC_INTERFACES",
+        value: "eth0,wg*",
+        help: "Interfaces (or globs) to collect in one process, like eth0,wg*",
+    },
+    Opt {
+        name: "IF1SEC_SOURCE",
+        value: "sysfs",
+        help: "Read counters from sysfs (default on Linux), procnetdev, netlink or getifaddrs \
+               (default elsewhere)",
+    },
+    Opt {
+        name: "IF1SEC_SYSFS_ROOT",
+        value: "/sys/class/net",
+        help: "Where to find the interfaces, default /sys/class/net",
+    },
+    Opt {
+        name: "IF1SEC_READ_TIMEOUT_MS",
+        value: "500",
+        help: "Give up reading the counters after that many ms, default 500, 0 for no timeout",
+    },
+    Opt {
+        name: "IF1SEC_MAX_SPEED_MBPS",
+        value: "1000",
+        help: "Link speed to use, instead of detecting it",
+    },
+    Opt {
+        name: "IF1SEC_WARN_PCT",
+        value: "80",
+        help: "Munin warning above that link utilization, in percent, only with a known speed",
+    },
+    Opt {
+        name: "IF1SEC_CRIT_PCT",
+        value: "95",
+        help: "Same for critical",
+    },
+    Opt {
+        name: "IF1SEC_BRIDGE_SPEED",
+        value: "max",
+        help: "Speed of a bridge, max (default) of its ports, or their sum",
+    },
+    Opt {
+        name: "IF1SEC_COUNTER_WIDTH",
+        value: "auto",
+        help: "Counter width, auto (default), 32 or 64",
+    },
+    Opt {
+        name: "IF1SEC_WAIT_FOR_IFACE",
+        value: "1",
+        help: "Set to 1 to wait for a missing interface",
+    },
+    Opt {
+        name: "IF1SEC_FOLLOW_RENAME",
+        value: "1",
+        help: "Set to 1 to follow a renamed interface (same ifindex), graphs keep the original \
+               name",
+    },
+    Opt {
+        name: "IF1SEC_OPEN_RETRIES",
+        value: "3",
+        help: "Retries to open a missing interface at startup, default 3, 0 for none",
+    },
+    Opt {
+        name: "IF1SEC_CATEGORY",
+        value: "network",
+        help: "The munin graph category, default network",
+    },
+    Opt {
+        name: "IF1SEC_FIELDS",
+        value: "bytes",
+        help: "Graphs to output, comma separated, default bytes (the traffic), or all. Others: \
+               peak, packets, pktsize, errors, overruns, linkerrors, carrier, nohandler, \
+               compressed, drops, softnet (backlog drops of the whole host, as \
+               if1sec_softnet, not part of all, ask for it in one plugin or with \
+               IF1SEC_INTERFACES), multicast, \
+               collisions, link, mtu, flaps, bond, wireless, stations, ipver (the nftables \
+               counters if1sec_<interface>_v4_rx, _v4_tx, _v6_rx and _v6_tx), txqueuelen, \
+               txbacklog, queues, ethtool, rings, skipped",
+    },
+    Opt {
+        name: "IF1SEC_SHOW_TOTAL",
+        value: "1",
+        help: "Set to 1 for a total line in the traffic graph",
+    },
+    Opt {
+        name: "IF1SEC_HIDE_ADDR",
+        value: "1",
+        help: "Set to 1 to keep MAC and IP addresses out of the graph info",
+    },
+    Opt {
+        name: "IF1SEC_UNITS",
+        value: "bits",
+        help: "Show traffic in bits (default) or bytes",
+    },
+    Opt {
+        name: "IF1SEC_BASE",
+        value: "1000",
+        help: "Scale the traffic graph by 1000 (default) or 1024",
+    },
+    Opt {
+        name: "IF1SEC_TYPE",
+        value: "derive",
+        help: "Hand munin the traffic counters (derive, the default), or the rate computed by \
+               us (gauge)",
+    },
+    Opt {
+        name: "IF1SEC_GRAPH_ARGS",
+        value: "--base 1000 --lower-limit 0",
+        help: "graph_args of the traffic graph, instead of just the base",
+    },
+    Opt {
+        name: "IF1SEC_GRAPH_VLABEL",
+        value: "bits in (-) / out (+)",
+        help: "graph_vlabel of the traffic graph",
+    },
+    Opt {
+        name: "IF1SEC_GRAPH_ORDER",
+        value: "eth0_rx eth0_tx",
+        help: "graph_order of the traffic graph, default none",
+    },
+    Opt {
+        name: "IF1SEC_STATE_FILE",
+        value: "/var/lib/munin-node/plugin-state/nobody/if1sec_{interface}.state",
+        help: "Where to keep the counters over a restart, {interface} gets replaced by the \
+               interface name, default $MUNIN_PLUGSTATE/if1sec_<interface>.state",
+    },
+    Opt {
+        name: "IF1SEC_UPDATE_RATE",
+        value: "1",
+        help: "Sample every that many seconds, default 1",
+    },
+    Opt {
+        name: "IF1SEC_DATA_SIZE",
+        value: "custom 1d, 1s for 1d, 5s for 2d, 10s for 7d, 1m for 1t, 5m for 1y",
+        help: "graph_data_size of all graphs: normal, huge or custom and the spans, default a \
+               day at the full rate, then coarser up to a year. More history needs more disk. \
+               Munin sizes the RRD files only when it creates them, remove them to resize",
+    },
+    Opt {
+        name: "IF1SEC_FETCHSIZE",
+        value: "65535",
+        help: "Output buffer size for fetch, default 65535",
+    },
+    Opt {
+        name: "IF1SEC_NETNS",
+        value: "blue",
+        help: "Network namespace to collect in, a name from ip netns or a path, needs \
+               CAP_SYS_ADMIN. Use with IF1SEC_SOURCE=procnetdev or netlink, sysfs stays in the \
+               namespace it got mounted in",
+    },
+    Opt {
+        name: "IF1SEC_NETNS_PID",
+        value: "1234",
+        help: "Read the counters from /proc/<pid>/net/dev, the network namespace of that \
+               process, without joining it. The other graphs still come from our own sysfs",
+    },
+    Opt {
+        name: "IF1SEC_ETHTOOL_STATS",
+        value: "rx_pause,tx_pause",
+        help: "Driver statistics (ethtool -S) to graph, comma separated, default \
+               rx_pause,tx_pause. Add :gauge for one that isn't a counter",
+    },
+    Opt {
+        name: "IF1SEC_RUN_AS",
+        value: "nobody",
+        help: "User to switch to once set up, if started as root",
+    },
+    Opt {
+        name: "IF1SEC_PLUGINS_DIR",
+        value: "/etc/munin/plugins",
+        help: "The munin plugins directory for (un)install, default /etc/munin/plugins",
+    },
+    Opt {
+        name: "IF1SEC_DEBUG_SOCKET",
+        value: "/run/munin/if1sec.sock",
+        help: "Unix socket the daemon dumps its current counters, rates and errors to, for \
+               every client",
+    },
+    Opt {
+        name: "IF1SEC_LOG_FORMAT",
+        value: "text",
+        help: "Log as text (default) or json, one object per line on stderr",
+    },
+    Opt {
+        name: "IF1SEC_QUIET",
+        value: "1",
+        help: "Set to 1 to only log errors, whatever RUST_LOG and MUNIN_DEBUG say",
+    },
+    Opt {
+        name: "RUST_LOG",
+        value: "warn",
+        help: "Log level, like debug, default warn",
+    },
+    Opt {
+        name: "MUNIN_DEBUG",
+        value: "1",
+        help: "Set to 1 by munin, logs at debug level, unless RUST_LOG is set",
+    },
+    Opt {
+        name: "IF1SEC_ONESHOT",
+        value: "1",
+        help: "Set to 1 to never start a daemon, every fetch reads and outputs one sample",
+    },
+];
+
+/// The default of the variable `name`, its value in [OPTIONS]. Only
+/// for variables having one, the others only have an example there.
+pub fn default(name: &str) -> &'static str {
+    match OPTIONS.iter().find(|opt| opt.name == name) {
+        Some(opt) => opt.value,
+        None => panic!("{} is missing in OPTIONS", name),
+    }
+}
+
+/// The variable `name`, made something by `parse`, if set and `parse`
+/// takes it. Else (complaining about a value it doesn't take) its
+/// [default].
+pub fn parse_or_default<T>(name: &str, parse: impl Fn(&str) -> Option<T>) -> T {
+    if let Ok(value) = std::env::var(name) {
+        match parse(&value) {
+            Some(parsed) => return parsed,
+            None => warn!(
+                "Ignoring invalid {} {:?}, using {}",
+                name,
+                value,
+                default(name)
+            ),
+        }
+    }
+    match parse(default(name)) {
+        Some(parsed) => parsed,
+        None => panic!("The default of {} is invalid", name),
+    }
+}
+
+/// Break `text` into lines of at most `width` characters, at spaces
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.len() + 1 + word.len() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    lines.push(line);
+    lines
+}
+
+/// Where the help text starts in --help, after the name, and where
+/// it ends
+const HELP_COLUMN: usize = 25;
+const HELP_WIDTH: usize = 76;
+
+/// Write the Environment part of --help to `out`
+pub fn write_help(out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "Environment:")?;
+    for opt in OPTIONS {
+        for (n, line) in wrap(opt.help, HELP_WIDTH - HELP_COLUMN).iter().enumerate() {
+            let name = if n == 0 { opt.name } else { "" };
+            writeln!(out, "  {:<width$}{}", name, line, width = HELP_COLUMN - 2)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write a sample munin plugin configuration with all of [OPTIONS],
+/// commented out, to `out`
+pub fn write_sample_config(out: &mut impl Write) -> io::Result<()> {
+    writeln!(
+        out,
+        "# Settings of if1sec, from if1sec --dump-config. Put it into"
+    )?;
+    writeln!(
+        out,
+        "# /etc/munin/plugin-conf.d/ and uncomment what you need, the"
+    )?;
+    writeln!(
+        out,
+        "# values are the defaults, or examples where there is none."
+    )?;
+    writeln!(out, "[if1sec_*]")?;
+    for opt in OPTIONS {
+        writeln!(out)?;
+        for line in wrap(opt.help, 70) {
+            writeln!(out, "# {}", line)?;
+        }
+        writeln!(out, "#env.{} {}", opt.name, opt.value)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("a bb ccc dd", 6), vec!["a bb", "ccc dd"]);
+        assert_eq!(wrap("", 6), vec![""]);
+        // Too long stays long
+        assert_eq!(wrap("abcdefgh ij", 6), vec!["abcdefgh", "ij"]);
+    }
+
+    #[test]
+    fn test_help() {
+        let mut out = Vec::new();
+        write_help(&mut out).unwrap();
+        let help = String::from_utf8(out).unwrap();
+        assert!(help.starts_with("Environment:\n  IF1SEC_INTERFACE       The interface "));
+        assert!(help.contains(
+            "\n  IF1SEC_READ_TIMEOUT_MS Give up reading the counters after that many ms,\n\
+             \x20                        default 500, 0 for no timeout\n"
+        ));
+        assert!(
+            help.lines().all(|line| line.len() <= HELP_WIDTH),
+            "{}",
+            help
+        );
+    }
+
+    #[test]
+    fn test_sample_config() {
+        let mut out = Vec::new();
+        write_sample_config(&mut out).unwrap();
+        let config = String::from_utf8(out).unwrap();
+        assert!(config.contains("\n[if1sec_*]\n"));
+        assert!(config.contains(
+            "\n# Counter width, auto (default), 32 or 64\n#env.IF1SEC_COUNTER_WIDTH auto\n"
+        ));
+        // Nothing active, all commented
+        assert!(config
+            .lines()
+            .all(|line| line.is_empty() || line.starts_with('#') || line == "[if1sec_*]"));
+        assert_eq!(config.matches("\n#env.").count(), OPTIONS.len());
+    }
+
+    /// The names of the environment variables the code (outside of
+    /// this file) reads, the string literals starting with IF1SEC_
+    fn used_variables() -> BTreeSet<String> {
+        let src = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+        let mut used = BTreeSet::new();
+        for entry in std::fs::read_dir(src).unwrap() {
+            let path = entry.unwrap().path();
+            if path.file_name().unwrap() == "options.rs" {
+                continue;
+            }
+            let code = std::fs::read_to_string(&path).unwrap();
+            for (at, _) in code.match_indices("\"IF1SEC_") {
+                let name: String = code[at + 1..]
+                    .chars()
+                    .take_while(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || *c == '_')
+                    .collect();
+                used.insert(name);
+            }
+        }
+        used
+    }
+
+    #[test]
+    fn test_default() {
+        assert_eq!(default("IF1SEC_CATEGORY"), "network");
+        assert_eq!(default("IF1SEC_UPDATE_RATE"), "1");
+    }
+
+    #[test]
+    #[should_panic(expected = "NO_SUCH_VARIABLE is missing in OPTIONS")]
+    fn test_default_missing() {
+        default("NO_SUCH_VARIABLE");
+    }
+
+    #[test]
+    fn test_all_there() {
+        let known: BTreeSet<String> = OPTIONS.iter().map(|opt| opt.name.to_string()).collect();
+        assert_eq!(known.len(), OPTIONS.len(), "Listed twice");
+        let used = used_variables();
+        assert!(used.len() > 30);
+        let missing: Vec<&String> = used.difference(&known).collect();
+        assert!(missing.is_empty(), "Not in OPTIONS: {:?}", missing);
+        // And nothing that isn't used any more
+        let unused: Vec<&String> = known
+            .iter()
+            .filter(|name| name.starts_with("IF1SEC_"))
+            .filter(|name| !used.contains(*name))
+            .collect();
+        assert!(unused.is_empty(), "Not used: {:?}", unused);
+    }
+}
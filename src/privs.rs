@@ -0,0 +1,78 @@
+//! Give up root once we are set up
+//!
+//! Reading the counters needs no privileges, but the setup may (say,
+//! entering a network namespace). With IF1SEC_RUN_AS set, and started
+//! as root, we switch to that user once the counter files are open.
+//! That user needs to be able to write to the munin plugin state
+//! directory, for the cache and pidfile of the daemon.
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use anyhow::{anyhow, Result};
+use log::{debug, info};
+use std::{ffi::CString, io};
+
+/// The user to run as, from IF1SEC_RUN_AS
+pub fn run_as() -> Option<String> {
+    std::env::var("IF1SEC_RUN_AS").ok()
+}
+
+/// Find the uid and gid of `user`
+fn lookup(user: &str) -> Result<(libc::uid_t, libc::gid_t)> {
+    let name = CString::new(user)?;
+    // SAFETY: name is NUL terminated, the result is only read before
+    // the next getpw* call
+    let pw = unsafe { libc::getpwnam(name.as_ptr()) };
+    if pw.is_null() {
+        return Err(anyhow!("No such user {}", user));
+    }
+    // SAFETY: not null, so a valid passwd entry
+    let pw = unsafe { &*pw };
+    Ok((pw.pw_uid, pw.pw_gid))
+}
+
+/// Turn an error return of a libc call into an error naming `what`
+fn check(ret: libc::c_int, what: &str) -> Result<()> {
+    if ret < 0 {
+        return Err(anyhow!("{} failed: {}", what, io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+/// Switch to `user`, with its groups. Only as root, anyone else can't
+/// and keeps their user. Reopening files may not work afterwards, so
+/// this needs a source that `keeps_open` its files.
+pub fn drop_to(user: &str, keeps_open: bool) -> Result<()> {
+    // SAFETY: no preconditions
+    if unsafe { libc::geteuid() } != 0 {
+        debug!("Not running as root, staying who we are");
+        return Ok(());
+    }
+    if !keeps_open {
+        return Err(anyhow!(
+            "IF1SEC_RUN_AS needs a source keeping its files open, like sysfs, not procnetdev"
+        ));
+    }
+    let (uid, gid) = lookup(user)?;
+    let name = CString::new(user)?;
+    // Groups first, after setuid we can't any more.
+    // SAFETY: plain syscalls, name is NUL terminated
+    unsafe {
+        // The type of the group differs between platforms
+        check(libc::initgroups(name.as_ptr(), gid as _), "initgroups")?;
+        check(libc::setgid(gid), "setgid")?;
+        check(libc::setuid(uid), "setuid")?;
+    }
+    info!("Now running as {} ({}:{})", user, uid, gid);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup("root").unwrap(), (0, 0));
+        assert!(lookup("if1sec-no-such-user").is_err());
+    }
+}
@@ -0,0 +1,817 @@
+//! Where we get the interface counters from
+//!
+//! The kernel exposes the statistics of an interface both in sysfs,
+//! one file per counter, and in /proc/net/dev, one line per interface
+//! with all counters. Either can be used, see [StatSource].
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+    time::Duration,
+};
+
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+use crate::getifaddrs::GetIfAddrs;
+#[cfg(target_os = "linux")]
+use crate::netlink::Netlink;
+use crate::options;
+
+/// The counters of one interface at one point in time. Bytes and
+/// packets are always there, everything else depends on the
+/// interface (and the source).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Counters {
+    /// Received bytes
+    pub rx_bytes: u64,
+    /// Transmitted bytes
+    pub tx_bytes: u64,
+    /// Received packets
+    pub rx_packets: u64,
+    /// Transmitted packets
+    pub tx_packets: u64,
+    /// Receive errors
+    pub rx_errors: Option<u64>,
+    /// Transmit errors
+    pub tx_errors: Option<u64>,
+    /// Dropped received packets
+    pub rx_dropped: Option<u64>,
+    /// Dropped transmit packets
+    pub tx_dropped: Option<u64>,
+    /// Received multicast packets
+    pub multicast: Option<u64>,
+    /// Collisions
+    pub collisions: Option<u64>,
+}
+
+/// Something we can read the counters of our interface from
+pub trait StatSource: std::fmt::Debug + Send {
+    /// Read the current counters
+    fn read_counters(&mut self) -> Result<Counters>;
+
+    /// Does it keep its files open, and so reads on after giving up
+    /// privileges?
+    fn keeps_open(&self) -> bool {
+        false
+    }
+
+    /// The acquire daemon runs, called before every sample it takes.
+    /// Threads started before munin-plugin forked it would be gone in
+    /// there, so this is where to start them.
+    fn daemon_running(&mut self) {}
+}
+
+/// Where to find the interfaces in sysfs, usually /sys/class/net,
+/// or as set in the environment variable IF1SEC_SYSFS_ROOT (say, in
+/// containers with the host sysfs mounted elsewhere).
+pub fn sysfs_root() -> PathBuf {
+    std::env::var_os("IF1SEC_SYSFS_ROOT")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(options::default("IF1SEC_SYSFS_ROOT")))
+}
+
+/// The sysfs file `name` of `interface` below `root`, like its
+/// operstate, if the interface has it.
+pub fn device_file(root: &Path, interface: &str, name: &str) -> Option<PathBuf> {
+    let path = root.join(interface).join(name);
+    if path.exists() {
+        Some(path)
+    } else {
+        debug!("{} not available for {}", name, interface);
+        None
+    }
+}
+
+/// The transmit queues of `interface` below `root`, as their number
+/// and where to read their backlog (bytes queued in the driver, see
+/// byte_queue_limits/inflight) from, sorted by number. Empty for
+/// interfaces without a queues/ tree, or without byte queue limits.
+pub fn tx_queues(root: &Path, interface: &str) -> Vec<(u64, PathBuf)> {
+    let queues = root.join(interface).join("queues");
+    let mut found: Vec<(u64, PathBuf)> = match std::fs::read_dir(&queues) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                let queue = name.to_str()?.strip_prefix("tx-")?.parse().ok()?;
+                let inflight = entry.path().join("byte_queue_limits").join("inflight");
+                inflight.exists().then_some((queue, inflight))
+            })
+            .collect(),
+        Err(e) => {
+            debug!("No queues for {}: {}", interface, e);
+            Vec::new()
+        }
+    };
+    found.sort();
+    found
+}
+
+/// The number of receive and transmit queues in the `queues`
+/// directory of an interface. Changes at runtime, with ethtool -L.
+pub fn queue_counts(queues: &Path) -> Result<(u64, u64)> {
+    let (mut rx, mut tx) = (0, 0);
+    for entry in std::fs::read_dir(queues)? {
+        let name = entry?.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("rx-") {
+            rx += 1;
+        } else if name.starts_with("tx-") {
+            tx += 1;
+        }
+    }
+    Ok((rx, tx))
+}
+
+/// The per CPU backlog statistics of the whole host
+pub const SOFTNET_STAT: &str = "/proc/net/softnet_stat";
+
+/// The packets dropped as the backlog of a CPU was full, summed over
+/// all CPUs, from `path` (see [SOFTNET_STAT]). One line per CPU, the
+/// second column of hex numbers, as that is what the kernel writes.
+pub fn softnet_dropped(path: &Path) -> Result<u64> {
+    let content = std::fs::read_to_string(path)?;
+    let mut dropped = 0u64;
+    for line in content.lines() {
+        let column = line
+            .split_whitespace()
+            .nth(1)
+            .ok_or_else(|| anyhow!("No dropped column in {:?}", line))?;
+        let value = u64::from_str_radix(column, 16)
+            .map_err(|e| anyhow!("Bad dropped column {:?}: {}", column, e))?;
+        dropped = dropped.wrapping_add(value);
+    }
+    Ok(dropped)
+}
+
+/// The [StatSource] to use without IF1SEC_SOURCE: sysfs on Linux,
+/// getifaddrs everywhere else
+pub const DEFAULT_SOURCE: &str = if cfg!(target_os = "linux") {
+    "sysfs"
+} else {
+    "getifaddrs"
+};
+
+/// Select the [StatSource] for `interface`, as set in the environment
+/// variable IF1SEC_SOURCE: `sysfs` (below `root`), `procnetdev`,
+/// `netlink` (falling back to sysfs, if netlink can't be used) or
+/// `getifaddrs`, [DEFAULT_SOURCE] if unset. With IF1SEC_NETNS_PID
+/// set it is the /proc/net/dev of that process, see [netns_pid_path].
+/// Reads are bound by [read_timeout].
+pub fn from_env(root: &Path, interface: &str) -> Result<Box<dyn StatSource>> {
+    let wanted = std::env::var("IF1SEC_SOURCE").ok();
+    let source = if let Some(path) = netns_pid_path() {
+        if wanted
+            .as_deref()
+            .is_some_and(|wanted| wanted != "procnetdev")
+        {
+            warn!("IF1SEC_NETNS_PID set, ignoring IF1SEC_SOURCE");
+        }
+        boxed(ProcNetDev::with_path(interface, path))?
+    } else {
+        let wanted = wanted.as_deref().unwrap_or(DEFAULT_SOURCE);
+        match open(wanted, root, interface) {
+            Some(source) => source?,
+            None => {
+                warn!("Unknown IF1SEC_SOURCE {}, using {}", wanted, DEFAULT_SOURCE);
+                open(DEFAULT_SOURCE, root, interface).expect("the default exists")?
+            }
+        }
+    };
+    Ok(match read_timeout() {
+        Some(timeout) => Box::new(Timed::new(source, timeout)),
+        None => source,
+    })
+}
+
+/// The /proc/net/dev of the process IF1SEC_NETNS_PID, if set. The
+/// kernel shows every process the interfaces of its own network
+/// namespace there, so this reads the counters of a container
+/// without joining its namespace (see [crate::netns]), and without
+/// the privileges for that.
+fn netns_pid_path() -> Option<PathBuf> {
+    let value = std::env::var("IF1SEC_NETNS_PID").ok()?;
+    match value.trim().parse::<u32>() {
+        Ok(pid) if pid > 0 => Some(PathBuf::from(format!("/proc/{}/net/dev", pid))),
+        _ => {
+            warn!("Ignoring invalid IF1SEC_NETNS_PID {:?}", value);
+            None
+        }
+    }
+}
+
+/// Box up a freshly opened `source`
+fn boxed<S: StatSource + 'static>(source: Result<S>) -> Result<Box<dyn StatSource>> {
+    Ok(Box::new(source?))
+}
+
+/// Open the [StatSource] called `name` for `interface`, None if there
+/// is no such source (on this platform)
+fn open(name: &str, root: &Path, interface: &str) -> Option<Result<Box<dyn StatSource>>> {
+    Some(match name {
+        "sysfs" => boxed(Sysfs::new(root, interface)),
+        "procnetdev" => boxed(ProcNetDev::new(interface)),
+        #[cfg(target_os = "linux")]
+        "netlink" => Netlink::new(interface)
+            .map(|source| Box::new(source) as Box<dyn StatSource>)
+            .or_else(|e| {
+                warn!("Can not use netlink, using sysfs: {}", e);
+                boxed(Sysfs::new(root, interface))
+            }),
+        #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+        "getifaddrs" => boxed(GetIfAddrs::new(interface)),
+        _ => return None,
+    })
+}
+
+/// How long reading the counters may take, from
+/// IF1SEC_READ_TIMEOUT_MS, 500ms by default. 0 turns it off, reading
+/// in our own thread.
+pub fn read_timeout() -> Option<Duration> {
+    let millis =
+        options::parse_or_default("IF1SEC_READ_TIMEOUT_MS", |value| value.trim().parse().ok());
+    (millis > 0).then(|| Duration::from_millis(millis))
+}
+
+/// Reading the counters took too long, most likely a hanging driver.
+/// Nothing to report this time, but maybe next time.
+#[derive(Debug)]
+pub struct ReadTimeout {
+    /// How long we waited
+    pub timeout: Duration,
+}
+
+impl std::fmt::Display for ReadTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Reading counters took longer than {}ms",
+            self.timeout.as_millis()
+        )
+    }
+}
+
+impl std::error::Error for ReadTimeout {}
+
+/// Read from another [StatSource] in a thread of its own, giving up
+/// after a timeout. A read hanging in the kernel can't be aborted, so
+/// while it hangs we don't ask again, and its late result, once it
+/// comes, is thrown away.
+///
+/// Until the acquire daemon runs, reads happen directly, without a
+/// timeout: only the forking thread survives the fork into the
+/// daemon, and a read hanging in a thread started before would take
+/// the source with it.
+#[derive(Debug)]
+pub struct Timed {
+    /// What we read from, until the reader thread takes it over
+    source: Option<Box<dyn StatSource>>,
+
+    /// The reader thread, once the daemon runs
+    worker: Option<Worker>,
+
+    /// How long to wait for a result
+    timeout: Duration,
+
+    /// Still waiting for a result from an earlier request
+    pending: bool,
+
+    /// See [StatSource::keeps_open] of the source in the thread
+    keeps_open: bool,
+}
+
+/// How we talk to the reader thread of [Timed]
+#[derive(Debug)]
+struct Worker {
+    /// Ask the reader thread for the counters
+    requests: Sender<()>,
+
+    /// What the reader thread read
+    results: Receiver<Result<Counters>>,
+}
+
+impl Timed {
+    /// Read from `source`, waiting at most `timeout` for every read
+    pub fn new(source: Box<dyn StatSource>, timeout: Duration) -> Self {
+        Self {
+            keeps_open: source.keeps_open(),
+            source: Some(source),
+            worker: None,
+            timeout,
+            pending: false,
+        }
+    }
+}
+
+impl Worker {
+    /// Start the reader thread, reading from `source`
+    fn start(mut source: Box<dyn StatSource>) -> Self {
+        let (requests, todo) = mpsc::channel::<()>();
+        let (done, results) = mpsc::channel();
+        // Ends with us dropping our end of the channels
+        std::thread::spawn(move || {
+            while todo.recv().is_ok() {
+                if done.send(source.read_counters()).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { requests, results }
+    }
+}
+
+impl StatSource for Timed {
+    fn read_counters(&mut self) -> Result<Counters> {
+        let timeout = self.timeout;
+        if let Some(source) = self.source.as_mut() {
+            return source.read_counters();
+        }
+        let worker = self
+            .worker
+            .as_ref()
+            .expect("the reader thread has the source");
+        if self.pending {
+            match worker.results.try_recv() {
+                Ok(_) => {
+                    debug!("Late read finished, dropping its result");
+                    self.pending = false;
+                }
+                Err(TryRecvError::Empty) => return Err(ReadTimeout { timeout }.into()),
+                Err(TryRecvError::Disconnected) => return Err(anyhow!("Reader thread is gone")),
+            }
+        }
+        worker
+            .requests
+            .send(())
+            .map_err(|_| anyhow!("Reader thread is gone"))?;
+        match worker.results.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => {
+                self.pending = true;
+                Err(ReadTimeout { timeout }.into())
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(anyhow!("Reader thread is gone")),
+        }
+    }
+
+    fn keeps_open(&self) -> bool {
+        self.keeps_open
+    }
+
+    fn daemon_running(&mut self) {
+        if let Some(source) = self.source.take() {
+            self.worker = Some(Worker::start(source));
+        }
+    }
+}
+
+/// A counter we read, but could not parse. Most likely a glitch
+/// while reading, not worth giving up for.
+#[derive(Debug)]
+pub struct BadSample {
+    /// What we read
+    pub raw: String,
+}
+
+impl std::fmt::Display for BadSample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Can not parse counter from {:?}", self.raw)
+    }
+}
+
+impl std::error::Error for BadSample {}
+
+/// Parse the content of a statistics file
+fn parse_counter(raw: &str) -> Result<u64> {
+    raw.trim().parse().map_err(|_| {
+        BadSample {
+            raw: raw.to_string(),
+        }
+        .into()
+    })
+}
+
+/// Read a counter from one of the statistics files
+pub fn read_counter(path: &Path) -> Result<u64> {
+    parse_counter(&std::fs::read_to_string(path)?)
+}
+
+/// Read a counter from an already opened statistics file.
+///
+/// sysfs changes the content of the file in place, so we seek
+/// back to the start and read it again, into `buf`. Compared to
+/// [std::fs::read_to_string] this saves the open, fstat and close
+/// syscalls, only lseek and the reads remain. The read goes
+/// through [Read::take], as reading straight from the [File]
+/// would fstat and lseek for a size hint first, and sysfs always
+/// claims 4096 bytes anyway.
+pub fn read_file_counter(file: &mut File, buf: &mut String) -> Result<u64> {
+    file.seek(SeekFrom::Start(0))?;
+    buf.clear();
+    file.take(64).read_to_string(buf)?;
+    parse_counter(buf)
+}
+
+/// Read the counters from sysfs, `<root>/<iface>/statistics/`. Every
+/// file gets opened once and kept open, see [read_file_counter].
+#[derive(Debug)]
+pub struct Sysfs {
+    /// Where to get TXBytes from
+    if_txbytes: File,
+
+    /// Where to get RXBytes from
+    if_rxbytes: File,
+
+    /// Where to get TXPackets from
+    if_txpackets: File,
+
+    /// Where to get RXPackets from
+    if_rxpackets: File,
+
+    /// Where to get TXErrors from, if the interface has them
+    if_txerrors: Option<File>,
+
+    /// Where to get RXErrors from, if the interface has them
+    if_rxerrors: Option<File>,
+
+    /// Where to get TXDropped from, if the interface has them
+    if_txdropped: Option<File>,
+
+    /// Where to get RXDropped from, if the interface has them
+    if_rxdropped: Option<File>,
+
+    /// Where to get received multicast packets from, if the interface
+    /// has them
+    if_multicast: Option<File>,
+
+    /// Where to get collisions from, if the interface has them
+    if_collisions: Option<File>,
+
+    /// Scratch buffer for reading any of them, reused every second
+    buf: String,
+}
+
+impl Sysfs {
+    /// Setup reading the counters of `interface` below `root`. Bytes
+    /// and packets must exist, everything else is optional.
+    pub fn new(root: &Path, interface: &str) -> Result<Self> {
+        let stats = root.join(interface).join("statistics");
+        let if_txbytes = Sysfs::open_stat(&stats, "tx_bytes", "TX")?;
+        let if_rxbytes = Sysfs::open_stat(&stats, "rx_bytes", "RX")?;
+        let if_txpackets = Sysfs::open_stat(&stats, "tx_packets", "TX packets")?;
+        let if_rxpackets = Sysfs::open_stat(&stats, "rx_packets", "RX packets")?;
+        Ok(Self {
+            if_txbytes,
+            if_rxbytes,
+            if_txpackets,
+            if_rxpackets,
+            if_rxerrors: Sysfs::optional_stat(&stats, "rx_errors"),
+            if_txerrors: Sysfs::optional_stat(&stats, "tx_errors"),
+            if_rxdropped: Sysfs::optional_stat(&stats, "rx_dropped"),
+            if_txdropped: Sysfs::optional_stat(&stats, "tx_dropped"),
+            if_multicast: Sysfs::optional_stat(&stats, "multicast"),
+            if_collisions: Sysfs::optional_stat(&stats, "collisions"),
+            buf: String::with_capacity(64),
+        })
+    }
+
+    /// Open the statistics file `name` in `stats`, which has to be
+    /// there, with the `what` counters for the error message.
+    fn open_stat(stats: &Path, name: &str, what: &str) -> Result<File> {
+        let path = stats.join(name);
+        if !Path::exists(&path) {
+            return Err(anyhow!("Can not find {} input file: {:?}", what, path));
+        }
+        File::open(&path).map_err(|e| anyhow!("Can not open {} input file {:?}: {}", what, path, e))
+    }
+
+    /// Open an optional statistics file in `stats`, None if the
+    /// interface does not provide it.
+    fn optional_stat(stats: &Path, name: &str) -> Option<File> {
+        match File::open(stats.join(name)) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                debug!("{} not available in {:?}: {}", name, stats, e);
+                None
+            }
+        }
+    }
+
+    /// Read an optional counter
+    fn read_optional(file: &mut Option<File>, buf: &mut String) -> Result<Option<u64>> {
+        file.as_mut()
+            .map(|file| read_file_counter(file, buf))
+            .transpose()
+    }
+}
+
+impl StatSource for Sysfs {
+    fn read_counters(&mut self) -> Result<Counters> {
+        let buf = &mut self.buf;
+        Ok(Counters {
+            rx_bytes: read_file_counter(&mut self.if_rxbytes, buf)?,
+            tx_bytes: read_file_counter(&mut self.if_txbytes, buf)?,
+            rx_packets: read_file_counter(&mut self.if_rxpackets, buf)?,
+            tx_packets: read_file_counter(&mut self.if_txpackets, buf)?,
+            rx_errors: Sysfs::read_optional(&mut self.if_rxerrors, buf)?,
+            tx_errors: Sysfs::read_optional(&mut self.if_txerrors, buf)?,
+            rx_dropped: Sysfs::read_optional(&mut self.if_rxdropped, buf)?,
+            tx_dropped: Sysfs::read_optional(&mut self.if_txdropped, buf)?,
+            multicast: Sysfs::read_optional(&mut self.if_multicast, buf)?,
+            collisions: Sysfs::read_optional(&mut self.if_collisions, buf)?,
+        })
+    }
+
+    /// All its counters, opened before giving up privileges
+    fn keeps_open(&self) -> bool {
+        true
+    }
+}
+
+/// Read the counters from /proc/net/dev, all of them with one open
+/// and read.
+#[derive(Debug)]
+pub struct ProcNetDev {
+    /// Which interface to look for
+    interface: String,
+
+    /// Where to read from
+    path: PathBuf,
+
+    /// Scratch buffer for reading, reused every second
+    buf: String,
+}
+
+impl ProcNetDev {
+    /// Setup reading the counters of `interface`, which has to be
+    /// listed in /proc/net/dev.
+    pub fn new(interface: &str) -> Result<Self> {
+        ProcNetDev::with_path(interface, PathBuf::from("/proc/net/dev"))
+    }
+
+    /// Setup reading the counters of `interface` from `path`, in the
+    /// format of /proc/net/dev, like /proc/<pid>/net/dev
+    pub fn with_path(interface: &str, path: PathBuf) -> Result<Self> {
+        let mut source = Self {
+            interface: interface.to_string(),
+            path,
+            buf: String::with_capacity(4096),
+        };
+        source.read_counters()?;
+        Ok(source)
+    }
+}
+
+impl StatSource for ProcNetDev {
+    fn read_counters(&mut self) -> Result<Counters> {
+        self.buf.clear();
+        // Gone for good with the process of IF1SEC_NETNS_PID
+        File::open(&self.path)
+            .map_err(|e| anyhow!("Can not open {}: {}", self.path.display(), e))?
+            .read_to_string(&mut self.buf)?;
+        parse_proc_net_dev(&self.buf, &self.interface).ok_or_else(|| {
+            anyhow!(
+                "Can not find interface {} in {:?}",
+                self.interface,
+                self.path
+            )
+        })
+    }
+}
+
+/// Find the line for `interface` in the content of /proc/net/dev and
+/// parse its counters. The interface name is right aligned, and long
+/// counters can follow the colon without any space.
+pub fn parse_proc_net_dev(content: &str, interface: &str) -> Option<Counters> {
+    // The first two lines are headers and have no colon
+    let values: Vec<u64> = content
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim() == interface)?
+        .1
+        .split_whitespace()
+        .map(|value| value.parse())
+        .collect::<Result<_, _>>()
+        .ok()?;
+    // Receive: bytes packets errs drop fifo frame compressed multicast
+    // Transmit: bytes packets errs drop fifo colls carrier compressed
+    if values.len() < 16 {
+        return None;
+    }
+    Some(Counters {
+        rx_bytes: values[0],
+        rx_packets: values[1],
+        rx_errors: Some(values[2]),
+        rx_dropped: Some(values[3]),
+        multicast: Some(values[7]),
+        tx_bytes: values[8],
+        tx_packets: values[9],
+        tx_errors: Some(values[10]),
+        tx_dropped: Some(values[11]),
+        collisions: Some(values[13]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Captured from a real system, with eth0 having counters long
+    /// enough to touch the colon
+    const PROC_NET_DEV: &str = "Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo: 6657021     702    0    0    0     0          0         0  6657021     702    0    0    0     0       0          0
+  eth0:12345678901 9876543    1    2    0     0          0        17 60663123   70312    3    4    0     5       0          0
+  br_lan: 4317629     644    0    0    0     0          0         0    60663     703    0    0    0     0       0          0
+";
+
+    #[test]
+    fn test_parse_proc_net_dev() {
+        let eth0 = parse_proc_net_dev(PROC_NET_DEV, "eth0").unwrap();
+        assert_eq!(
+            eth0,
+            Counters {
+                rx_bytes: 12345678901,
+                tx_bytes: 60663123,
+                rx_packets: 9876543,
+                tx_packets: 70312,
+                rx_errors: Some(1),
+                tx_errors: Some(3),
+                rx_dropped: Some(2),
+                tx_dropped: Some(4),
+                multicast: Some(17),
+                collisions: Some(5),
+            }
+        );
+        let br_lan = parse_proc_net_dev(PROC_NET_DEV, "br_lan").unwrap();
+        assert_eq!(br_lan.rx_bytes, 4317629);
+        assert_eq!(br_lan.tx_packets, 703);
+        assert!(parse_proc_net_dev(PROC_NET_DEV, "eth").is_none());
+        assert!(parse_proc_net_dev(PROC_NET_DEV, "face |bytes").is_none());
+    }
+
+    #[test]
+    fn test_tx_queues() {
+        let dir = tempfile::tempdir().unwrap();
+        let queues = dir.path().join("eth0").join("queues");
+        for queue in ["tx-0", "tx-1", "tx-10", "tx-2", "rx-0"] {
+            let bql = queues.join(queue).join("byte_queue_limits");
+            std::fs::create_dir_all(&bql).unwrap();
+            std::fs::write(bql.join("inflight"), "0\n").unwrap();
+        }
+        // No byte queue limits
+        std::fs::create_dir_all(queues.join("tx-3")).unwrap();
+
+        let found: Vec<u64> = tx_queues(dir.path(), "eth0")
+            .into_iter()
+            .map(|(queue, _)| queue)
+            .collect();
+        assert_eq!(found, vec![0, 1, 2, 10]);
+        assert!(tx_queues(dir.path(), "eth1").is_empty());
+        // All of them count, byte queue limits or not
+        assert_eq!(queue_counts(&queues).unwrap(), (1, 5));
+        assert!(queue_counts(&dir.path().join("eth1/queues")).is_err());
+    }
+
+    #[test]
+    fn test_softnet_dropped() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            tmp.path(),
+            "00003a1f 0000000a 00000002 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000\n\
+             0001b2c4 000000ff 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000001 00000000 00000001\n",
+        )
+        .unwrap();
+        assert_eq!(softnet_dropped(tmp.path()).unwrap(), 10 + 255);
+        std::fs::write(tmp.path(), "00003a1f\n").unwrap();
+        assert!(softnet_dropped(tmp.path()).is_err());
+        std::fs::write(tmp.path(), "00003a1f 0000zz00\n").unwrap();
+        assert!(softnet_dropped(tmp.path()).is_err());
+    }
+
+    #[test]
+    fn test_read_file_counter() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(tmp.path(), "1234\n").unwrap();
+        let mut file = File::open(tmp.path()).unwrap();
+        let mut buf = String::new();
+        assert_eq!(read_file_counter(&mut file, &mut buf).unwrap(), 1234);
+
+        // Rewrite in place, as sysfs does, and read again from the
+        // same handle
+        std::fs::write(tmp.path(), "98765\n").unwrap();
+        assert_eq!(read_file_counter(&mut file, &mut buf).unwrap(), 98765);
+        std::fs::write(tmp.path(), "42\n").unwrap();
+        assert_eq!(read_file_counter(&mut file, &mut buf).unwrap(), 42);
+    }
+
+    #[test]
+    fn test_sysfs() {
+        let dir = tempfile::tempdir().unwrap();
+        let stats = dir.path().join("fake0").join("statistics");
+        std::fs::create_dir_all(&stats).unwrap();
+        let write = |counters: &[(&str, u64)]| {
+            for (name, value) in counters {
+                std::fs::write(stats.join(name), format!("{}\n", value)).unwrap();
+            }
+        };
+        write(&[
+            ("rx_bytes", 1000),
+            ("tx_bytes", 2000),
+            ("rx_packets", 10),
+            ("tx_packets", 20),
+            ("rx_errors", 1),
+        ]);
+        let mut source = Sysfs::new(dir.path(), "fake0").unwrap();
+        let counters = source.read_counters().unwrap();
+        assert_eq!(counters.rx_packets, 10);
+        assert_eq!(counters.rx_errors, Some(1));
+        assert_eq!(counters.tx_errors, None);
+
+        // Every file read again from the handles opened at the start
+        write(&[
+            ("rx_bytes", 1500),
+            ("tx_bytes", 2500),
+            ("rx_packets", 15),
+            ("tx_packets", 25),
+            ("rx_errors", 2),
+        ]);
+        let counters = source.read_counters().unwrap();
+        assert_eq!(
+            (counters.rx_bytes, counters.tx_bytes, counters.rx_packets),
+            (1500, 2500, 15)
+        );
+        assert_eq!(counters.tx_packets, 25);
+        assert_eq!(counters.rx_errors, Some(2));
+    }
+
+    #[test]
+    fn test_proc_net_dev_pid() {
+        // Our own view, lo is always there
+        let path = PathBuf::from(format!("/proc/{}/net/dev", std::process::id()));
+        let mut source = ProcNetDev::with_path("lo", path).unwrap();
+        assert!(source.read_counters().is_ok());
+        // A process that can't exist
+        let e = ProcNetDev::with_path("lo", PathBuf::from("/proc/4294967295/net/dev")).unwrap_err();
+        assert!(e.to_string().contains("/proc/4294967295/net/dev"), "{}", e);
+    }
+
+    #[test]
+    fn test_timed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dev");
+        std::fs::write(&path, PROC_NET_DEV).unwrap();
+        let source = ProcNetDev {
+            interface: String::from("eth0"),
+            path,
+            buf: String::new(),
+        };
+        let mut timed = Timed::new(Box::new(source), Duration::from_millis(500));
+        // Still setting up, before the fork, no thread yet
+        assert_eq!(timed.read_counters().unwrap().rx_bytes, 12345678901);
+        assert!(timed.worker.is_none());
+
+        timed.daemon_running();
+        assert_eq!(timed.read_counters().unwrap().rx_bytes, 12345678901);
+        assert!(timed.worker.is_some());
+        // Only one thread
+        timed.daemon_running();
+        assert!(timed.source.is_none());
+        assert_eq!(timed.read_counters().unwrap().rx_bytes, 12345678901);
+    }
+
+    #[test]
+    fn test_timed_hanging() {
+        let dir = tempfile::tempdir().unwrap();
+        let fifo = dir.path().join("fifo");
+        let name = std::ffi::CString::new(fifo.as_os_str().as_encoded_bytes()).unwrap();
+        // SAFETY: name is a valid NUL terminated path
+        assert_eq!(unsafe { libc::mkfifo(name.as_ptr(), 0o600) }, 0);
+        // Opening a FIFO blocks until there is a writer, there is
+        // none, for now
+        let source = ProcNetDev {
+            interface: String::from("eth0"),
+            path: fifo.clone(),
+            buf: String::new(),
+        };
+        let mut timed = Timed::new(Box::new(source), Duration::from_millis(50));
+        timed.daemon_running();
+        let e = timed.read_counters().unwrap_err();
+        assert!(e.is::<ReadTimeout>(), "{}", e);
+        // Still hanging, no new read
+        assert!(timed.read_counters().unwrap_err().is::<ReadTimeout>());
+
+        // Unblock it, with nothing to read
+        drop(File::options().write(true).open(&fifo).unwrap());
+        std::thread::sleep(Duration::from_millis(50));
+        let e = timed.read_counters().unwrap_err();
+        // The late result gets dropped, this is a fresh read, which
+        // blocks again
+        assert!(e.is::<ReadTimeout>(), "{}", e);
+    }
+}
@@ -0,0 +1,184 @@
+//! Keep the byte counters over a restart
+//!
+//! The values we hand to munin are the unwrapped counters, ever
+//! increasing even if the kernel ones are only 32bit wide. A fresh
+//! process starts over at the raw values, which for munin looks like a
+//! counter going backwards, and the sample gets lost. So the last
+//! values of every sample are kept in a small state file, and a new
+//! process carries on from there, if they are recent enough.
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use anyhow::Result;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use crate::wrap::WrapCounter;
+
+/// Seconds after which a state file is too old to carry on from
+const MAX_AGE: u64 = 10;
+
+/// Where to keep the state for `interface`, one of `several` the
+/// plugin does, or not. See [state_path], for IF1SEC_STATE_FILE and
+/// MUNIN_PLUGSTATE.
+pub fn state_file(interface: &str, several: bool) -> Option<PathBuf> {
+    state_path(
+        std::env::var("IF1SEC_STATE_FILE").ok().as_deref(),
+        std::env::var_os("MUNIN_PLUGSTATE").as_deref(),
+        interface,
+        several,
+    )
+}
+
+/// Where to keep the state for `interface`. The `wanted` file wins,
+/// with `{interface}` in it replaced by the interface name. Without
+/// that, if the plugin does `several` interfaces, they would all
+/// share it, so the name gets appended as `_<interface>`. Otherwise
+/// below the munin plugin state directory `plugstate`, if munin tells
+/// us about one.
+fn state_path(
+    wanted: Option<&str>,
+    plugstate: Option<&OsStr>,
+    interface: &str,
+    several: bool,
+) -> Option<PathBuf> {
+    if let Some(path) = wanted {
+        return Some(PathBuf::from(if path.contains("{interface}") {
+            path.replace("{interface}", interface)
+        } else if several {
+            format!("{}_{}", path, interface)
+        } else {
+            path.to_string()
+        }));
+    }
+    Some(Path::new(plugstate?).join(format!("if1sec_{}.state", interface)))
+}
+
+/// The byte counters of one sample
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    /// When the sample got taken
+    pub epoch: u64,
+    /// Received bytes
+    pub rx_bytes: WrapCounter,
+    /// Transmitted bytes
+    pub tx_bytes: WrapCounter,
+}
+
+impl State {
+    /// Read the state from `path`, if there is a usable one, not older
+    /// than [MAX_AGE] seconds at `now`.
+    pub fn load(path: &Path, now: u64) -> Option<Self> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                debug!("No state in {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        let state: State = match serde_json::from_str(&content) {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("Ignoring broken state file {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        if now.saturating_sub(state.epoch) > MAX_AGE || state.epoch > now {
+            debug!("Ignoring stale state from {}", state.epoch);
+            return None;
+        }
+        Some(state)
+    }
+
+    /// Write the state to `path`. Goes through a temporary file, so a
+    /// reader never sees half of it.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, serde_json::to_string(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wrap::CounterWidth;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("if1sec_eth0.state");
+        let mut state = State {
+            epoch: 1000,
+            ..Default::default()
+        };
+        state.rx_bytes.update(12345, CounterWidth::Auto);
+        state.tx_bytes.update(67890, CounterWidth::Auto);
+        state.save(&path).unwrap();
+
+        assert_eq!(State::load(&path, 1002), Some(state));
+        // Too old, or from the future
+        assert_eq!(State::load(&path, 1000 + MAX_AGE + 1), None);
+        assert_eq!(State::load(&path, 999), None);
+
+        std::fs::write(&path, "junk").unwrap();
+        assert_eq!(State::load(&path, 1002), None);
+        assert_eq!(State::load(&dir.path().join("missing"), 1002), None);
+    }
+
+    #[test]
+    fn test_state_path() {
+        let plugstate = Some(OsStr::new("/var/lib/munin-node/plugin-state/nobody"));
+        assert_eq!(
+            state_path(None, plugstate, "eth0", false),
+            Some(PathBuf::from(
+                "/var/lib/munin-node/plugin-state/nobody/if1sec_eth0.state"
+            ))
+        );
+        assert_eq!(state_path(None, None, "eth0", false), None);
+        let wanted = Some("/run/if1sec.state");
+        assert_eq!(
+            state_path(wanted, plugstate, "eth0", false),
+            Some(PathBuf::from("/run/if1sec.state"))
+        );
+        assert_eq!(
+            state_path(Some("/run/{interface}/if1sec.state"), None, "eth0", false),
+            Some(PathBuf::from("/run/eth0/if1sec.state"))
+        );
+        assert_eq!(
+            state_path(Some("/run/if1sec_{interface}"), None, "eth1", true),
+            Some(PathBuf::from("/run/if1sec_eth1"))
+        );
+    }
+
+    /// Two interfaces of one plugin, with one IF1SEC_STATE_FILE, each
+    /// keep their own state
+    #[test]
+    fn test_state_path_several() {
+        let dir = tempfile::tempdir().unwrap();
+        let wanted = dir.path().join("if1sec.state");
+        let wanted = wanted.to_str();
+        let eth0 = state_path(wanted, None, "eth0", true).unwrap();
+        let eth1 = state_path(wanted, None, "eth1", true).unwrap();
+        assert_ne!(eth0, eth1);
+
+        let mut states = Vec::new();
+        for (path, bytes) in [(&eth0, 1000), (&eth1, 3_000_000)] {
+            let mut state = State {
+                epoch: 1000,
+                ..Default::default()
+            };
+            state.rx_bytes.update(bytes, CounterWidth::Auto);
+            state.tx_bytes.update(bytes * 2, CounterWidth::Auto);
+            state.save(path).unwrap();
+            states.push(state);
+        }
+        assert_eq!(State::load(&eth0, 1001), Some(states[0]));
+        assert_eq!(State::load(&eth1, 1001), Some(states[1]));
+    }
+}
@@ -0,0 +1,176 @@
+//! Keep an eye on the timing of our samples
+//!
+//! munin-plugin calls acquire once a second and hands us the wall
+//! clock epoch to stamp the values with. Scheduler hiccups can make a
+//! call arrive late, or two calls land in the same second, which munin
+//! would take as two values for one timestamp. The monotonic clock
+//! tells us how late we really are, the epoch whether a second got
+//! sampled already.
+//!
+//! After a suspend (or a paused VM) the epoch jumps forward while the
+//! counters did not move on, the first sample after such a gap gives
+//! a misleading rate, and is better left out.
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use log::{debug, warn};
+use std::time::{Duration, Instant};
+
+/// How often we expect to be called
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// How late a call may be before we tell about it
+const DRIFT_TOLERANCE: Duration = Duration::from_millis(500);
+
+/// Sampling intervals between two samples, above which we consider
+/// it a gap, not just a late call
+const MAX_GAP: u64 = 5;
+
+/// What to do with a call, see [SampleClock::check]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tick {
+    /// Take a sample
+    Sample,
+    /// Sampled recently enough already, nothing lost
+    Early,
+    /// First after a gap, skipped, the rate would be misleading
+    Gap,
+}
+
+/// Tracks when we sampled last
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleClock {
+    /// Sample every this many seconds, munins update_rate
+    rate: u64,
+
+    /// When we were called last, by the monotonic clock
+    last_instant: Option<Instant>,
+
+    /// The epoch we sampled last
+    last_epoch: Option<u64>,
+}
+
+impl Default for SampleClock {
+    /// Sample every second
+    fn default() -> Self {
+        SampleClock::new(1)
+    }
+}
+
+impl SampleClock {
+    /// Sample every `rate` seconds. We still get called every
+    /// second, the calls in between get skipped.
+    pub fn new(rate: u64) -> Self {
+        SampleClock {
+            rate: rate.max(1),
+            last_instant: None,
+            last_epoch: None,
+        }
+    }
+
+    /// Check a sample taken at `now`, for the wall clock `epoch`.
+    /// Logs if it comes late, tells if it should be skipped, as we
+    /// sampled that second already (or another one less than our
+    /// rate ago), or it is the first after a gap.
+    pub fn check(&mut self, now: Instant, epoch: u64) -> Tick {
+        if let Some(last) = self.last_instant {
+            let elapsed = now.saturating_duration_since(last);
+            if elapsed > INTERVAL + DRIFT_TOLERANCE {
+                warn!("Sample {}ms late", (elapsed - INTERVAL).as_millis());
+            }
+        }
+        self.last_instant = Some(now);
+
+        if let Some(last) = self.last_epoch {
+            if epoch < last + self.rate {
+                debug!("Sampled {} already, skipping {}", last, epoch);
+                return Tick::Early;
+            }
+            if epoch - last > MAX_GAP * self.rate {
+                warn!(
+                    "Gap of {}s since the last sample (suspend?), skipping this one",
+                    epoch - last
+                );
+                self.last_epoch = Some(epoch);
+                return Tick::Gap;
+            }
+        }
+        self.last_epoch = Some(epoch);
+        Tick::Sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_second() {
+        let start = Instant::now();
+        let mut clock = SampleClock::default();
+        assert_eq!(clock.check(start, 100), Tick::Sample);
+        // Early call, still the same second
+        assert_eq!(
+            clock.check(start + Duration::from_millis(400), 100),
+            Tick::Early
+        );
+        assert_eq!(
+            clock.check(start + Duration::from_millis(1000), 101),
+            Tick::Sample
+        );
+        // Late, skipping a second, is still a sample
+        assert_eq!(
+            clock.check(start + Duration::from_millis(3100), 103),
+            Tick::Sample
+        );
+        // Wall clock stepped back
+        assert_eq!(
+            clock.check(start + Duration::from_millis(4100), 102),
+            Tick::Early
+        );
+        assert_eq!(
+            clock.check(start + Duration::from_millis(5100), 104),
+            Tick::Sample
+        );
+    }
+
+    #[test]
+    fn test_rate() {
+        let start = Instant::now();
+        let mut clock = SampleClock::new(2);
+        assert_eq!(clock.check(start, 100), Tick::Sample);
+        assert_eq!(
+            clock.check(start + Duration::from_secs(1), 101),
+            Tick::Early
+        );
+        assert_eq!(
+            clock.check(start + Duration::from_secs(2), 102),
+            Tick::Sample
+        );
+        // A late one, still sampled, and not yet a gap
+        assert_eq!(
+            clock.check(start + Duration::from_secs(5), 105),
+            Tick::Sample
+        );
+        assert_eq!(
+            clock.check(start + Duration::from_secs(12), 112),
+            Tick::Sample
+        );
+    }
+
+    #[test]
+    fn test_gap() {
+        let start = Instant::now();
+        let mut clock = SampleClock::default();
+        assert_eq!(clock.check(start, 100), Tick::Sample);
+        assert_eq!(
+            clock.check(start + Duration::from_secs(5), 105),
+            Tick::Sample
+        );
+        // Back from suspend, skip the first, go on with the next
+        assert_eq!(clock.check(start + Duration::from_secs(6), 3700), Tick::Gap);
+        assert_eq!(
+            clock.check(start + Duration::from_secs(7), 3701),
+            Tick::Sample
+        );
+    }
+}
@@ -0,0 +1,482 @@
+//! Signal strength and bitrate of Wi-Fi interfaces
+//!
+//! Throughput alone doesn't tell about radio trouble. The signal
+//! level comes from /proc/net/wireless, the bitrate from the wireless
+//! extensions ioctl SIOCGIWRATE, which cfg80211 still answers. Only
+//! built with the `wireless` feature.
+//!
+//! On an access point the traffic of the interface is that of all
+//! its clients together. nl80211 (over generic netlink) knows the
+//! bytes of each station, [Stations] asks for them.
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use log::debug;
+use std::{
+    cell::Cell,
+    ffi::CString,
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::Path,
+};
+
+use crate::ethtool::ioctl_socket;
+use crate::netlink::{align, attributes, transact, NLM_F_DUMP, NLM_F_REQUEST};
+
+/// The ioctl request to get the bitrate
+const SIOCGIWRATE: libc::c_ulong = 0x8B21;
+
+/// struct iw_param from linux/wireless.h
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct IwParam {
+    value: i32,
+    fixed: u8,
+    disabled: u8,
+    flags: u16,
+}
+
+/// struct iwreq from linux/wireless.h, with only the iw_param member
+/// of the union, padded to the full size.
+#[repr(C)]
+struct IwReq {
+    ifr_name: [libc::c_char; libc::IFNAMSIZ],
+    param: IwParam,
+    _pad: [u8; 8],
+}
+
+/// The generic netlink protocol, from linux/netlink.h
+const NETLINK_GENERIC: libc::c_int = 16;
+
+/// Size of struct genlmsghdr
+const GENL_HDRLEN: usize = 4;
+
+/// The generic netlink controller, to find nl80211, from
+/// linux/genetlink.h
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+/// Commands and attributes from linux/nl80211.h
+const NL80211_CMD_GET_INTERFACE: u8 = 5;
+const NL80211_CMD_GET_STATION: u8 = 17;
+const NL80211_ATTR_IFINDEX: u16 = 3;
+const NL80211_ATTR_IFTYPE: u16 = 5;
+const NL80211_ATTR_MAC: u16 = 6;
+const NL80211_ATTR_STA_INFO: u16 = 21;
+const NL80211_IFTYPE_AP: u32 = 3;
+const NL80211_STA_INFO_RX_BYTES: u16 = 2;
+const NL80211_STA_INFO_TX_BYTES: u16 = 3;
+const NL80211_STA_INFO_RX_BYTES64: u16 = 23;
+const NL80211_STA_INFO_TX_BYTES64: u16 = 24;
+
+/// Is `interface` below `root` a Wi-Fi interface?
+pub fn is_wireless(root: &Path, interface: &str) -> bool {
+    let dir = root.join(interface);
+    dir.join("wireless").exists() || dir.join("phy80211").exists()
+}
+
+/// Find the signal level (dBm) of `interface` in the content of
+/// /proc/net/wireless
+pub fn parse_proc_net_wireless(content: &str, interface: &str) -> Option<i64> {
+    content.lines().skip(2).find_map(|line| {
+        let (name, rest) = line.split_once(':')?;
+        if name.trim() != interface {
+            return None;
+        }
+        // status, link quality, signal level, ...
+        let level = rest.split_whitespace().nth(2)?;
+        level.trim_end_matches('.').parse().ok()
+    })
+}
+
+/// Read the signal level (dBm) of `interface`, None if there isn't
+/// any (say, not associated).
+pub fn signal(interface: &str) -> Option<i64> {
+    let content = std::fs::read_to_string("/proc/net/wireless").ok()?;
+    parse_proc_net_wireless(&content, interface)
+}
+
+/// Query the current bitrate of `interface` in Mbps from the driver
+pub fn bitrate(interface: &str) -> Option<u64> {
+    match query_bitrate(interface) {
+        Ok(rate) if rate > 0 => Some(rate as u64 / 1_000_000),
+        Ok(_) => None,
+        Err(e) => {
+            debug!("SIOCGIWRATE failed for {}: {}", interface, e);
+            None
+        }
+    }
+}
+
+/// Run SIOCGIWRATE on `interface`, returns the bitrate in bit/s
+fn query_bitrate(interface: &str) -> io::Result<i32> {
+    if interface.len() >= libc::IFNAMSIZ {
+        return Err(io::Error::from(io::ErrorKind::InvalidInput));
+    }
+    let mut iwr = IwReq {
+        ifr_name: [0; libc::IFNAMSIZ],
+        param: IwParam::default(),
+        _pad: [0; 8],
+    };
+    for (dst, src) in iwr.ifr_name.iter_mut().zip(interface.bytes()) {
+        *dst = src as libc::c_char;
+    }
+    let sock = ioctl_socket()?;
+
+    // SAFETY: iwr is a properly sized and NUL terminated iwreq,
+    // outliving the call.
+    let ret = unsafe { libc::ioctl(sock.as_raw_fd(), SIOCGIWRATE as _, &mut iwr) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(iwr.param.value)
+}
+
+/// One station associated with an access point, with the bytes
+/// received from it and sent to it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Station {
+    /// Its MAC address
+    pub mac: [u8; 6],
+    /// Bytes received from the station
+    pub rx_bytes: u64,
+    /// Bytes sent to the station
+    pub tx_bytes: u64,
+}
+
+impl Station {
+    /// The MAC address as usual, aa:bb:cc:dd:ee:ff
+    pub fn address(&self) -> String {
+        self.mac
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+
+    /// The MAC address for a munin field name, aabbccddeeff
+    pub fn field(&self) -> String {
+        self.mac
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// The u16, u32 or u64 in an attribute payload
+fn attribute_value(payload: &[u8]) -> Option<u64> {
+    match payload.len() {
+        2 => Some(u16::from_ne_bytes(payload.try_into().ok()?).into()),
+        4 => Some(u32::from_ne_bytes(payload.try_into().ok()?).into()),
+        8 => Some(u64::from_ne_bytes(payload.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+/// The station in the attributes of a NL80211_CMD_NEW_STATION
+/// message. The 64bit byte counters if there, older kernels only
+/// have 32bit ones.
+fn parse_station(attrs: &[u8]) -> Option<Station> {
+    let mut station = Station::default();
+    let mut info = None;
+    for (kind, payload) in attributes(attrs) {
+        match kind {
+            NL80211_ATTR_MAC => station.mac = payload.try_into().ok()?,
+            NL80211_ATTR_STA_INFO => info = Some(payload),
+            _ => {}
+        }
+    }
+    let (mut rx32, mut tx32, mut rx64, mut tx64) = (None, None, None, None);
+    for (kind, payload) in attributes(info?) {
+        let value = attribute_value(payload);
+        match kind {
+            NL80211_STA_INFO_RX_BYTES => rx32 = value,
+            NL80211_STA_INFO_TX_BYTES => tx32 = value,
+            NL80211_STA_INFO_RX_BYTES64 => rx64 = value,
+            NL80211_STA_INFO_TX_BYTES64 => tx64 = value,
+            _ => {}
+        }
+    }
+    station.rx_bytes = rx64.or(rx32)?;
+    station.tx_bytes = tx64.or(tx32)?;
+    Some(station)
+}
+
+/// Build a generic netlink request for `family`, numbered `seq`, with
+/// the command `cmd` and the attributes `attrs`
+fn genl_request(family: u16, flags: u16, seq: u32, cmd: u8, attrs: &[(u16, &[u8])]) -> Vec<u8> {
+    let mut msg = Vec::new();
+    // struct nlmsghdr, the length goes in at the end
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg.extend_from_slice(&family.to_ne_bytes());
+    msg.extend_from_slice(&(NLM_F_REQUEST | flags).to_ne_bytes());
+    msg.extend_from_slice(&seq.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    // struct genlmsghdr: command, version, reserved
+    msg.extend_from_slice(&[cmd, 1, 0, 0]);
+    for (kind, payload) in attrs {
+        msg.extend_from_slice(&((4 + payload.len()) as u16).to_ne_bytes());
+        msg.extend_from_slice(&kind.to_ne_bytes());
+        msg.extend_from_slice(payload);
+        msg.resize(align(msg.len()), 0);
+    }
+    let len = msg.len() as u32;
+    msg[..4].copy_from_slice(&len.to_ne_bytes());
+    msg
+}
+
+/// Asks nl80211 for the stations associated with an access point
+#[derive(Debug)]
+pub struct Stations {
+    /// Which interface, for messages
+    interface: String,
+
+    /// Its ifindex, what nl80211 wants
+    ifindex: u32,
+
+    /// The generic netlink family of nl80211
+    family: u16,
+
+    /// Our generic netlink socket
+    socket: OwnedFd,
+
+    /// Number of the last request. config() only gets to look, so
+    /// it has to change without a mutable borrow.
+    seq: Cell<u32>,
+}
+
+impl Stations {
+    /// Setup asking about the stations of `interface`, if it is an
+    /// access point. None for anything else, or without nl80211.
+    pub fn ap(interface: &str) -> Option<Self> {
+        let stations = match Stations::new(interface) {
+            Ok(stations) => stations,
+            Err(e) => {
+                debug!("No nl80211 for {}: {}", interface, e);
+                return None;
+            }
+        };
+        match stations.is_ap() {
+            Ok(true) => Some(stations),
+            Ok(false) => {
+                debug!("{} is no access point, no stations", interface);
+                None
+            }
+            Err(e) => {
+                debug!("Can not ask nl80211 about {}: {}", interface, e);
+                None
+            }
+        }
+    }
+
+    /// Open the socket and find nl80211
+    fn new(interface: &str) -> io::Result<Self> {
+        let name = CString::new(interface)?;
+        // SAFETY: name is NUL terminated
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: plain socket call, the fd gets owned (and closed)
+        // below
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                NETLINK_GENERIC,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut stations = Stations {
+            interface: interface.to_string(),
+            ifindex,
+            family: GENL_ID_CTRL,
+            // SAFETY: fd is a freshly opened socket nobody else owns
+            socket: unsafe { OwnedFd::from_raw_fd(fd) },
+            seq: Cell::new(0),
+        };
+        let mut family = None;
+        stations.transact(
+            CTRL_CMD_GETFAMILY,
+            0,
+            &[(CTRL_ATTR_FAMILY_NAME, b"nl80211\0")],
+            |attrs| {
+                family = attributes(attrs)
+                    .into_iter()
+                    .find(|(kind, _)| *kind == CTRL_ATTR_FAMILY_ID)
+                    .and_then(|(_, payload)| attribute_value(payload));
+            },
+        )?;
+        stations.family = family
+            .and_then(|family| u16::try_from(family).ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No nl80211 family"))?;
+        Ok(stations)
+    }
+
+    /// Send the request `cmd` with `attrs`, handing the attributes of
+    /// every message of the answer to `each`
+    fn transact(
+        &self,
+        cmd: u8,
+        flags: u16,
+        attrs: &[(u16, &[u8])],
+        each: impl FnMut(&[u8]),
+    ) -> io::Result<()> {
+        let seq = self.seq.get().wrapping_add(1);
+        self.seq.set(seq);
+        let request = genl_request(self.family, flags, seq, cmd, attrs);
+        transact(&self.socket, &request, seq, GENL_HDRLEN, each)
+    }
+
+    /// Whether our interface runs as an access point
+    fn is_ap(&self) -> io::Result<bool> {
+        let mut iftype = None;
+        self.transact(
+            NL80211_CMD_GET_INTERFACE,
+            0,
+            &[(NL80211_ATTR_IFINDEX, &self.ifindex.to_ne_bytes())],
+            |attrs| {
+                iftype = attributes(attrs)
+                    .into_iter()
+                    .find(|(kind, _)| *kind == NL80211_ATTR_IFTYPE)
+                    .and_then(|(_, payload)| attribute_value(payload));
+            },
+        )?;
+        Ok(iftype == Some(NL80211_IFTYPE_AP.into()))
+    }
+
+    /// The stations associated right now, sorted by MAC. They come
+    /// and go, what isn't in the list just isn't there.
+    pub fn dump(&self) -> io::Result<Vec<Station>> {
+        let mut stations = Vec::new();
+        self.transact(
+            NL80211_CMD_GET_STATION,
+            NLM_F_DUMP,
+            &[(NL80211_ATTR_IFINDEX, &self.ifindex.to_ne_bytes())],
+            |attrs| match parse_station(attrs) {
+                Some(station) => stations.push(station),
+                None => debug!("Station of {} without byte counters", self.interface),
+            },
+        )?;
+        stations.sort_by_key(|station| station.mac);
+        Ok(stations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::netlink::{attribute, parse_dump, u16_at, NLMSG_DONE, NLMSG_HDRLEN, NLM_F_MULTI};
+
+    #[test]
+    fn test_parse_proc_net_wireless() {
+        let content = "\
+Inter-| sta-|   Quality        |   Discarded packets               | Missed | WE
+ face | tus | link level noise |  nwid  crypt   frag  retry   misc | beacon | 22
+wlan0: 0000   70.  -40.  -256        0      0      0      0      0        0
+ wlp2s0: 0000   45.  -65.  -256        0      0      0      3      0        0
+";
+        assert_eq!(parse_proc_net_wireless(content, "wlan0"), Some(-40));
+        assert_eq!(parse_proc_net_wireless(content, "wlp2s0"), Some(-65));
+        assert_eq!(parse_proc_net_wireless(content, "wlan1"), None);
+    }
+
+    #[test]
+    fn test_is_wireless() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("wlan0/wireless")).unwrap();
+        std::fs::create_dir_all(dir.path().join("eth0")).unwrap();
+        assert!(is_wireless(dir.path(), "wlan0"));
+        assert!(!is_wireless(dir.path(), "eth0"));
+    }
+
+    /// A NL80211_CMD_NEW_STATION message of a dump, numbered `seq`
+    fn station_message(seq: u32, mac: [u8; 6], info: &[u8]) -> Vec<u8> {
+        let mut attrs = attribute(NL80211_ATTR_MAC, &mac);
+        attrs.extend(attribute(NL80211_ATTR_STA_INFO | 0x8000, info));
+        let mut msg = genl_request(28, NLM_F_MULTI, seq, 19, &[]);
+        msg.extend(attrs);
+        let len = msg.len() as u32;
+        msg[..4].copy_from_slice(&len.to_ne_bytes());
+        msg
+    }
+
+    #[test]
+    fn test_parse_stations() {
+        let request = genl_request(
+            28,
+            NLM_F_DUMP,
+            5,
+            NL80211_CMD_GET_STATION,
+            &[(3, &[7, 0, 0, 0])],
+        );
+        assert_eq!(request.len(), 28);
+        assert_eq!(u16_at(&request, 4), Some(28));
+        assert_eq!(u16_at(&request, 6), Some(NLM_F_REQUEST | NLM_F_DUMP));
+        assert_eq!(request[16], NL80211_CMD_GET_STATION);
+
+        let mut info64 = attribute(NL80211_STA_INFO_RX_BYTES, &1000u32.to_ne_bytes());
+        info64.extend(attribute(
+            NL80211_STA_INFO_RX_BYTES64,
+            &5_000_000_000u64.to_ne_bytes(),
+        ));
+        info64.extend(attribute(
+            NL80211_STA_INFO_TX_BYTES64,
+            &600u64.to_ne_bytes(),
+        ));
+        info64.extend(attribute(NL80211_STA_INFO_TX_BYTES, &600u32.to_ne_bytes()));
+        // An older kernel, 32bit only
+        let mut info32 = attribute(NL80211_STA_INFO_RX_BYTES, &70u32.to_ne_bytes());
+        info32.extend(attribute(NL80211_STA_INFO_TX_BYTES, &80u32.to_ne_bytes()));
+        let first = [0x02, 0, 0, 0, 0, 0x01];
+        let second = [0x02, 0, 0, 0, 0xab, 0x02];
+
+        let mut buf = station_message(9, first, &info64);
+        // Not ours
+        buf.extend(station_message(8, second, &info64));
+        buf.extend(station_message(9, second, &info32));
+        let mut stations = Vec::new();
+        let mut each = |attrs: &[u8]| stations.push(parse_station(attrs).unwrap());
+        // The dump goes on
+        assert!(parse_dump(&buf, 9, GENL_HDRLEN, &mut each).is_none());
+        let mut done = Vec::new();
+        done.extend_from_slice(&(NLMSG_HDRLEN as u32 + 4).to_ne_bytes());
+        done.extend_from_slice(&NLMSG_DONE.to_ne_bytes());
+        done.extend_from_slice(&NLM_F_MULTI.to_ne_bytes());
+        done.extend_from_slice(&9u32.to_ne_bytes());
+        done.extend_from_slice(&[0; 8]);
+        assert!(parse_dump(&done, 9, GENL_HDRLEN, &mut each)
+            .unwrap()
+            .is_ok());
+        assert_eq!(
+            stations,
+            vec![
+                Station {
+                    mac: first,
+                    rx_bytes: 5_000_000_000,
+                    tx_bytes: 600
+                },
+                Station {
+                    mac: second,
+                    rx_bytes: 70,
+                    tx_bytes: 80
+                }
+            ]
+        );
+        assert_eq!(stations[1].address(), "02:00:00:00:ab:02");
+        assert_eq!(stations[1].field(), "02000000ab02");
+
+        // Without counters, no station to graph
+        assert_eq!(parse_station(&attribute(NL80211_ATTR_MAC, &first)), None);
+        // Cut short
+        let msg = station_message(9, first, &info64);
+        assert!(
+            parse_dump(&msg[..msg.len() - 4], 9, GENL_HDRLEN, &mut |_| {})
+                .unwrap()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,429 @@
+//! Deal with counters wrapping around
+//!
+//! Some drivers (and older kernels) only have 32bit counters, which
+//! wrap at 4GiB. For munin that looks like the counter going
+//! backwards, which DERIVE throws away, losing the data of that
+//! second. Instead we notice the wrap and keep counting upwards.
+// SPDX-License-Identifier:  GPL-3.0-only
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::{options, source::Counters};
+
+/// One wrap of a 32bit counter
+const WRAP_32: u64 = 1 << 32;
+
+/// How far a 32bit counter has to go backwards for us to consider
+/// it a wrap, instead of the driver resetting it.
+const WRAP_THRESHOLD: u64 = 1 << 31;
+
+/// How wide the counters of the interface are
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CounterWidth {
+    /// Treat counters as 32bit as long as their values fit
+    #[default]
+    Auto,
+    /// Counters are 32bit and wrap at 2^32
+    Bits32,
+    /// Counters are 64bit and never wrap (in practice)
+    Bits64,
+}
+
+impl CounterWidth {
+    /// Read the width from the environment variable
+    /// IF1SEC_COUNTER_WIDTH, `auto` (the default), `32` or `64`.
+    pub fn from_env() -> Self {
+        options::parse_or_default("IF1SEC_COUNTER_WIDTH", |value| match value {
+            "auto" => Some(CounterWidth::Auto),
+            "32" => Some(CounterWidth::Bits32),
+            "64" => Some(CounterWidth::Bits64),
+            _ => None,
+        })
+    }
+}
+
+/// How much a counter went up between the raw values `prev` and
+/// `curr`. None if it went backwards in a way that isn't a wrap of a
+/// counter of the given `width`.
+pub fn counter_delta(prev: u64, curr: u64, width: CounterWidth) -> Option<u64> {
+    if curr >= prev {
+        return Some(curr - prev);
+    }
+    let wraps = match width {
+        CounterWidth::Bits32 => true,
+        CounterWidth::Bits64 => false,
+        CounterWidth::Auto => prev < WRAP_32,
+    };
+    if wraps && prev < WRAP_32 && prev - curr > WRAP_THRESHOLD {
+        Some(curr + WRAP_32 - prev)
+    } else {
+        None
+    }
+}
+
+/// Whether going from `prev` to `curr` is a driver glitch: a step
+/// backwards that is no wrap, and too small for a reset, which starts
+/// over from (near) zero. Some drivers briefly report a lower value
+/// than before, counting on from it would count the difference twice.
+pub fn is_glitch(prev: u64, curr: u64, width: CounterWidth) -> bool {
+    curr < prev && counter_delta(prev, curr, width).is_none() && curr >= prev / 2
+}
+
+/// Turns the raw values of one counter into an ever increasing one
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WrapCounter {
+    /// The raw value we saw last
+    last_raw: Option<u64>,
+
+    /// The value we reported last
+    value: u64,
+}
+
+impl WrapCounter {
+    /// Feed in the next raw value, returns the value to report
+    pub fn update(&mut self, raw: u64, width: CounterWidth) -> u64 {
+        self.value = match self.last_raw {
+            None => raw,
+            Some(prev) => match counter_delta(prev, raw, width) {
+                Some(delta) => {
+                    if raw < prev {
+                        debug!("Counter wrapped from {} to {}", prev, raw);
+                    }
+                    self.value + delta
+                }
+                // Went backwards, not a wrap. Nothing we can count,
+                // carry on from the new raw value.
+                None => self.value,
+            },
+        };
+        self.last_raw = Some(raw);
+        self.value
+    }
+
+    /// The counter starts over at `raw`, say on an interface that came
+    /// back. Carry on from the value reported last, counting only what
+    /// comes after `raw`, not the step to it. Before the first raw
+    /// value there is nothing to carry on from, `raw` is it.
+    pub fn restart(&mut self, raw: u64) {
+        if self.last_raw.is_some() {
+            self.last_raw = Some(raw);
+        }
+    }
+}
+
+/// The wrap state for the counters likely to wrap, bytes and
+/// packets. The others don't grow fast enough to matter.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Unwrapper {
+    rx_bytes: WrapCounter,
+    tx_bytes: WrapCounter,
+    rx_packets: WrapCounter,
+    tx_packets: WrapCounter,
+}
+
+impl Unwrapper {
+    /// Replace the raw values in `counters` with unwrapped ones
+    pub fn apply(&mut self, counters: &mut Counters, width: CounterWidth) {
+        counters.rx_bytes = self.rx_bytes.update(counters.rx_bytes, width);
+        counters.tx_bytes = self.tx_bytes.update(counters.tx_bytes, width);
+        counters.rx_packets = self.rx_packets.update(counters.rx_packets, width);
+        counters.tx_packets = self.tx_packets.update(counters.tx_packets, width);
+    }
+
+    /// The counters start over at the raw values in `counters`, see
+    /// [WrapCounter::restart]
+    pub fn restart(&mut self, counters: &Counters) {
+        self.rx_bytes.restart(counters.rx_bytes);
+        self.tx_bytes.restart(counters.tx_bytes);
+        self.rx_packets.restart(counters.rx_packets);
+        self.tx_packets.restart(counters.tx_packets);
+    }
+
+    /// The state of the received and transmitted bytes
+    pub fn bytes(&self) -> (WrapCounter, WrapCounter) {
+        (self.rx_bytes, self.tx_bytes)
+    }
+
+    /// Carry on with the received and transmitted bytes from an
+    /// earlier [Unwrapper::bytes]
+    pub fn set_bytes(&mut self, rx: WrapCounter, tx: WrapCounter) {
+        self.rx_bytes = rx;
+        self.tx_bytes = tx;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_delta() {
+        assert_eq!(counter_delta(10, 15, CounterWidth::Auto), Some(5));
+        // A wrap
+        assert_eq!(
+            counter_delta(WRAP_32 - 10, 5, CounterWidth::Bits32),
+            Some(15)
+        );
+        assert_eq!(counter_delta(WRAP_32 - 10, 5, CounterWidth::Auto), Some(15));
+        assert_eq!(counter_delta(WRAP_32 - 10, 5, CounterWidth::Bits64), None);
+        // Small step backwards is no wrap
+        assert_eq!(counter_delta(1000, 900, CounterWidth::Bits32), None);
+        // Can't be a 32bit counter
+        assert_eq!(counter_delta(WRAP_32 + 10, 5, CounterWidth::Auto), None);
+    }
+
+    #[test]
+    fn test_is_glitch() {
+        assert!(is_glitch(1000, 990, CounterWidth::Auto));
+        assert!(is_glitch(1000, 500, CounterWidth::Bits64));
+        // Going up, a wrap or a reset are no glitches
+        assert!(!is_glitch(1000, 1000, CounterWidth::Auto));
+        assert!(!is_glitch(1000, 1010, CounterWidth::Auto));
+        assert!(!is_glitch(WRAP_32 - 10, 5, CounterWidth::Auto));
+        assert!(!is_glitch(1000, 3, CounterWidth::Auto));
+    }
+
+    #[test]
+    fn test_wrap_sequence() {
+        let mut counter = WrapCounter::default();
+        let step = 1_500_000_000;
+        let mut expected = 3_000_000_000;
+        assert_eq!(counter.update(expected, CounterWidth::Auto), expected);
+        for _ in 0..10 {
+            expected += step;
+            let raw = expected % WRAP_32;
+            assert_eq!(counter.update(raw, CounterWidth::Auto), expected);
+        }
+        assert!(expected > 4 * WRAP_32);
+    }
+
+    #[test]
+    fn test_restart() {
+        let mut counter = WrapCounter::default();
+        // Nothing to carry on from yet
+        counter.restart(7);
+        assert_eq!(
+            counter.update(3_000_000_000, CounterWidth::Auto),
+            3_000_000_000
+        );
+        // No wrap, a new counter
+        counter.restart(100);
+        assert_eq!(counter.update(100, CounterWidth::Auto), 3_000_000_000);
+        assert_eq!(counter.update(150, CounterWidth::Auto), 3_000_000_050);
+    }
+
+    /// xorshift64*, enough randomness for test sequences, and the same
+    /// every run for a seed
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        /// A step a counter may do in one sample without looking like
+        /// anything but a wrap, with the edges more likely than in a
+        /// plain uniform pick: nothing, a little, and just below the
+        /// wrap threshold.
+        fn step(&mut self) -> u64 {
+            match self.next() % 8 {
+                0 => 0,
+                1 => 1,
+                2 => WRAP_THRESHOLD - 1,
+                3 => WRAP_THRESHOLD - 1 - self.next() % 1000,
+                4 | 5 => self.next() % 100_000,
+                _ => self.next() % WRAP_THRESHOLD,
+            }
+        }
+    }
+
+    #[test]
+    fn test_counter_delta_edges() {
+        // Exactly at the threshold is a reset, not a wrap
+        assert_eq!(counter_delta(WRAP_THRESHOLD, 0, CounterWidth::Bits32), None);
+        assert_eq!(
+            counter_delta(WRAP_THRESHOLD + 1, 0, CounterWidth::Bits32),
+            Some(WRAP_THRESHOLD - 1)
+        );
+        assert_eq!(counter_delta(WRAP_32 - 1, 0, CounterWidth::Bits32), Some(1));
+        assert_eq!(counter_delta(WRAP_32, 0, CounterWidth::Bits32), None);
+        assert_eq!(
+            counter_delta(u64::MAX, u64::MAX, CounterWidth::Auto),
+            Some(0)
+        );
+    }
+
+    /// A property that did not hold
+    #[derive(Debug)]
+    struct Failure {
+        /// At which step
+        step: usize,
+        /// What went wrong
+        message: String,
+    }
+
+    /// Find fewer `steps` from `start` for which `property` still
+    /// fails, dropping what comes after the failing step, then one
+    /// step after the other. None if it holds.
+    fn shrink<T: Clone>(
+        start: u64,
+        mut steps: Vec<T>,
+        property: impl Fn(u64, &[T]) -> Result<(), Failure>,
+    ) -> Option<(Vec<T>, Failure)> {
+        let mut failure = property(start, &steps).err()?;
+        steps.truncate(failure.step + 1);
+        let mut i = 0;
+        while i < steps.len() {
+            let mut fewer = steps.clone();
+            fewer.remove(i);
+            match property(start, &fewer) {
+                Err(smaller) => {
+                    fewer.truncate(smaller.step + 1);
+                    steps = fewer;
+                    failure = smaller;
+                }
+                Ok(()) => i += 1,
+            }
+        }
+        Some((steps, failure))
+    }
+
+    /// Check `property` for `start` and `steps`, generated from `seed`,
+    /// failing with the smallest case [shrink] finds
+    fn check<T: Clone + std::fmt::Debug>(
+        seed: u64,
+        start: u64,
+        steps: Vec<T>,
+        property: impl Fn(u64, &[T]) -> Result<(), Failure>,
+    ) {
+        if let Some((steps, failure)) = shrink(start, steps, property) {
+            panic!(
+                "seed {}: {} at step {}, minimal case start {} steps {:?}",
+                seed, failure.message, failure.step, start, steps
+            );
+        }
+    }
+
+    #[test]
+    fn test_shrink() {
+        // Fails at the first step of 1
+        let property = |_, steps: &[u64]| match steps.iter().position(|&step| step == 1) {
+            Some(step) => Err(Failure {
+                step,
+                message: String::from("one"),
+            }),
+            None => Ok(()),
+        };
+        let (steps, failure) = shrink(0, vec![5, 7, 1, 3, 1], property).unwrap();
+        assert_eq!(steps, [1]);
+        assert_eq!(failure.step, 0);
+        assert!(shrink(0, vec![5, 7, 3], property).is_none());
+    }
+
+    /// Feed sequences of 32bit counters, wrapping every now and then,
+    /// with auto and 32bit width: every step gets counted exactly,
+    /// and the value only goes up.
+    #[test]
+    fn test_wrap_properties() {
+        for seed in 1..=200u64 {
+            let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+            let start = rng.next() % WRAP_32;
+            let steps: Vec<u64> = (0..500).map(|_| rng.step()).collect();
+            for width in [CounterWidth::Auto, CounterWidth::Bits32] {
+                check(seed, start, steps.clone(), |start, steps| {
+                    let fail = |step, message| Err(Failure { step, message });
+                    let mut counter = WrapCounter::default();
+                    let mut expected = start;
+                    let mut raw = start;
+                    if counter.update(raw, width) != start {
+                        return fail(0, format!("{:?} first value not {}", width, start));
+                    }
+                    for (i, &step) in steps.iter().enumerate() {
+                        let prev = raw;
+                        raw = (raw + step) % WRAP_32;
+                        let delta = counter_delta(prev, raw, width);
+                        if delta != Some(step) {
+                            return fail(
+                                i,
+                                format!("{:?} delta {} -> {} is {:?}", width, prev, raw, delta),
+                            );
+                        }
+                        let last = expected;
+                        expected += step;
+                        let value = counter.update(raw, width);
+                        if value != expected || value < last {
+                            return fail(
+                                i,
+                                format!("{:?} value {}, expected {}", width, value, expected),
+                            );
+                        }
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+
+    /// One step of a 64bit counter
+    #[derive(Debug, Clone, Copy)]
+    enum Step64 {
+        /// Up by that much
+        Up(u64),
+        /// Reset by the driver, to some random value (but still above
+        /// what a 32bit counter could be, for auto) below the current
+        Reset(u64),
+    }
+
+    /// 64bit counters never wrap: steps get counted exactly, going
+    /// backwards (a reset) counts nothing, and the value still never
+    /// goes down.
+    #[test]
+    fn test_64bit_properties() {
+        for seed in 1..=200u64 {
+            let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+            let start = WRAP_32 + rng.next() % WRAP_32;
+            let steps: Vec<Step64> = (0..500)
+                .map(|_| match rng.next() % 50 {
+                    0 => Step64::Reset(rng.next()),
+                    _ => Step64::Up(rng.step() * 16),
+                })
+                .collect();
+            for width in [CounterWidth::Auto, CounterWidth::Bits64] {
+                check(seed, start, steps.clone(), |start, steps| {
+                    let fail = |step, message| Err(Failure { step, message });
+                    let mut counter = WrapCounter::default();
+                    let mut raw = start;
+                    let mut expected = counter.update(raw, width);
+                    for (i, step) in steps.iter().enumerate() {
+                        let prev = raw;
+                        raw = match *step {
+                            Step64::Up(up) => raw + up,
+                            Step64::Reset(to) => WRAP_32 + to % (prev - WRAP_32 + 1),
+                        };
+                        let delta = counter_delta(prev, raw, width);
+                        let wanted = (raw >= prev).then(|| raw - prev);
+                        if delta != wanted {
+                            return fail(
+                                i,
+                                format!("{:?} delta {} -> {} is {:?}", width, prev, raw, delta),
+                            );
+                        }
+                        let value = counter.update(raw, width);
+                        if value != expected + delta.unwrap_or(0) || value < expected {
+                            return fail(
+                                i,
+                                format!("{:?} value {} after {}", width, value, expected),
+                            );
+                        }
+                        expected = value;
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+}
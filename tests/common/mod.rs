@@ -0,0 +1,70 @@
+//! What the tests running the plugin against a fake sysfs tree share
+// SPDX-License-Identifier:  GPL-3.0-only
+// Every test crate uses only some of it
+#![allow(dead_code)]
+
+use std::{
+    ffi::OsStr,
+    fs,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Build a fake interface `name` below `root`, with the given counter
+/// files in its statistics directory
+pub fn fake_interface(root: &Path, name: &str, counters: &[(&str, &str)]) {
+    let stats = root.join(name).join("statistics");
+    fs::create_dir_all(&stats).unwrap();
+    for (file, value) in counters {
+        fs::write(stats.join(file), value).unwrap();
+    }
+}
+
+/// The plugin as if1sec_<interface>, a symlink in `dir`
+pub fn plugin(dir: &Path, interface: &str) -> PathBuf {
+    let plugin = dir.join(format!("if1sec_{}", interface));
+    if !plugin.exists() {
+        symlink(env!("CARGO_BIN_EXE_munin-if1sec"), &plugin).unwrap();
+    }
+    plugin
+}
+
+/// Run `program` on the fake tree `root`, with none of our settings
+/// from the environment of the tests, and no MUNIN_PLUGSTATE
+pub fn command(program: impl AsRef<OsStr>, root: &Path) -> Command {
+    let mut command = Command::new(program);
+    for (var, _) in std::env::vars() {
+        if var.starts_with("IF1SEC_") {
+            command.env_remove(var);
+        }
+    }
+    command
+        .env_remove("MUNIN_PLUGSTATE")
+        .env("IF1SEC_SYSFS_ROOT", root);
+    command
+}
+
+/// Run the plugin as if1sec_<interface> with `arg` (none if empty) and
+/// the extra environment `envs`, return its stdout
+pub fn run_plugin_env(
+    dir: &Path,
+    root: &Path,
+    interface: &str,
+    arg: &str,
+    envs: &[(&str, &str)],
+) -> String {
+    let output = command(plugin(dir, interface), root)
+        .args((!arg.is_empty()).then_some(arg))
+        .envs(envs.iter().copied())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    // Logs go to stderr, stdout is munin's alone
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Run the plugin as if1sec_<interface> with `arg`, return its stdout
+pub fn run_plugin(dir: &Path, root: &Path, interface: &str, arg: &str) -> String {
+    run_plugin_env(dir, root, interface, arg, &[])
+}
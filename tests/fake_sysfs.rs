@@ -0,0 +1,97 @@
+//! One config and one oneshot fetch of the plugin, against a fake
+//! sysfs tree, comparing every line munin would get
+// SPDX-License-Identifier:  GPL-3.0-only
+
+mod common;
+
+use common::run_plugin_env;
+use std::{
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The epoch in the expected output, standing in for the one of the
+/// sample
+const EPOCH: u64 = 1_700_000_000;
+
+/// Build interface `name` below `root`, like /sys/class/net/eth0 of a
+/// card with a link `speed` (as the kernel writes it) fast
+fn fake_interface(root: &Path, name: &str, speed: &str) {
+    common::fake_interface(
+        root,
+        name,
+        &[
+            ("rx_bytes", "1000\n"),
+            ("tx_bytes", "2000\n"),
+            ("rx_packets", "10\n"),
+            ("tx_packets", "20\n"),
+        ],
+    );
+    fs::write(root.join(name).join("speed"), speed).unwrap();
+    fs::write(root.join(name).join("operstate"), "up\n").unwrap();
+}
+
+/// Run the plugin as if1sec_<name> in `dir` with `arg`, on the fake
+/// tree in `dir`, return its stdout
+fn run_plugin(dir: &Path, name: &str, arg: &str) -> String {
+    let plugstate = dir.to_str().unwrap();
+    run_plugin_env(
+        dir,
+        &dir.join("net"),
+        name,
+        arg,
+        &[("MUNIN_PLUGSTATE", plugstate)],
+    )
+}
+
+/// What config and then a oneshot fetch of `name` in `dir` write, the
+/// epoch of the sample replaced by [EPOCH]
+fn config_fetch(dir: &Path, name: &str) -> String {
+    let config = run_plugin(dir, name, "config");
+    let start = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let fetch = run_plugin(dir, name, "--oneshot");
+    let end = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let fetch: String = fetch
+        .lines()
+        .map(|line| match line.split_once(".value ") {
+            Some((field, sample)) => {
+                let (epoch, value) = sample.split_once(':').unwrap();
+                let epoch: u64 = epoch.parse().unwrap();
+                assert!((start..=end).contains(&epoch), "{}", line);
+                format!("{}.value {}:{}\n", field, EPOCH, value)
+            }
+            None => format!("{}\n", line),
+        })
+        .collect();
+    config + &fetch
+}
+
+#[test]
+fn test_if1sec9() {
+    let dir = tempfile::tempdir().unwrap();
+    fake_interface(&dir.path().join("net"), "if1sec9", "1000\n");
+    assert_eq!(
+        config_fetch(dir.path(), "if1sec9"),
+        include_str!("data/fake_if1sec9.txt")
+    );
+}
+
+#[test]
+fn test_unknown_speed() {
+    let dir = tempfile::tempdir().unwrap();
+    // No interface of that name on the host, whose driver would get
+    // asked for the speed
+    fake_interface(&dir.path().join("net"), "if1sec9", "-1\n");
+    // Graphed like a gigabit link
+    assert_eq!(
+        config_fetch(dir.path(), "if1sec9"),
+        include_str!("data/fake_if1sec9.txt")
+    );
+}
@@ -0,0 +1,262 @@
+//! Run the plugin against a fake sysfs tree, using IF1SEC_SYSFS_ROOT
+// SPDX-License-Identifier:  GPL-3.0-only
+
+mod common;
+
+use common::{command, fake_interface, plugin, run_plugin, run_plugin_env};
+use std::{fs, path::Path};
+
+#[test]
+fn test_sysfs_root() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("net");
+    let counters = [
+        ("rx_bytes", "1000\n"),
+        ("tx_bytes", "2000\n"),
+        ("rx_packets", "10\n"),
+        ("tx_packets", "20\n"),
+    ];
+    fake_interface(&root, "lo", &counters);
+    fake_interface(&root, "fake0", &counters);
+    fs::write(root.join("fake0/speed"), "10000\n").unwrap();
+    fs::write(root.join("fake0/operstate"), "up\n").unwrap();
+    fake_interface(&root, "fake1", &counters);
+    fs::write(root.join("fake1/operstate"), "down\n").unwrap();
+    // No statistics at all
+    fs::create_dir_all(root.join("fake2")).unwrap();
+
+    let config = run_plugin(dir.path(), &root, "fake0", "config");
+    assert!(config.contains("multigraph if1sec_fake0\n"));
+    assert!(config.contains("fake0_rx.max 1250000000\n"));
+    // Only the traffic, unless asked for more
+    assert!(!config.contains("multigraph if1sec_packets_fake0\n"));
+    let envs = [("IF1SEC_FIELDS", "all")];
+    let config = run_plugin_env(dir.path(), &root, "fake0", "config", &envs);
+    assert!(config.contains("multigraph if1sec_fake0\n"));
+    assert!(config.contains("multigraph if1sec_packets_fake0\n"));
+    // No error counters in our fake tree
+    assert!(!config.contains("multigraph if1sec_errors_fake0\n"));
+    // Not the graph of the whole host, every if1sec_* would have it
+    assert!(!config.contains("if1sec_softnet"));
+
+    assert_eq!(run_plugin(dir.path(), &root, "", "suggest"), "fake0\n");
+    assert_eq!(run_plugin(dir.path(), &root, "", "autoconf"), "yes\n");
+
+    let list = run_plugin(dir.path(), &root, "", "list-interfaces");
+    assert!(list.starts_with("interface "), "{}", list);
+    assert!(list.contains("\nfake0              10000 Mbps up               yes       yes\n"));
+    assert!(list.contains("\nfake1 "));
+    assert!(list.contains("\nfake2               1000 Mbps -                no        no\n"));
+}
+
+#[test]
+fn test_multiple_interfaces() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("net");
+    let counters = [
+        ("rx_bytes", "1000\n"),
+        ("tx_bytes", "2000\n"),
+        ("rx_packets", "10\n"),
+        ("tx_packets", "20\n"),
+    ];
+    fake_interface(&root, "lo", &counters);
+    fake_interface(&root, "fake0", &counters);
+    fake_interface(&root, "fake1", &counters);
+
+    let envs = [
+        ("IF1SEC_INTERFACES", "fake0,fake1"),
+        ("IF1SEC_FIELDS", "bytes,packets"),
+    ];
+    let config = run_plugin_env(dir.path(), &root, "multi", "config", &envs);
+    for graph in ["if1sec", "if1sec_packets"] {
+        for interface in ["fake0", "fake1"] {
+            assert!(config.contains(&format!("multigraph {}_{}\n", graph, interface)));
+        }
+    }
+    // In the order asked for
+    assert!(config.find("if1sec_fake0\n") < config.find("if1sec_fake1\n"));
+
+    let json = run_plugin_env(dir.path(), &root, "multi", "--json", &envs);
+    assert!(json.contains("{\"interface\":\"fake0\""));
+    assert!(json.contains("{\"interface\":\"fake1\""));
+
+    // One state file setting, a state file per interface
+    let state = dir.path().join("if1sec.state");
+    let envs = [
+        ("IF1SEC_INTERFACES", "fake0,fake1"),
+        ("IF1SEC_STATE_FILE", state.to_str().unwrap()),
+    ];
+    run_plugin_env(dir.path(), &root, "multi", "--oneshot", &envs);
+    assert!(!state.exists());
+    for interface in ["fake0", "fake1"] {
+        let state = fs::read_to_string(dir.path().join(format!("if1sec.state_{}", interface)));
+        assert!(state.unwrap().contains("\"rx_bytes\""));
+    }
+
+    // The whole host backlog drops once, not per interface
+    if Path::new("/proc/net/softnet_stat").exists() {
+        let envs = [
+            ("IF1SEC_INTERFACES", "fake0,fake1"),
+            ("IF1SEC_FIELDS", "softnet"),
+        ];
+        let config = run_plugin_env(dir.path(), &root, "multi", "config", &envs);
+        assert_eq!(config.matches("multigraph if1sec_softnet\n").count(), 1);
+        assert!(!config.contains("if1sec_softnet_"));
+    }
+
+    // A missing interface and files that look like ones get skipped,
+    // the others still work
+    fs::write(root.join("fake_masters"), "bond0\n").unwrap();
+    let envs = [("IF1SEC_INTERFACES", "gone0,fake*")];
+    let config = run_plugin_env(dir.path(), &root, "multi", "config", &envs);
+    assert!(config.contains("multigraph if1sec_fake0\n"));
+    assert!(config.contains("multigraph if1sec_fake1\n"));
+    assert!(!config.contains("gone0"));
+    assert!(!config.contains("fake_masters"));
+
+    // if1sec_all picks up every usable interface, but not lo
+    let config = run_plugin(dir.path(), &root, "all", "config");
+    assert!(config.contains("multigraph if1sec_fake0\n"));
+    assert!(config.contains("multigraph if1sec_fake1\n"));
+    assert!(!config.contains("multigraph if1sec_lo\n"));
+}
+
+#[test]
+fn test_version_help() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("net");
+    let version = run_plugin(dir.path(), &root, "eth0", "--version");
+    assert_eq!(version, format!("if1sec {}\n", env!("CARGO_PKG_VERSION")));
+    let help = run_plugin(dir.path(), &root, "eth0", "--help");
+    assert!(help.starts_with("Usage: if1sec_<interface>"));
+    assert!(help.contains("IF1SEC_SYSFS_ROOT"));
+    let sample = run_plugin(dir.path(), &root, "eth0", "--dump-config");
+    assert!(sample.contains("\n[if1sec_*]\n"), "{}", sample);
+    assert!(sample.contains("\n#env.IF1SEC_SYSFS_ROOT /sys/class/net\n"));
+}
+
+#[test]
+fn test_oneshot() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("net");
+    fake_interface(
+        &root,
+        "fake0",
+        &[
+            ("rx_bytes", "1000\n"),
+            ("tx_bytes", "2000\n"),
+            ("rx_packets", "10\n"),
+            ("tx_packets", "20\n"),
+        ],
+    );
+
+    for output in [
+        run_plugin(dir.path(), &root, "fake0", "--oneshot"),
+        run_plugin_env(dir.path(), &root, "fake0", "", &[("IF1SEC_ONESHOT", "1")]),
+    ] {
+        assert!(output.contains("multigraph if1sec_fake0\n"), "{}", output);
+        // With a timestamp, as the daemon would write it
+        let rx = output
+            .lines()
+            .find_map(|line| line.strip_prefix("fake0_rx.value "))
+            .unwrap();
+        let (epoch, value) = rx.split_once(':').unwrap();
+        assert!(epoch.parse::<u64>().unwrap() > 1_600_000_000);
+        assert_eq!(value, "1000");
+    }
+}
+
+#[test]
+fn test_interface_argument() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("net");
+    let counters = [
+        ("rx_bytes", "1000\n"),
+        ("tx_bytes", "2000\n"),
+        ("rx_packets", "10\n"),
+        ("tx_packets", "20\n"),
+    ];
+    fake_interface(&root, "fake0", &counters);
+    fake_interface(&root, "fake1", &counters);
+
+    // The environment wins over the name
+    let envs = [("IF1SEC_INTERFACE", "fake1")];
+    let config = run_plugin_env(dir.path(), &root, "fake0", "config", &envs);
+    assert!(config.contains("multigraph if1sec_fake1\n"), "{}", config);
+    assert!(!config.contains("fake0"));
+
+    // No symlink needed with --interface
+    let output = command(env!("CARGO_BIN_EXE_munin-if1sec"), &root)
+        .args(["--interface", "fake0", "config"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let config = String::from_utf8(output.stdout).unwrap();
+    assert!(config.contains("multigraph if1sec_fake0\n"), "{}", config);
+
+    // Not there
+    let output = command(env!("CARGO_BIN_EXE_munin-if1sec"), &root)
+        .args(["--interface", "fake2", "config"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "{:?}", output);
+}
+
+/// Whether `line` is something munin understands
+fn munin_line(line: &str) -> bool {
+    line.starts_with("multigraph ")
+        || line.starts_with("graph_")
+        || line.starts_with("update_rate ")
+        || line.split_once(' ').is_some_and(|(key, _)| {
+            key.split_once('.')
+                .is_some_and(|(f, a)| !f.is_empty() && !a.is_empty())
+        })
+}
+
+#[test]
+fn test_stdout_munin_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("net");
+    fake_interface(
+        &root,
+        "fake0",
+        &[
+            ("rx_bytes", "1000\n"),
+            ("tx_bytes", "2000\n"),
+            ("rx_packets", "10\n"),
+            ("tx_packets", "20\n"),
+        ],
+    );
+    fs::write(root.join("fake0").join("speed"), "-1\n").unwrap();
+    let plugin = plugin(dir.path(), "fake0");
+    let run = |arg: &str, envs: &[(&str, &str)]| {
+        let output = command(&plugin, &root)
+            .args((!arg.is_empty()).then_some(arg))
+            .env("IF1SEC_ONESHOT", "1")
+            .env("RUST_LOG", "debug")
+            .env("MUNIN_PLUGSTATE", dir.path())
+            .envs(envs.iter().copied())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        (
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        )
+    };
+
+    // Even logging everything, the logs stay off stdout
+    for arg in ["config", ""] {
+        let (stdout, stderr) = run(arg, &[]);
+        assert!(!stdout.is_empty());
+        for line in stdout.lines() {
+            assert!(munin_line(line), "{:?} in {}", line, stdout);
+        }
+        assert!(stderr.contains("if1sec started"), "{}", stderr);
+    }
+
+    // And quiet only has errors to tell, of which there are none
+    let (stdout, stderr) = run("config", &[("IF1SEC_QUIET", "1")]);
+    assert!(stdout.contains("multigraph if1sec_fake0\n"));
+    assert_eq!(stderr, "");
+}